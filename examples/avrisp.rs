@@ -0,0 +1,80 @@
+//! 用法: avrisp info | read <file> | write <file.hex> | eeprom <file> | fuse <low> <high> [ext]
+//!
+//! RESET 接 IO0, 片选不用
+use std::fs;
+
+use ch347_rs::{
+    ch347,
+    gpio::Output,
+    ihex::Image,
+    spi::{
+        Config, SpiDevice,
+        avr::{Fuse, Programmer},
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "usage: {} info | read <file> | write <file.hex> | eeprom <file> | fuse <low> <high> [ext]",
+            args[0]
+        );
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    // 最慢的 60M >> 7 = 468.75kHz, 目标时钟要 2MHz 以上
+    let config = Config {
+        speed: 7,
+        ..Default::default()
+    };
+    let spi = SpiDevice::new(p.SPI0, config).unwrap();
+    let reset = Output::new(p.IO0);
+    let mut avr = Programmer::new(spi, reset).unwrap();
+    println!("{}", avr.part().name);
+
+    let progress = |done, total| print!("\r{done}/{total}");
+    match args[1].as_str() {
+        "info" => {
+            println!(
+                "low {:02x} high {:02x} lock {:02x} cal {:02x}",
+                avr.read_fuse(Fuse::Low).unwrap(),
+                avr.read_fuse(Fuse::High).unwrap(),
+                avr.read_fuse(Fuse::Lock).unwrap(),
+                avr.read_calibration().unwrap()
+            );
+            if avr.part().extended_fuse {
+                println!("ext {:02x}", avr.read_fuse(Fuse::Extended).unwrap());
+            }
+        }
+        "read" => {
+            let mut flash = vec![0; avr.part().flash_size as usize];
+            avr.read_flash(0, &mut flash).unwrap();
+            fs::write(&args[2], flash).unwrap();
+        }
+        "write" => {
+            let image = Image::load(&args[2], 0).unwrap();
+            avr.write_image(&image, progress).unwrap();
+            println!();
+            avr.verify_image(&image, progress).unwrap();
+            println!();
+        }
+        "eeprom" => {
+            let mut eeprom = vec![0; avr.part().eeprom_size as usize];
+            avr.read_eeprom(0, &mut eeprom).unwrap();
+            fs::write(&args[2], eeprom).unwrap();
+        }
+        "fuse" => {
+            let parse = |s: &str| u8::from_str_radix(s.trim_start_matches("0x"), 16).unwrap();
+            avr.write_fuse(Fuse::Low, parse(&args[2])).unwrap();
+            avr.write_fuse(Fuse::High, parse(&args[3])).unwrap();
+            if let Some(ext) = args.get(4) {
+                avr.write_fuse(Fuse::Extended, parse(ext)).unwrap();
+            }
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+    avr.release().unwrap();
+}
@@ -0,0 +1,33 @@
+use ch347_rs::{
+    bitbang::{i2c, spi},
+    ch347,
+};
+use embedded_hal::{i2c::I2c, spi::SpiBus};
+
+fn main() {
+    env_logger::init();
+    let p = ch347::init().unwrap();
+
+    // IO0 = SCL, IO1 = SDA, 需要外部上拉
+    let mut i2c = i2c::I2cbus::new(p.IO0, p.IO1);
+    let mut who_am_i = [0; 1];
+    match i2c.write_read(0x68, &[0x75], &mut who_am_i) {
+        Ok(()) => println!("mpu6050 WHO_AM_I: {:#04x}", who_am_i[0]),
+        Err(e) => println!("mpu6050 not found: {:?}", e),
+    }
+
+    // IO2 = SCK, IO3 = MOSI, IO4 = MISO, 9 位字
+    let mut spi = spi::SpiBus::new(
+        p.IO2,
+        p.IO3,
+        p.IO4,
+        spi::Config {
+            bits: 9,
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    let mut words = [0x1AA, 0x055];
+    SpiBus::<u16>::transfer_in_place(&mut spi, &mut words).unwrap();
+    println!("spi read back: {:03x?}", words);
+}
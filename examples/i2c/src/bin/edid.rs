@@ -0,0 +1,16 @@
+use ch347_rs::{
+    ch347,
+    i2c::{I2cbus, ddc::Ddc},
+};
+
+/// 读显示器的 EDID 并打印
+fn main() {
+    env_logger::init();
+
+    let p = ch347::init().unwrap();
+    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut ddc = Ddc::new(i2c);
+
+    let edid = ddc.read_edid().unwrap();
+    print!("{}", edid);
+}
@@ -0,0 +1,39 @@
+use std::fs;
+
+use ch347_rs::{
+    ch347,
+    i2c::{
+        I2cbus,
+        eeprom::{Chip, Eeprom},
+    },
+};
+
+/// 用法: eeprom dump <file> | eeprom program <file>
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        println!("usage: {} dump|program <file>", args[0]);
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut eeprom = Eeprom::new(i2c, Chip::At24C256);
+    let progress = |done, total| print!("\r{done}/{total}");
+
+    match args[1].as_str() {
+        "dump" => {
+            let image = eeprom.read_image(progress).unwrap();
+            fs::write(&args[2], image).unwrap();
+        }
+        "program" => {
+            let image = fs::read(&args[2]).unwrap();
+            eeprom.write_image(&image, progress).unwrap();
+            println!();
+            eeprom.verify_image(&image, progress).unwrap();
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+    println!();
+}
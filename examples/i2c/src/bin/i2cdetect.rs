@@ -0,0 +1,26 @@
+use ch347_rs::{
+    ch347,
+    i2c::{I2cbus, Probe, ScanConfig},
+};
+
+fn main() {
+    env_logger::init();
+    let p = ch347::init().unwrap();
+    let mut i2c = I2cbus::new(p.I2C, Default::default());
+
+    // 参数: -q 只用写探测, -r 只用读探测, -a 包括保留地址
+    let mut config = ScanConfig {
+        identify: true,
+        ..Default::default()
+    };
+    for arg in std::env::args().skip(1) {
+        match arg.as_str() {
+            "-q" => config.probe = Probe::Write,
+            "-r" => config.probe = Probe::Read,
+            "-a" => config.include_reserved = true,
+            _ => {}
+        }
+    }
+
+    print!("{}", i2c.detect(&config).unwrap());
+}
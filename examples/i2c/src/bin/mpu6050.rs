@@ -1,6 +1,9 @@
 use std::{thread::sleep, time::Duration};
 
-use ch347_rs::{ch347, i2c::I2cbus};
+use ch347_rs::{
+    ch347,
+    i2c::{I2cbus, Part},
+};
 use mpu6050::*;
 
 #[derive(Debug)]
@@ -24,9 +27,17 @@ where
 fn main() {
     env_logger::init();
     let p = ch347::init().unwrap();
-    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut i2c = I2cbus::new(p.I2C, Default::default());
     let mut delay = Delay;
 
+    if i2c.identify(0x68) != Some(Part::Mpu6050) {
+        println!(
+            "no mpu6050 at 0x68, bus has: {:02x?}",
+            i2c.scan(&Default::default()).unwrap()
+        );
+        return;
+    }
+
     let mut mpu = Mpu6050::new(i2c);
     mpu.init(&mut delay).unwrap();
 
@@ -0,0 +1,55 @@
+use ch347_rs::{
+    ch347,
+    i2c::I2cbus,
+    register_map,
+    regmap::{Device, I2cInterface, RegisterMap},
+};
+
+register_map! {
+    mod mpu6050 {
+        PWR_MGMT_1 @ 0x6B: u8, RW, reset = 0x40 {
+            DEVICE_RESET: 7,
+            SLEEP: 6,
+            CYCLE: 5,
+            TEMP_DIS: 3,
+            CLKSEL: 0..3,
+        }
+        WHO_AM_I @ 0x75: u8, RO, reset = 0x68 {
+            WHO_AM_I: 1..7,
+        }
+    }
+}
+
+/// 用法: regmap [map.toml|map.yaml], 不给文件时用编译期的 mpu6050 表
+fn main() {
+    env_logger::init();
+    let map = match std::env::args().nth(1) {
+        Some(path) => RegisterMap::load(path).unwrap(),
+        None => mpu6050::map(),
+    };
+
+    let p = ch347::init().unwrap();
+    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut device = Device::new(I2cInterface::new(i2c, 0x68), map).unwrap();
+
+    let before = device.dump().unwrap();
+    print!("{}", before);
+
+    // 唤醒, 时钟切到 X 轴陀螺仪
+    device
+        .modify(|r: mpu6050::PWR_MGMT_1| {
+            r.with(mpu6050::PWR_MGMT_1::SLEEP, 0)
+                .with(mpu6050::PWR_MGMT_1::CLKSEL, 1)
+        })
+        .unwrap();
+
+    let after = device.dump().unwrap();
+    println!("changes:");
+    for change in before.diff(&after) {
+        print!("{}", change);
+    }
+    println!("differs from reset:");
+    for change in after.diff_reset() {
+        print!("{}", change);
+    }
+}
@@ -5,7 +5,11 @@ use ssd1306::{I2CDisplayInterface, Ssd1306, mode::DisplayConfig, size::DisplaySi
 fn main() {
     env_logger::init();
     let p = ch347::init().unwrap();
-    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut i2c = I2cbus::new(p.I2C, Default::default());
+    if !i2c.scan(&Default::default()).unwrap().contains(&0x3C) {
+        println!("no ssd1306 at 0x3c");
+        return;
+    }
 
     let interface = I2CDisplayInterface::new(i2c);
     let mut display = Ssd1306::new(
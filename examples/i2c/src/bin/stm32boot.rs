@@ -0,0 +1,52 @@
+use ch347_rs::{
+    ch347,
+    i2c::{
+        I2cbus,
+        stm32boot::{Bootloader, Erase},
+    },
+    ihex::Image,
+};
+
+/// 用法: stm32boot <firmware.hex|firmware.bin> [bootloader 地址, 默认 0x56]
+///
+/// bin 文件从 0x08000000 开始写
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        println!("usage: {} <firmware.hex|firmware.bin> [address]", args[0]);
+        return;
+    }
+    let address = args
+        .get(2)
+        .map(|a| u8::from_str_radix(a.trim_start_matches("0x"), 16).unwrap())
+        .unwrap_or(0x56);
+    let image = Image::load(&args[1], 0x0800_0000).unwrap();
+
+    let p = ch347::init().unwrap();
+    let i2c = I2cbus::new(p.I2C, Default::default());
+    let mut bl = Bootloader::new(i2c, address).unwrap();
+    println!(
+        "bootloader v{}.{}, pid {:#05x}",
+        bl.info().version >> 4,
+        bl.info().version & 0x0F,
+        bl.get_id().unwrap()
+    );
+
+    let progress = |done, total| print!("\r{done}/{total}");
+    println!("erasing");
+    bl.erase(&Erase::Global).unwrap();
+    println!("writing");
+    bl.write_image(&image, progress).unwrap();
+    println!("\nverifying");
+    bl.verify_image(&image, progress).unwrap();
+    println!();
+
+    // Go 要的是向量表地址, 不是 05 记录里的复位入口
+    let vectors = image
+        .segments
+        .first()
+        .map_or(0x0800_0000, |seg| seg.address);
+    bl.go(vectors).unwrap();
+    println!("jump to {:#010x}", vectors);
+}
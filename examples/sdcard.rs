@@ -0,0 +1,89 @@
+//! 用法: sdcard info | ls [dir] | cat <file> | dump <file> <blocks>
+use std::fs;
+use std::ops::ControlFlow;
+
+use ch347_rs::{
+    ch347,
+    spi::{ChipSelect, Config, SharedBus, sd::SdCard},
+};
+use embedded_sdmmc::{Mode, TimeSource, Timestamp, VolumeIdx, VolumeManager};
+
+struct Clock;
+
+impl TimeSource for Clock {
+    fn get_timestamp(&self) -> Timestamp {
+        Timestamp::from_calendar(2024, 1, 1, 0, 0, 0).unwrap()
+    }
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "usage: {} info | ls [dir] | cat <file> | dump <file> <blocks>",
+            args[0]
+        );
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    let bus = SharedBus::new(p.SPI0);
+    // 60M >> 7 = 468.75kHz, 最慢的一档, 初始化用
+    let slow = Config {
+        speed: 7,
+        ..Default::default()
+    };
+    let spi = bus.device(ChipSelect::Cs0, slow).unwrap();
+    let card = SdCard::new(spi, true).unwrap();
+    card.with_spi(|spi| spi.set_config(Config::default()));
+    println!("{:?}, {} bytes", card.card_type(), card.capacity());
+
+    match args[1].as_str() {
+        "info" => {
+            let cid = card.read_cid().unwrap();
+            println!(
+                "{:02x} {} {} rev {:?} sn {:08x} date {:?}",
+                cid.manufacturer(),
+                cid.oem(),
+                cid.product(),
+                cid.revision(),
+                cid.serial(),
+                cid.date()
+            );
+            println!("max speed {} Hz", card.csd().max_speed());
+        }
+        "dump" => {
+            let blocks: usize = args[3].parse().unwrap();
+            let mut image = vec![0; blocks * 512];
+            card.read_blocks(0, &mut image).unwrap();
+            fs::write(&args[2], image).unwrap();
+        }
+        "ls" | "cat" => {
+            let volumes: VolumeManager<_, _> = VolumeManager::new(card, Clock);
+            let volume = volumes.open_volume(VolumeIdx(0)).unwrap();
+            let root = volume.open_root_dir().unwrap();
+            if args[1] == "ls" {
+                let dir = match args.get(2) {
+                    Some(name) => root.open_dir(name.as_str()).unwrap(),
+                    None => root,
+                };
+                dir.iterate_dir(|entry| {
+                    println!("{:>10} {} {}", entry.size, entry.mtime, entry.name);
+                    ControlFlow::Continue(())
+                })
+                .unwrap();
+            } else {
+                let file = root
+                    .open_file_in_dir(args[2].as_str(), Mode::ReadOnly)
+                    .unwrap();
+                let mut buf = [0; 512];
+                while !file.is_eof() {
+                    let n = file.read(&mut buf).unwrap();
+                    print!("{}", String::from_utf8_lossy(&buf[..n]));
+                }
+            }
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+}
@@ -18,8 +18,10 @@ fn main() {
             speed: 0,
             mode: ch347_rs::spi::Mode::Mode0,
             bit_order: ch347_rs::spi::BitOrder::MSB,
+            ..Default::default()
         },
-    );
+    )
+    .unwrap();
     let dc = Output::new(p.IO1);
     let rst = Output::new(p.IO2);
     let mut buffer = [0; 512];
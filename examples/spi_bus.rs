@@ -0,0 +1,29 @@
+//! 用 embedded-hal-bus 在硬件 SPI 上挂两个设备, 片选用普通 IO
+use std::cell::RefCell;
+
+use ch347_rs::{ch347, gpio::Output, spi};
+use critical_section::Mutex;
+use embedded_hal::spi::SpiDevice;
+use embedded_hal_bus::spi::CriticalSectionDevice;
+
+fn main() {
+    env_logger::init();
+    let p = ch347::init().unwrap();
+
+    let bus = Mutex::new(RefCell::new(
+        spi::SpiBus::new(p.SPI0, Default::default()).unwrap(),
+    ));
+    let mut flash =
+        CriticalSectionDevice::new(&bus, Output::new(p.IO0), ch347_rs::Delay::new()).unwrap();
+    let mut sensor =
+        CriticalSectionDevice::new(&bus, Output::new(p.IO1), ch347_rs::Delay::new()).unwrap();
+
+    // JEDEC ID
+    let mut id = [0x9Fu8, 0, 0, 0];
+    flash.transfer_in_place(&mut id).unwrap();
+    println!("flash id: {:02x?}", &id[1..]);
+
+    let mut rev = [0u8; 2];
+    sensor.transfer(&mut rev, &[0x80 | 0x0F]).unwrap();
+    println!("sensor: {:02x?}", rev);
+}
@@ -0,0 +1,39 @@
+//! 用法: spi_eeprom dump <file> | spi_eeprom program <file>
+use std::fs;
+
+use ch347_rs::{
+    ch347,
+    spi::{
+        SpiDevice,
+        eeprom::{Chip, Eeprom},
+    },
+};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() != 3 {
+        println!("usage: {} dump|program <file>", args[0]);
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    let spi = SpiDevice::new(p.SPI0, Default::default()).unwrap();
+    let mut eeprom = Eeprom::new(spi, Chip::Eeprom25x256);
+    let progress = |done, total| print!("\r{done}/{total}");
+
+    match args[1].as_str() {
+        "dump" => {
+            let image = eeprom.read_image(progress).unwrap();
+            fs::write(&args[2], image).unwrap();
+        }
+        "program" => {
+            let image = fs::read(&args[2]).unwrap();
+            eeprom.write_image(&image, progress).unwrap();
+            println!();
+            eeprom.verify_image(&image, progress).unwrap();
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+    println!();
+}
@@ -0,0 +1,44 @@
+//! 用法: spi_flash id | dump <file> | program <file>
+use std::fs;
+
+use ch347_rs::{
+    ch347,
+    spi::{SpiDevice, nor::Flash},
+};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        println!("usage: {} id | dump <file> | program <file>", args[0]);
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    let spi = SpiDevice::new(p.SPI0, Default::default()).unwrap();
+    let mut flash = Flash::new(spi).unwrap();
+    println!("{:02x?}", flash.id());
+    println!("{:#x?}", flash.parameters());
+
+    let progress = |done, total| print!("\r{done}/{total}");
+    match args[1].as_str() {
+        "id" => {}
+        "dump" => {
+            let mut image = vec![0; flash.size() as usize];
+            for (i, chunk) in image.chunks_mut(65536).enumerate() {
+                flash.read((i * 65536) as u32, chunk).unwrap();
+                progress(i * 65536 + chunk.len(), flash.size() as usize);
+            }
+            println!();
+            fs::write(&args[2], image).unwrap();
+        }
+        "program" => {
+            let image = fs::read(&args[2]).unwrap();
+            let changed = flash.program(0, &image, progress).unwrap();
+            println!("\n{} sectors changed", changed);
+            flash.verify(0, &image, progress).unwrap();
+            println!();
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+}
@@ -0,0 +1,53 @@
+//! 用法: spi_nand scan | dump <file> <bytes> | program <file> | raw <page>
+use std::fs;
+
+use ch347_rs::{
+    ch347,
+    spi::{SpiDevice, nand::Nand},
+};
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        println!(
+            "usage: {} scan | dump <file> <bytes> | program <file> | raw <page>",
+            args[0]
+        );
+        return;
+    }
+
+    let p = ch347::init().unwrap();
+    let spi = SpiDevice::new(p.SPI0, Default::default()).unwrap();
+    let mut nand = Nand::new(spi).unwrap();
+    println!("{:#?}", nand.part());
+
+    let progress = |done, total| print!("\r{done}/{total}");
+    match args[1].as_str() {
+        "scan" => {
+            let bad = nand.scan_bad_blocks(progress).unwrap();
+            println!("\nbad blocks: {:?}", bad);
+        }
+        "dump" => {
+            let mut image = vec![0; args[3].parse().unwrap()];
+            nand.read_image(0, &mut image, progress).unwrap();
+            println!();
+            fs::write(&args[2], image).unwrap();
+        }
+        "program" => {
+            let image = fs::read(&args[2]).unwrap();
+            let used = nand.write_image(0, &image, progress).unwrap();
+            println!("\nblocks: {:?}", used);
+        }
+        "raw" => {
+            let page = args[2].parse().unwrap();
+            nand.dump_raw(page, 1, |page, data| {
+                for (i, line) in data.chunks(32).enumerate() {
+                    println!("{:05x}:{:04x}: {:02x?}", page, i * 32, line);
+                }
+            })
+            .unwrap();
+        }
+        _ => println!("unknown command {}", args[1]),
+    }
+}
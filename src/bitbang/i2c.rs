@@ -0,0 +1,472 @@
+use embassy_hal_internal::Peripheral;
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+
+use crate::gpio::{
+    Batch, DegradePin, Flex, Levels,
+    types::{PinAction, PinState},
+};
+
+/// 释放总线, 高电平由外部上拉给出
+const RELEASE: PinAction = PinAction::Input;
+const PULL_LOW: PinAction = PinAction::Output(PinState::Low);
+
+/// 软件 I2C, SCL/SDA 可以是任意两个 IO
+///
+/// CH347 的 IO 没有开漏输出, 所以用 "输入 = 释放, 输出低 = 拉低" 来模拟,
+/// 两根线都必须有外部上拉. 不支持时钟延展
+pub struct I2cbus<'d> {
+    scl: Flex<'d>,
+    sda: Flex<'d>,
+}
+
+impl<'d> I2cbus<'d> {
+    pub fn new(
+        scl: impl Peripheral<P = impl DegradePin> + 'd,
+        sda: impl Peripheral<P = impl DegradePin> + 'd,
+    ) -> Self {
+        let bus = Self {
+            scl: Flex::new(scl),
+            sda: Flex::new(sda),
+        };
+        let mut batch = Batch::new();
+        batch.push(&[(&bus.scl, RELEASE), (&bus.sda, RELEASE)]);
+        // 这里失败的话, 第一次传输会报告同样的错误
+        let _ = batch.execute();
+        bus
+    }
+
+    fn run(&mut self, address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+        transaction(&self.scl, &self.sda, address, operations, |batch| {
+            batch.execute().map_err(|_| ErrorKind::Other)
+        })
+    }
+}
+
+/// 每到一个 ACK 位就把之前的帧执行掉并检查, 收到 NACK 立刻发 STOP 结束,
+/// 后面的字节不会再出现在总线上. 一个字节连同 ACK 是 27 帧, 不到一个 USB 包,
+/// 所以按 ACK 分批几乎不增加 USB 往返
+fn transaction<F>(
+    scl: &Flex<'_>,
+    sda: &Flex<'_>,
+    address: u8,
+    operations: &mut [Operation<'_>],
+    execute: F,
+) -> Result<(), ErrorKind>
+where
+    F: FnMut(&mut Batch) -> Result<Vec<Levels>, ErrorKind>,
+{
+    if operations.is_empty() {
+        return Ok(());
+    }
+
+    let mut encoder = Encoder::new(scl, sda, execute);
+    let mut prev_is_read = None;
+    for i in 0..operations.len() {
+        let is_read = matches!(operations[i], Operation::Read(_));
+        // 方向改变时才需要 (重复) 起始位和地址
+        if prev_is_read != Some(is_read) {
+            encoder.start();
+            encoder.write_byte(
+                (address << 1) | u8::from(is_read),
+                NoAcknowledgeSource::Address,
+            )?;
+        }
+
+        match &operations[i] {
+            Operation::Write(buf) => {
+                for &byte in buf.iter() {
+                    encoder.write_byte(byte, NoAcknowledgeSource::Data)?;
+                }
+            }
+            Operation::Read(buf) => {
+                // 最后一次读的最后一个字节回 NACK
+                let next_is_read = matches!(operations.get(i + 1), Some(Operation::Read(_)));
+                for j in 0..buf.len() {
+                    encoder.read_byte(next_is_read || j + 1 < buf.len());
+                }
+            }
+        }
+        prev_is_read = Some(is_read);
+    }
+    encoder.stop();
+    encoder.flush()?;
+
+    let mut bits = std::mem::take(&mut encoder.bits).into_iter();
+    for op in operations.iter_mut() {
+        if let Operation::Read(buf) = op {
+            for byte in buf.iter_mut() {
+                *byte = 0;
+                for index in bits.by_ref().take(8) {
+                    *byte = (*byte << 1) | u8::from(encoder.level(index) == PinState::High);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// 把 I2C 时序展开成 GPIO 帧, 同时记下需要采样的帧
+///
+/// 帧序号从整个 transaction 的第一帧算起, 已经执行的帧的电平存在 `levels` 里
+struct Encoder<'a, 'd, F> {
+    scl: &'a Flex<'d>,
+    sda: &'a Flex<'d>,
+    batch: Batch,
+    execute: F,
+    levels: Vec<Levels>,
+    /// 读数据每一位所在的帧
+    bits: Vec<usize>,
+}
+
+impl<'a, 'd, F> Encoder<'a, 'd, F>
+where
+    F: FnMut(&mut Batch) -> Result<Vec<Levels>, ErrorKind>,
+{
+    fn new(scl: &'a Flex<'d>, sda: &'a Flex<'d>, execute: F) -> Self {
+        Self {
+            scl,
+            sda,
+            batch: Batch::new(),
+            execute,
+            levels: Vec::new(),
+            bits: Vec::new(),
+        }
+    }
+
+    fn scl(&mut self, action: PinAction) -> usize {
+        self.levels.len() + self.batch.push(&[(self.scl, action)])
+    }
+
+    fn sda(&mut self, action: PinAction) -> usize {
+        self.levels.len() + self.batch.push(&[(self.sda, action)])
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorKind> {
+        let levels = (self.execute)(&mut self.batch)?;
+        self.levels.extend(levels);
+        Ok(())
+    }
+
+    fn level(&self, index: usize) -> PinState {
+        self.levels[index].get(self.sda)
+    }
+
+    /// 起始位和重复起始位一样: 先把两根线都释放, 再在 SCL 为高时拉低 SDA
+    fn start(&mut self) {
+        self.sda(RELEASE);
+        self.scl(RELEASE);
+        self.sda(PULL_LOW);
+        self.scl(PULL_LOW);
+    }
+
+    fn stop(&mut self) {
+        self.sda(PULL_LOW);
+        self.scl(RELEASE);
+        self.sda(RELEASE);
+    }
+
+    /// 写一个字节并检查 ACK, NACK 时发出 STOP
+    fn write_byte(&mut self, byte: u8, source: NoAcknowledgeSource) -> Result<(), ErrorKind> {
+        for i in (0..8).rev() {
+            self.sda(if byte >> i & 0x01 == 0x01 {
+                RELEASE
+            } else {
+                PULL_LOW
+            });
+            self.scl(RELEASE);
+            self.scl(PULL_LOW);
+        }
+
+        self.sda(RELEASE);
+        let index = self.scl(RELEASE);
+        self.scl(PULL_LOW);
+        self.flush()?;
+
+        if self.level(index) == PinState::High {
+            self.stop();
+            self.flush()?;
+            return Err(ErrorKind::NoAcknowledge(source));
+        }
+        Ok(())
+    }
+
+    fn read_byte(&mut self, ack: bool) {
+        self.sda(RELEASE);
+        for _ in 0..8 {
+            let index = self.scl(RELEASE);
+            self.bits.push(index);
+            self.scl(PULL_LOW);
+        }
+
+        self.sda(if ack { PULL_LOW } else { RELEASE });
+        self.scl(RELEASE);
+        self.scl(PULL_LOW);
+    }
+}
+
+mod embedded_hal_v100_impl {
+    use embedded_hal::i2c::*;
+
+    use super::I2cbus;
+
+    impl<'d> ErrorType for I2cbus<'d> {
+        type Error = ErrorKind;
+    }
+
+    impl<'d> I2c for I2cbus<'d> {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.run(address, operations)
+        }
+    }
+}
+
+mod embedded_hal_v027_impl {
+    use embedded_hal::i2c::ErrorKind;
+    use embedded_hal_027::blocking::i2c::*;
+
+    use super::I2cbus;
+
+    impl<'d> WriteRead for I2cbus<'d> {
+        type Error = ErrorKind;
+        fn write_read(
+            &mut self,
+            address: u8,
+            bytes: &[u8],
+            buffer: &mut [u8],
+        ) -> Result<(), Self::Error> {
+            <Self as embedded_hal::i2c::I2c>::write_read(self, address, bytes, buffer)
+        }
+    }
+
+    impl<'d> Write for I2cbus<'d> {
+        type Error = ErrorKind;
+        fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
+            <Self as embedded_hal::i2c::I2c>::write(self, address, bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+    use crate::hal::peripherals::{IO0, IO1};
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum Phase {
+        Idle,
+        Address,
+        Write,
+        Read,
+    }
+
+    /// 按帧模拟的 I2C 从机, SCL 在 IO0, SDA 在 IO1
+    struct Slave {
+        address: u8,
+        /// 写入超过这么多字节后回 NACK
+        limit: usize,
+        data: VecDeque<u8>,
+        written: Vec<u8>,
+        batches: Vec<usize>,
+        scl: bool,
+        /// 起始位之后出现过上升沿, 下降沿才算一个时钟
+        clocked: bool,
+        master_low: bool,
+        slave_low: bool,
+        phase: Phase,
+        bit: u8,
+        byte: u8,
+        ack: bool,
+        master_ack: bool,
+    }
+
+    impl Slave {
+        fn new(address: u8, limit: usize, data: &[u8]) -> Self {
+            Self {
+                address,
+                limit,
+                data: data.iter().copied().collect(),
+                written: Vec::new(),
+                batches: Vec::new(),
+                scl: true,
+                clocked: false,
+                master_low: false,
+                slave_low: false,
+                phase: Phase::Idle,
+                bit: 0,
+                byte: 0,
+                ack: false,
+                master_ack: false,
+            }
+        }
+
+        fn sda(&self) -> bool {
+            !(self.master_low || self.slave_low)
+        }
+
+        fn load(&mut self) {
+            self.byte = self.data.pop_front().unwrap_or(0xFF);
+            self.slave_low = self.byte & 0x80 == 0;
+        }
+
+        fn rising(&mut self) {
+            self.clocked = true;
+            if self.bit < 8 {
+                if matches!(self.phase, Phase::Address | Phase::Write) {
+                    self.byte = (self.byte << 1) | u8::from(self.sda());
+                }
+            } else if self.phase == Phase::Read {
+                self.master_ack = !self.sda();
+            }
+        }
+
+        fn falling(&mut self) {
+            if self.phase == Phase::Idle || !self.clocked {
+                return;
+            }
+            self.clocked = false;
+            self.bit += 1;
+            match self.bit {
+                8 => {
+                    self.ack = match self.phase {
+                        Phase::Address => self.byte >> 1 == self.address,
+                        Phase::Write => {
+                            self.written.push(self.byte);
+                            self.written.len() <= self.limit
+                        }
+                        _ => false,
+                    };
+                    self.slave_low = self.ack;
+                }
+                9 => {
+                    self.bit = 0;
+                    self.slave_low = false;
+                    self.phase = match self.phase {
+                        Phase::Address if !self.ack => Phase::Idle,
+                        Phase::Address if self.byte & 0x01 == 0x01 => Phase::Read,
+                        Phase::Address => Phase::Write,
+                        Phase::Write if !self.ack => Phase::Idle,
+                        Phase::Read if !self.master_ack => Phase::Idle,
+                        phase => phase,
+                    };
+                    self.master_ack = true;
+                    match self.phase {
+                        Phase::Read => self.load(),
+                        _ => self.byte = 0,
+                    }
+                }
+                bit if self.phase == Phase::Read => {
+                    self.slave_low = self.byte >> (7 - bit) & 0x01 == 0;
+                }
+                _ => {}
+            }
+        }
+
+        fn frame(&mut self, frame: &[u8; 8]) -> Levels {
+            let (scl, sda) = (self.scl, self.sda());
+            if frame[0] != 0 {
+                self.scl = frame[0] == u8::from(RELEASE);
+            }
+            if frame[1] != 0 {
+                self.master_low = frame[1] == u8::from(PULL_LOW);
+            }
+
+            if !scl && self.scl {
+                self.rising();
+            } else if scl && !self.scl {
+                self.falling();
+            } else if self.scl && sda && !self.sda() {
+                self.phase = Phase::Address;
+                self.clocked = false;
+                self.bit = 0;
+                self.byte = 0;
+            } else if self.scl && !sda && self.sda() {
+                self.phase = Phase::Idle;
+                self.slave_low = false;
+            }
+            Levels(u8::from(self.scl) | (u8::from(self.sda()) << 1))
+        }
+
+        fn execute(&mut self, batch: &mut Batch) -> Result<Vec<Levels>, ErrorKind> {
+            let frames = batch.take();
+            self.batches.push(frames.len());
+            Ok(frames.iter().map(|frame| self.frame(frame)).collect())
+        }
+    }
+
+    fn run(
+        slave: &mut Slave,
+        address: u8,
+        operations: &mut [Operation<'_>],
+    ) -> Result<(), ErrorKind> {
+        let scl = Flex::new(unsafe { IO0::steal() });
+        let sda = Flex::new(unsafe { IO1::steal() });
+        transaction(&scl, &sda, address, operations, |batch| {
+            slave.execute(batch)
+        })
+    }
+
+    #[test]
+    fn write_read() {
+        let mut slave = Slave::new(0x50, usize::MAX, &[0xA5, 0x3C, 0xFF]);
+        let mut buf = [0; 2];
+        run(
+            &mut slave,
+            0x50,
+            &mut [Operation::Write(&[0x12, 0x34]), Operation::Read(&mut buf)],
+        )
+        .unwrap();
+        assert_eq!(slave.written, [0x12, 0x34]);
+        assert_eq!(buf, [0xA5, 0x3C]);
+        // 最后一个字节回 NACK, 从机不再取下一个字节
+        assert_eq!(slave.data.len(), 1);
+        assert!(slave.phase == Phase::Idle);
+    }
+
+    #[test]
+    fn address_nack_stops_at_once() {
+        let mut slave = Slave::new(0x50, usize::MAX, &[]);
+        let mut buf = [0; 4];
+        let result = run(
+            &mut slave,
+            0x51,
+            &mut [Operation::Write(&[0x12, 0x34]), Operation::Read(&mut buf)],
+        );
+        assert_eq!(
+            result,
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Address))
+        );
+        // 起始位 + 地址字节一批, STOP 一批, 数据没有发出去
+        assert_eq!(slave.batches, [4 + 27, 3]);
+        assert!(slave.written.is_empty());
+    }
+
+    #[test]
+    fn data_nack_stops_at_once() {
+        let mut slave = Slave::new(0x50, 1, &[]);
+        let result = run(&mut slave, 0x50, &mut [Operation::Write(&[1, 2, 3, 4])]);
+        assert_eq!(
+            result,
+            Err(ErrorKind::NoAcknowledge(NoAcknowledgeSource::Data))
+        );
+        assert_eq!(slave.written, [1, 2]);
+        assert_eq!(slave.batches, [4 + 27, 27, 27, 3]);
+        assert!(slave.phase == Phase::Idle);
+    }
+
+    #[test]
+    fn usb_error() {
+        let scl = Flex::new(unsafe { IO0::steal() });
+        let sda = Flex::new(unsafe { IO1::steal() });
+        let result = transaction(&scl, &sda, 0x50, &mut [Operation::Write(&[0])], |_| {
+            Err(ErrorKind::Other)
+        });
+        assert_eq!(result, Err(ErrorKind::Other));
+    }
+}
@@ -0,0 +1,7 @@
+//! 用 IO0 ~ IO7 模拟的 I2C/SPI 总线
+//!
+//! 每个时钟沿都是一条 0xCC GPIO 命令, 整次传输先编成 [`crate::gpio::Batch`],
+//! 再按 USB 包一次性发出去, 速度取决于 USB 往返而不是单个引脚操作
+
+pub mod i2c;
+pub mod spi;
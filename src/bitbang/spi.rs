@@ -0,0 +1,253 @@
+use embassy_hal_internal::Peripheral;
+use embedded_hal::spi::ErrorKind;
+
+use crate::gpio::{
+    Batch, DegradePin, Flex,
+    types::{PinAction, PinState},
+};
+use crate::spi::{BitOrder, Mode};
+
+/// 软件 SPI 的配置
+///
+/// `bits` 是每个字的位数, 1 ~ 16, 比如 9 位的显示屏命令就用 `SpiBus<u16>` 加 `bits: 9`.
+/// 用 `u8` 传输时最多取 8 位
+#[derive(Debug, Clone, Copy)]
+pub struct Config {
+    pub mode: Mode,
+    pub bit_order: BitOrder,
+    pub bits: u8,
+}
+
+/// 配置不合法
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// `bits` 不在 1 ~ 16 之间
+    Bits(u8),
+}
+
+impl Config {
+    fn validate(&self) -> Result<(), ConfigError> {
+        if (1..=16).contains(&self.bits) {
+            Ok(())
+        } else {
+            Err(ConfigError::Bits(self.bits))
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            mode: Mode::Mode0,
+            bit_order: BitOrder::MSB,
+            bits: 8,
+        }
+    }
+}
+
+/// 软件 SPI, SCK/MOSI/MISO 可以是任意 IO
+///
+/// 用 [`SpiBus::new_3wire`] 创建时 MOSI 和 MISO 共用一根线, 写的时候驱动, 读的时候释放.
+/// 不带片选, 片选自己用 [`crate::gpio::Output`] 控制
+pub struct SpiBus<'d> {
+    sck: Flex<'d>,
+    mosi: Flex<'d>,
+    miso: Option<Flex<'d>>,
+    config: Config,
+}
+
+impl<'d> SpiBus<'d> {
+    pub fn new(
+        sck: impl Peripheral<P = impl DegradePin> + 'd,
+        mosi: impl Peripheral<P = impl DegradePin> + 'd,
+        miso: impl Peripheral<P = impl DegradePin> + 'd,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let bus = Self {
+            sck: Flex::new(sck),
+            mosi: Flex::new(mosi),
+            miso: Some(Flex::new(miso)),
+            config,
+        };
+        let mut batch = Batch::new();
+        batch.push(&[
+            (&bus.sck, PinAction::Output(bus.idle())),
+            (&bus.mosi, PinAction::Output(PinState::Low)),
+            (bus.miso.as_ref().unwrap(), PinAction::Input),
+        ]);
+        // 这里失败的话, 第一次传输会报告同样的错误
+        let _ = batch.execute();
+        Ok(bus)
+    }
+
+    /// 三线模式, 数据线双向
+    pub fn new_3wire(
+        sck: impl Peripheral<P = impl DegradePin> + 'd,
+        sdio: impl Peripheral<P = impl DegradePin> + 'd,
+        config: Config,
+    ) -> Result<Self, ConfigError> {
+        config.validate()?;
+        let bus = Self {
+            sck: Flex::new(sck),
+            mosi: Flex::new(sdio),
+            miso: None,
+            config,
+        };
+        let mut batch = Batch::new();
+        batch.push(&[
+            (&bus.sck, PinAction::Output(bus.idle())),
+            (&bus.mosi, PinAction::Input),
+        ]);
+        // 这里失败的话, 第一次传输会报告同样的错误
+        let _ = batch.execute();
+        Ok(bus)
+    }
+
+    pub fn set_config(&mut self, config: Config) -> Result<(), ConfigError> {
+        config.validate()?;
+        self.config = config;
+        let mut batch = Batch::new();
+        batch.push(&[(&self.sck, PinAction::Output(self.idle()))]);
+        // 这里失败的话, 第一次传输会报告同样的错误
+        let _ = batch.execute();
+        Ok(())
+    }
+
+    fn idle(&self) -> PinState {
+        match self.config.mode {
+            Mode::Mode0 | Mode::Mode1 => PinState::Low,
+            Mode::Mode2 | Mode::Mode3 => PinState::High,
+        }
+    }
+
+    /// 移出 `count` 个字并返回同时采到的字, `words` 不够时补全 1
+    ///
+    /// `drive` 为 false 时 (只在三线模式下有意义) 数据线设为输入
+    fn shift(
+        &mut self,
+        words: &[u16],
+        count: usize,
+        bits: u8,
+        drive: bool,
+    ) -> Result<Vec<u16>, ErrorKind> {
+        let idle = self.idle();
+        let active = if idle == PinState::Low {
+            PinState::High
+        } else {
+            PinState::Low
+        };
+        let cpha = matches!(self.config.mode, Mode::Mode1 | Mode::Mode3);
+        let drive = drive || self.miso.is_some();
+        let order: Vec<u8> = match self.config.bit_order {
+            BitOrder::MSB => (0..bits).rev().collect(),
+            BitOrder::LSB => (0..bits).collect(),
+        };
+
+        let mut batch = Batch::new();
+        let mut samples = Vec::with_capacity(count * bits as usize);
+        for i in 0..count {
+            let word = words.get(i).copied().unwrap_or(0xFFFF);
+            for &bit in order.iter() {
+                let data = if !drive {
+                    PinAction::Input
+                } else if word >> bit & 0x01 == 0x01 {
+                    PinAction::Output(PinState::High)
+                } else {
+                    PinAction::Output(PinState::Low)
+                };
+
+                if cpha {
+                    // 前沿放数据, 后沿采样
+                    batch.push(&[(&self.sck, PinAction::Output(active)), (&self.mosi, data)]);
+                    samples.push(batch.push(&[(&self.sck, PinAction::Output(idle))]));
+                } else {
+                    // 前沿之前放好数据, 前沿采样
+                    batch.push(&[(&self.sck, PinAction::Output(idle)), (&self.mosi, data)]);
+                    samples.push(batch.push(&[(&self.sck, PinAction::Output(active))]));
+                }
+            }
+        }
+        if !cpha && count > 0 {
+            batch.push(&[(&self.sck, PinAction::Output(idle))]);
+        }
+
+        let levels = batch.execute().map_err(|_| ErrorKind::Other)?;
+        let input = self.miso.as_ref().unwrap_or(&self.mosi);
+        Ok(samples
+            .chunks(bits as usize)
+            .map(|chunk| {
+                let mut word = 0;
+                for (&index, &bit) in chunk.iter().zip(order.iter()) {
+                    if levels[index].get(input) == PinState::High {
+                        word |= 1 << bit;
+                    }
+                }
+                word
+            })
+            .collect())
+    }
+}
+
+mod embedded_hal_v100_impl {
+    use embedded_hal::spi::*;
+
+    impl<'d> ErrorType for super::SpiBus<'d> {
+        type Error = ErrorKind;
+    }
+
+    macro_rules! spi_bus_impl {
+        ($word: ty, $max_bits: expr) => {
+            impl<'d> SpiBus<$word> for super::SpiBus<'d> {
+                fn read(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+                    let bits = self.config.bits.min($max_bits);
+                    let rev = self.shift(&[], words.len(), bits, false)?;
+                    for (word, rev) in words.iter_mut().zip(rev) {
+                        *word = rev as $word;
+                    }
+                    Ok(())
+                }
+
+                fn write(&mut self, words: &[$word]) -> Result<(), Self::Error> {
+                    let bits = self.config.bits.min($max_bits);
+                    let words: Vec<u16> = words.iter().map(|&w| u16::from(w)).collect();
+                    self.shift(&words, words.len(), bits, true)?;
+                    Ok(())
+                }
+
+                fn transfer(
+                    &mut self,
+                    read: &mut [$word],
+                    write: &[$word],
+                ) -> Result<(), Self::Error> {
+                    let bits = self.config.bits.min($max_bits);
+                    let count = read.len().max(write.len());
+                    let words: Vec<u16> = write.iter().map(|&w| u16::from(w)).collect();
+                    let rev = self.shift(&words, count, bits, true)?;
+                    for (word, rev) in read.iter_mut().zip(rev) {
+                        *word = rev as $word;
+                    }
+                    Ok(())
+                }
+
+                fn transfer_in_place(&mut self, words: &mut [$word]) -> Result<(), Self::Error> {
+                    let bits = self.config.bits.min($max_bits);
+                    let out: Vec<u16> = words.iter().map(|&w| u16::from(w)).collect();
+                    let rev = self.shift(&out, out.len(), bits, true)?;
+                    for (word, rev) in words.iter_mut().zip(rev) {
+                        *word = rev as $word;
+                    }
+                    Ok(())
+                }
+
+                fn flush(&mut self) -> Result<(), Self::Error> {
+                    // 每次操作都是同步执行完的
+                    Ok(())
+                }
+            }
+        };
+    }
+
+    spi_bus_impl!(u8, 8);
+    spi_bus_impl!(u16, 16);
+}
@@ -1,7 +1,7 @@
 use crate::gpio::hal::Pin;
 use embassy_hal_internal::{Peripheral, PeripheralRef, into_ref};
 
-mod hal {
+pub(crate) mod hal {
     static mut GPIO_COMMANDS: [u8; 11] = [
         0xCC, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
     ];
@@ -57,6 +57,51 @@ mod hal {
             }
         }
     }
+
+    /// 一个 USB 包最多塞 46 条 0xCC 命令 (46 * 11 = 506)
+    const FRAMES_PER_PACKET: usize = 46;
+
+    /// 连续执行多条 GPIO 命令, 每帧是 8 个引脚的控制字节, 0x00 表示不改变
+    /// 返回每帧执行后读回的 8 个引脚状态
+    pub fn transfer(frames: &[[u8; 8]]) -> Result<Vec<[u8; 8]>, crate::ch347::Error> {
+        let mut states = Vec::with_capacity(frames.len());
+
+        for packet in frames.chunks(FRAMES_PER_PACKET) {
+            let mut obuf = Vec::with_capacity(packet.len() * 11);
+            for frame in packet {
+                obuf.extend_from_slice(&[0xCC, 0x08, 0x00]);
+                obuf.extend_from_slice(frame);
+            }
+            write(&obuf)?;
+
+            // 回复可能分成好几次 bulk in, 收齐为止
+            let mut ibuf = Vec::with_capacity(obuf.len());
+            let mut buf = [0; 512];
+            while ibuf.len() < obuf.len() {
+                let rev = read(&mut buf)?;
+                ibuf.extend_from_slice(&buf[..rev]);
+            }
+
+            for reply in ibuf.chunks_exact(11) {
+                let mut state = [0; 8];
+                state.copy_from_slice(&reply[3..]);
+                states.push(state);
+            }
+        }
+
+        // 记住最后的方向和电平, 否则单个引脚操作时会把它们改回去
+        for frame in frames {
+            for (i, &byte) in frame.iter().enumerate() {
+                if byte != 0x00 {
+                    unsafe {
+                        GPIO_COMMANDS[3 + i] = byte;
+                    }
+                }
+            }
+        }
+
+        Ok(states)
+    }
 }
 
 pub trait DegradePin: Peripheral<P = Self> + Into<AnyPin> + hal::Pin + Sized + 'static {
@@ -66,11 +111,28 @@ pub trait DegradePin: Peripheral<P = Self> + Into<AnyPin> + hal::Pin + Sized + '
 }
 
 pub mod types {
-    #[derive(Debug, PartialEq, PartialOrd)]
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
     pub enum PinState {
         Low,
         High,
     }
+
+    /// 批量命令中单个引脚的动作
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum PinAction {
+        Input,
+        Output(PinState),
+    }
+
+    impl From<PinAction> for u8 {
+        fn from(value: PinAction) -> Self {
+            match value {
+                PinAction::Input => 0xC0,
+                PinAction::Output(PinState::Low) => 0xF0,
+                PinAction::Output(PinState::High) => 0xF8,
+            }
+        }
+    }
 }
 
 pub struct AnyPin {
@@ -119,6 +181,77 @@ impl<'d> Flex<'d> {
     }
 }
 
+/// 把多次 GPIO 操作合并成连续的 0xCC 命令一起发送, 省掉每次操作的 USB 往返
+///
+/// 每一帧对应一条 GPIO 命令, 帧里没有提到的引脚保持不变
+#[derive(Default)]
+pub struct Batch {
+    frames: Vec<[u8; 8]>,
+}
+
+impl Batch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 追加一帧, 返回帧序号, 用来在 [`Batch::execute`] 的结果里找到这一帧的采样
+    pub fn push(&mut self, actions: &[(&Flex<'_>, types::PinAction)]) -> usize {
+        let mut frame = [0; 8];
+        for (pin, action) in actions {
+            frame[pin.pin.pin() as usize] = u8::from(*action);
+        }
+        self.frames.push(frame);
+        self.frames.len() - 1
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    /// 取出并清空所有帧
+    pub(crate) fn take(&mut self) -> Vec<[u8; 8]> {
+        std::mem::take(&mut self.frames)
+    }
+
+    /// 执行并清空所有帧, 返回每帧执行后的引脚电平
+    pub fn execute(&mut self) -> Result<Vec<Levels>, crate::ch347::Error> {
+        let frames = self.take();
+        if frames.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(hal::transfer(&frames)?
+            .into_iter()
+            .map(|state| {
+                let mut mask = 0;
+                for (i, byte) in state.iter().enumerate() {
+                    if byte & 0x40 != 0 {
+                        mask |= 1 << i;
+                    }
+                }
+                Levels(mask)
+            })
+            .collect())
+    }
+}
+
+/// 一帧执行后 8 个引脚的电平
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Levels(pub u8);
+
+impl Levels {
+    pub fn get(&self, pin: &Flex<'_>) -> types::PinState {
+        if self.0 & (1 << pin.pin.pin()) != 0 {
+            types::PinState::High
+        } else {
+            types::PinState::Low
+        }
+    }
+}
+
 pub struct Output<'d> {
     pub(crate) pin: Flex<'d>,
 }
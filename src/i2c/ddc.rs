@@ -0,0 +1,809 @@
+//! 通过 DDC 读显示器的 EDID, 解析基本块、CTA-861 扩展块和 DisplayID 扩展块
+//!
+//! EDID 在 0x50, 每 256 字节是一个段. 读第 2 块以后的数据要先把段号写到 0x30 (E-DDC 段指针),
+//! 段指针在 Stop 之后清零, 所以写段指针和读数据必须在同一次传输里
+
+use std::fmt;
+
+use embedded_hal::i2c::{ErrorKind, Operation};
+
+use super::{I2cbus, Instance};
+
+pub const EDID_ADDRESS: u8 = 0x50;
+pub const SEGMENT_ADDRESS: u8 = 0x30;
+pub const BLOCK_SIZE: usize = 128;
+
+const HEADER: [u8; 8] = [0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00];
+
+#[derive(Debug)]
+pub enum Error {
+    I2c(ErrorKind),
+    /// 基本块开头不是 00 FF FF FF FF FF FF 00
+    Header,
+    /// 某一块的 128 字节加起来不是 0
+    Checksum {
+        block: usize,
+    },
+}
+
+impl From<ErrorKind> for Error {
+    fn from(value: ErrorKind) -> Self {
+        Self::I2c(value)
+    }
+}
+
+pub struct Ddc<'d, T: Instance> {
+    i2c: I2cbus<'d, T>,
+}
+
+impl<'d, T: Instance> Ddc<'d, T> {
+    pub fn new(i2c: I2cbus<'d, T>) -> Self {
+        Self { i2c }
+    }
+
+    pub fn release(self) -> I2cbus<'d, T> {
+        self.i2c
+    }
+
+    /// 读第 `index` 块, 不做校验
+    pub fn read_block(&mut self, index: usize) -> Result<[u8; BLOCK_SIZE], Error> {
+        let segment = (index / 2) as u8;
+        let offset = ((index % 2) * BLOCK_SIZE) as u8;
+        let mut block = [0; BLOCK_SIZE];
+
+        let offset = [offset];
+        if segment == 0 {
+            // 不支持 E-DDC 的显示器不应答 0x30, 第 0 段不写段指针
+            self.i2c.transaction_chain(&mut [(
+                EDID_ADDRESS,
+                &mut [Operation::Write(&offset), Operation::Read(&mut block)],
+            )])?;
+        } else {
+            let segment = [segment];
+            self.i2c.transaction_chain(&mut [
+                (SEGMENT_ADDRESS, &mut [Operation::Write(&segment)]),
+                (
+                    EDID_ADDRESS,
+                    &mut [Operation::Write(&offset), Operation::Read(&mut block)],
+                ),
+            ])?;
+        }
+        Ok(block)
+    }
+
+    /// 读基本块和所有扩展块, 检查头和每一块的校验和
+    pub fn read_raw(&mut self) -> Result<Vec<u8>, Error> {
+        let base = self.read_block(0)?;
+        if base[..8] != HEADER {
+            return Err(Error::Header);
+        }
+        verify(&base, 0)?;
+
+        let mut raw = base.to_vec();
+        for index in 1..=base[126] as usize {
+            let block = self.read_block(index)?;
+            verify(&block, index)?;
+            raw.extend_from_slice(&block);
+        }
+        Ok(raw)
+    }
+
+    pub fn read_edid(&mut self) -> Result<Edid, Error> {
+        Ok(Edid::parse(&self.read_raw()?))
+    }
+}
+
+fn verify(block: &[u8], index: usize) -> Result<(), Error> {
+    let sum = block.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+    if sum != 0 {
+        return Err(Error::Checksum { block: index });
+    }
+    Ok(())
+}
+
+/// 18 字节的详细时序描述
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetailedTiming {
+    /// kHz
+    pub pixel_clock: u32,
+    pub h_active: u16,
+    pub h_blank: u16,
+    pub h_sync_offset: u16,
+    pub h_sync_width: u16,
+    pub v_active: u16,
+    pub v_blank: u16,
+    pub v_sync_offset: u16,
+    pub v_sync_width: u16,
+    /// mm
+    pub h_size: u16,
+    pub v_size: u16,
+    pub interlaced: bool,
+}
+
+impl DetailedTiming {
+    /// 像素时钟为 0 时是显示器描述符, 返回 `None`
+    fn parse(d: &[u8]) -> Option<Self> {
+        let clock = u16::from_le_bytes([d[0], d[1]]);
+        if clock == 0 {
+            return None;
+        }
+        let hi = |byte: u8, shift: u8, mask: u8| u16::from((byte >> shift) & mask) << 8;
+        Some(Self {
+            pixel_clock: u32::from(clock) * 10,
+            h_active: u16::from(d[2]) | hi(d[4], 4, 0x0F),
+            h_blank: u16::from(d[3]) | hi(d[4], 0, 0x0F),
+            v_active: u16::from(d[5]) | hi(d[7], 4, 0x0F),
+            v_blank: u16::from(d[6]) | hi(d[7], 0, 0x0F),
+            h_sync_offset: u16::from(d[8]) | hi(d[11], 6, 0x03),
+            h_sync_width: u16::from(d[9]) | hi(d[11], 4, 0x03),
+            v_sync_offset: u16::from(d[10] >> 4) | (u16::from((d[11] >> 2) & 0x03) << 4),
+            v_sync_width: u16::from(d[10] & 0x0F) | (u16::from(d[11] & 0x03) << 4),
+            h_size: u16::from(d[12]) | hi(d[14], 4, 0x0F),
+            v_size: u16::from(d[13]) | hi(d[14], 0, 0x0F),
+            interlaced: d[17] & 0x80 != 0,
+        })
+    }
+
+    /// Hz
+    pub fn refresh(&self) -> f32 {
+        let total =
+            u32::from(self.h_active + self.h_blank) * u32::from(self.v_active + self.v_blank);
+        if total == 0 {
+            return 0.0;
+        }
+        self.pixel_clock as f32 * 1000.0 / total as f32
+    }
+}
+
+impl fmt::Display for DetailedTiming {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}x{}{} @ {:.2} Hz, {:.2} MHz",
+            self.h_active,
+            self.v_active,
+            if self.interlaced { "i" } else { "" },
+            self.refresh(),
+            self.pixel_clock as f32 / 1000.0
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StandardTiming {
+    pub h_active: u16,
+    pub v_active: u16,
+    pub refresh: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeLimits {
+    /// Hz
+    pub v_min: u8,
+    pub v_max: u8,
+    /// kHz
+    pub h_min: u8,
+    pub h_max: u8,
+    /// MHz
+    pub max_pixel_clock: u16,
+}
+
+/// CTA-861 数据块
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CtaDataBlock {
+    /// 每 3 字节一个 Short Audio Descriptor
+    Audio(Vec<[u8; 3]>),
+    /// VIC 列表, `true` 表示原生分辨率
+    Video(Vec<(u8, bool)>),
+    VendorSpecific {
+        oui: u32,
+        payload: Vec<u8>,
+    },
+    SpeakerAllocation(Vec<u8>),
+    /// 扩展标签块, 第一个字节是扩展标签
+    Extended {
+        tag: u8,
+        payload: Vec<u8>,
+    },
+    Other {
+        tag: u8,
+        payload: Vec<u8>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CtaExtension {
+    pub revision: u8,
+    pub underscan: bool,
+    pub basic_audio: bool,
+    pub ycbcr444: bool,
+    pub ycbcr422: bool,
+    pub native_timings: u8,
+    pub data_blocks: Vec<CtaDataBlock>,
+    pub detailed_timings: Vec<DetailedTiming>,
+}
+
+impl CtaExtension {
+    fn parse(block: &[u8]) -> Self {
+        let dtd_offset = (block[2] as usize).clamp(4, 127);
+        let mut data_blocks = Vec::new();
+        let mut i = 4;
+        while i < dtd_offset {
+            let tag = block[i] >> 5;
+            let len = (block[i] & 0x1F) as usize;
+            let end = (i + 1 + len).min(dtd_offset);
+            let payload = block[i + 1..end].to_vec();
+            data_blocks.push(match tag {
+                1 => CtaDataBlock::Audio(
+                    payload
+                        .chunks_exact(3)
+                        .map(|c| [c[0], c[1], c[2]])
+                        .collect(),
+                ),
+                2 => CtaDataBlock::Video(
+                    payload
+                        .iter()
+                        .map(|&v| {
+                            // VIC 1 ~ 64 的 bit7 是原生标志
+                            if (0x81..=0xC0).contains(&v) {
+                                (v & 0x7F, true)
+                            } else {
+                                (v, false)
+                            }
+                        })
+                        .collect(),
+                ),
+                3 if payload.len() >= 3 => CtaDataBlock::VendorSpecific {
+                    oui: u32::from_le_bytes([payload[0], payload[1], payload[2], 0]),
+                    payload: payload[3..].to_vec(),
+                },
+                4 => CtaDataBlock::SpeakerAllocation(payload),
+                7 if !payload.is_empty() => CtaDataBlock::Extended {
+                    tag: payload[0],
+                    payload: payload[1..].to_vec(),
+                },
+                _ => CtaDataBlock::Other { tag, payload },
+            });
+            i = end;
+        }
+
+        let detailed_timings = block[dtd_offset..127]
+            .chunks_exact(18)
+            .map_while(DetailedTiming::parse)
+            .collect();
+
+        Self {
+            revision: block[1],
+            underscan: block[3] & 0x80 != 0,
+            basic_audio: block[3] & 0x40 != 0,
+            ycbcr444: block[3] & 0x20 != 0,
+            ycbcr422: block[3] & 0x10 != 0,
+            native_timings: block[3] & 0x0F,
+            data_blocks,
+            detailed_timings,
+        }
+    }
+}
+
+/// DisplayID 数据块, 只拆出标签和内容
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayIdBlock {
+    pub tag: u8,
+    pub revision: u8,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DisplayIdExtension {
+    /// 高 4 位主版本, 低 4 位次版本
+    pub version: u8,
+    pub product_type: u8,
+    pub data_blocks: Vec<DisplayIdBlock>,
+}
+
+impl DisplayIdExtension {
+    /// EDID 扩展块里的 DisplayID 段从第 1 字节开始
+    fn parse(block: &[u8]) -> Self {
+        let section = &block[1..];
+        let bytes = (section[1] as usize).min(section.len().saturating_sub(5));
+        let mut data_blocks = Vec::new();
+        let mut i = 4;
+        while i + 3 <= 4 + bytes {
+            let len = section[i + 2] as usize;
+            if section[i] == 0 && len == 0 {
+                // 填充
+                break;
+            }
+            let end = (i + 3 + len).min(4 + bytes);
+            data_blocks.push(DisplayIdBlock {
+                tag: section[i],
+                revision: section[i + 1],
+                payload: section[i + 3..end].to_vec(),
+            });
+            i = end;
+        }
+
+        Self {
+            version: section[0],
+            product_type: section[2],
+            data_blocks,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Extension {
+    Cta(CtaExtension),
+    DisplayId(DisplayIdExtension),
+    Other { tag: u8, raw: Vec<u8> },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edid {
+    /// 三个字母的厂商 ID
+    pub manufacturer: String,
+    pub product_code: u16,
+    pub serial: u32,
+    pub week: u8,
+    pub year: u16,
+    pub version: (u8, u8),
+    pub digital: bool,
+    /// cm, 为 0 表示未知或可变
+    pub width: u8,
+    pub height: u8,
+    pub name: Option<String>,
+    pub serial_string: Option<String>,
+    pub range_limits: Option<RangeLimits>,
+    pub established_timings: Vec<&'static str>,
+    pub standard_timings: Vec<StandardTiming>,
+    pub detailed_timings: Vec<DetailedTiming>,
+    pub extensions: Vec<Extension>,
+}
+
+/// 字节 35 ~ 37 每一位对应的时序
+const ESTABLISHED_TIMINGS: [&str; 17] = [
+    "720x400@70",
+    "720x400@88",
+    "640x480@60",
+    "640x480@67",
+    "640x480@72",
+    "640x480@75",
+    "800x600@56",
+    "800x600@60",
+    "800x600@72",
+    "800x600@75",
+    "832x624@75",
+    "1024x768@87i",
+    "1024x768@60",
+    "1024x768@70",
+    "1024x768@75",
+    "1280x1024@75",
+    "1152x870@75",
+];
+
+impl Edid {
+    /// `raw` 是 [`Ddc::read_raw`] 读出的数据, 至少 128 字节
+    pub fn parse(raw: &[u8]) -> Self {
+        let base = &raw[..BLOCK_SIZE];
+
+        let id = u16::from_be_bytes([base[8], base[9]]);
+        let manufacturer = [10, 5, 0]
+            .iter()
+            .map(|&shift| (b'A' - 1 + ((id >> shift) & 0x1F) as u8) as char)
+            .collect();
+
+        let mut established_timings = Vec::new();
+        let bits = u32::from(base[35]) << 16 | u32::from(base[36]) << 8 | u32::from(base[37]);
+        for (i, name) in ESTABLISHED_TIMINGS.iter().enumerate() {
+            if bits & (1 << (23 - i)) != 0 {
+                established_timings.push(*name);
+            }
+        }
+
+        let standard_timings = base[38..54]
+            .chunks_exact(2)
+            .filter(|t| !(t[0] == 0x01 && t[1] == 0x01) && t[0] != 0x00)
+            .map(|t| {
+                let h_active = (u16::from(t[0]) + 31) * 8;
+                let v_active = match t[1] >> 6 {
+                    // EDID 1.3 以前是 1:1
+                    0b00 if base[19] < 3 => h_active,
+                    0b00 => h_active * 10 / 16,
+                    0b01 => h_active * 3 / 4,
+                    0b10 => h_active * 4 / 5,
+                    _ => h_active * 9 / 16,
+                };
+                StandardTiming {
+                    h_active,
+                    v_active,
+                    refresh: (t[1] & 0x3F) + 60,
+                }
+            })
+            .collect();
+
+        let mut edid = Self {
+            manufacturer,
+            product_code: u16::from_le_bytes([base[10], base[11]]),
+            serial: u32::from_le_bytes([base[12], base[13], base[14], base[15]]),
+            week: base[16],
+            year: 1990 + u16::from(base[17]),
+            version: (base[18], base[19]),
+            digital: base[20] & 0x80 != 0,
+            width: base[21],
+            height: base[22],
+            name: None,
+            serial_string: None,
+            range_limits: None,
+            established_timings,
+            standard_timings,
+            detailed_timings: Vec::new(),
+            extensions: Vec::new(),
+        };
+
+        for d in base[54..126].chunks_exact(18) {
+            if let Some(timing) = DetailedTiming::parse(d) {
+                edid.detailed_timings.push(timing);
+                continue;
+            }
+            let text = || {
+                String::from_utf8_lossy(&d[5..18])
+                    .split('\n')
+                    .next()
+                    .unwrap_or_default()
+                    .trim_end()
+                    .to_string()
+            };
+            match d[3] {
+                0xFF => edid.serial_string = Some(text()),
+                0xFC => edid.name = Some(text()),
+                0xFD => {
+                    edid.range_limits = Some(RangeLimits {
+                        v_min: d[5],
+                        v_max: d[6],
+                        h_min: d[7],
+                        h_max: d[8],
+                        max_pixel_clock: u16::from(d[9]) * 10,
+                    })
+                }
+                _ => {}
+            }
+        }
+
+        for block in raw[BLOCK_SIZE..].chunks_exact(BLOCK_SIZE) {
+            edid.extensions.push(match block[0] {
+                0x02 => Extension::Cta(CtaExtension::parse(block)),
+                0x70 => Extension::DisplayId(DisplayIdExtension::parse(block)),
+                tag => Extension::Other {
+                    tag,
+                    raw: block.to_vec(),
+                },
+            });
+        }
+
+        edid
+    }
+}
+
+/// 常见 VIC 的名字
+fn vic_name(vic: u8) -> Option<&'static str> {
+    Some(match vic {
+        1 => "640x480p60",
+        2 | 3 => "720x480p60",
+        4 => "1280x720p60",
+        5 => "1920x1080i60",
+        16 => "1920x1080p60",
+        17 | 18 => "720x576p50",
+        19 => "1280x720p50",
+        20 => "1920x1080i50",
+        31 => "1920x1080p50",
+        32 => "1920x1080p24",
+        33 => "1920x1080p25",
+        34 => "1920x1080p30",
+        93 => "3840x2160p24",
+        94 => "3840x2160p25",
+        95 => "3840x2160p30",
+        96 => "3840x2160p50",
+        97 => "3840x2160p60",
+        _ => return None,
+    })
+}
+
+impl fmt::Display for Edid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "EDID {}.{}", self.version.0, self.version.1)?;
+        writeln!(
+            f,
+            "  Manufacturer: {} product {:#06x} serial {:#010x}",
+            self.manufacturer, self.product_code, self.serial
+        )?;
+        writeln!(f, "  Made in week {} of {}", self.week, self.year)?;
+        if let Some(name) = &self.name {
+            writeln!(f, "  Name: {}", name)?;
+        }
+        if let Some(serial) = &self.serial_string {
+            writeln!(f, "  Serial: {}", serial)?;
+        }
+        writeln!(
+            f,
+            "  {} input, {}x{} cm",
+            if self.digital { "Digital" } else { "Analog" },
+            self.width,
+            self.height
+        )?;
+        if let Some(r) = &self.range_limits {
+            writeln!(
+                f,
+                "  Range: {}-{} Hz V, {}-{} kHz H, max {} MHz",
+                r.v_min, r.v_max, r.h_min, r.h_max, r.max_pixel_clock
+            )?;
+        }
+        if !self.established_timings.is_empty() {
+            writeln!(
+                f,
+                "  Established timings: {}",
+                self.established_timings.join(" ")
+            )?;
+        }
+        for t in self.standard_timings.iter() {
+            writeln!(
+                f,
+                "  Standard timing: {}x{}@{}",
+                t.h_active, t.v_active, t.refresh
+            )?;
+        }
+        for t in self.detailed_timings.iter() {
+            writeln!(f, "  Detailed timing: {}", t)?;
+        }
+
+        for (i, extension) in self.extensions.iter().enumerate() {
+            match extension {
+                Extension::Cta(cta) => {
+                    writeln!(f, "Block {}: CTA-861 revision {}", i + 1, cta.revision)?;
+                    writeln!(
+                        f,
+                        "  underscan {} audio {} YCbCr 4:4:4 {} 4:2:2 {}",
+                        cta.underscan, cta.basic_audio, cta.ycbcr444, cta.ycbcr422
+                    )?;
+                    for block in cta.data_blocks.iter() {
+                        match block {
+                            CtaDataBlock::Audio(sads) => {
+                                writeln!(f, "  Audio: {} descriptors", sads.len())?
+                            }
+                            CtaDataBlock::Video(vics) => {
+                                write!(f, "  Video:")?;
+                                for &(vic, native) in vics.iter() {
+                                    write!(f, " VIC{}", vic)?;
+                                    if let Some(name) = vic_name(vic) {
+                                        write!(f, "({})", name)?;
+                                    }
+                                    if native {
+                                        write!(f, "*")?;
+                                    }
+                                }
+                                writeln!(f)?;
+                            }
+                            CtaDataBlock::VendorSpecific { oui, payload } => writeln!(
+                                f,
+                                "  Vendor specific: OUI {:06x}, {} bytes",
+                                oui,
+                                payload.len()
+                            )?,
+                            CtaDataBlock::SpeakerAllocation(payload) => {
+                                writeln!(f, "  Speaker allocation: {:02x?}", payload)?
+                            }
+                            CtaDataBlock::Extended { tag, payload } => {
+                                writeln!(f, "  Extended tag {}: {} bytes", tag, payload.len())?
+                            }
+                            CtaDataBlock::Other { tag, payload } => {
+                                writeln!(f, "  Tag {}: {} bytes", tag, payload.len())?
+                            }
+                        }
+                    }
+                    for t in cta.detailed_timings.iter() {
+                        writeln!(f, "  Detailed timing: {}", t)?;
+                    }
+                }
+                Extension::DisplayId(id) => {
+                    writeln!(
+                        f,
+                        "Block {}: DisplayID {}.{}, product type {}",
+                        i + 1,
+                        id.version >> 4,
+                        id.version & 0x0F,
+                        id.product_type
+                    )?;
+                    for block in id.data_blocks.iter() {
+                        writeln!(
+                            f,
+                            "  Data block {:#04x} rev {}: {} bytes",
+                            block.tag,
+                            block.revision,
+                            block.payload.len()
+                        )?;
+                    }
+                }
+                Extension::Other { tag, .. } => {
+                    writeln!(f, "Block {}: extension tag {:#04x}", i + 1, tag)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 1920x1080@60 的详细时序
+    const DTD_1080P: [u8; 18] = [
+        0x02, 0x3A, 0x80, 0x18, 0x71, 0x38, 0x2D, 0x40, 0x58, 0x2C, 0x45, 0x00, 0x50, 0x2D, 0x21,
+        0x00, 0x00, 0x1E,
+    ];
+
+    fn seal(block: &mut [u8]) {
+        let sum = block[..127].iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        block[127] = 0u8.wrapping_sub(sum);
+    }
+
+    fn base(extensions: u8) -> Vec<u8> {
+        let mut b = vec![0; BLOCK_SIZE];
+        b[..8].copy_from_slice(&HEADER);
+        // "DEL"
+        b[8..10].copy_from_slice(&[0x10, 0xAC]);
+        b[10..12].copy_from_slice(&[0xB1, 0xA0]);
+        b[12..16].copy_from_slice(&[0x78, 0x56, 0x34, 0x12]);
+        b[16] = 12;
+        b[17] = 30;
+        b[18..20].copy_from_slice(&[1, 4]);
+        b[20] = 0x80;
+        b[21..23].copy_from_slice(&[60, 34]);
+        b[35] = 0x20;
+        b[38..54].fill(0x01);
+        // 1920x1080 16:9 @60
+        b[38..40].copy_from_slice(&[0xD1, 0xC0]);
+        b[54..72].copy_from_slice(&DTD_1080P);
+        b[72..77].copy_from_slice(&[0, 0, 0, 0xFC, 0]);
+        b[77..90].copy_from_slice(b"TEST\n        ");
+        b[126] = extensions;
+        seal(&mut b);
+        b
+    }
+
+    fn cta() -> Vec<u8> {
+        let mut b = vec![0; BLOCK_SIZE];
+        b[..4].copy_from_slice(&[0x02, 0x03, 20, 0xF1]);
+        b[4..20].copy_from_slice(&[
+            // 视频: VIC 16 原生, VIC 4
+            0x42, 0x90, 0x04, //
+            // 音频: 一个 SAD
+            0x23, 0x09, 0x07, 0x07, //
+            // HDMI VSDB
+            0x65, 0x03, 0x0C, 0x00, 0x10, 0x00, //
+            // 扩展标签 5 (Colorimetry)
+            0xE2, 0x05, 0x40,
+        ]);
+        b[20..38].copy_from_slice(&DTD_1080P);
+        seal(&mut b);
+        b
+    }
+
+    fn display_id() -> Vec<u8> {
+        let mut b = vec![0; BLOCK_SIZE];
+        b[0] = 0x70;
+        b[1..5].copy_from_slice(&[0x20, 9, 0x03, 0x00]);
+        b[5..14].copy_from_slice(&[0x22, 0x00, 0x03, 1, 2, 3, 0x81, 0x01, 0x00]);
+        seal(&mut b);
+        b
+    }
+
+    #[test]
+    fn checksum() {
+        let mut b = base(0);
+        assert!(verify(&b, 0).is_ok());
+        b[100] ^= 0x01;
+        assert!(matches!(verify(&b, 3), Err(Error::Checksum { block: 3 })));
+        assert!(verify(&cta(), 1).is_ok());
+        assert!(verify(&display_id(), 1).is_ok());
+    }
+
+    #[test]
+    fn base_block() {
+        let edid = Edid::parse(&base(0));
+        assert_eq!(edid.manufacturer, "DEL");
+        assert_eq!(edid.product_code, 0xA0B1);
+        assert_eq!(edid.serial, 0x1234_5678);
+        assert_eq!(edid.year, 2020);
+        assert_eq!(edid.version, (1, 4));
+        assert!(edid.digital);
+        assert_eq!(edid.name.as_deref(), Some("TEST"));
+        assert_eq!(edid.established_timings, ["640x480@60"]);
+        assert_eq!(
+            edid.standard_timings,
+            [StandardTiming {
+                h_active: 1920,
+                v_active: 1080,
+                refresh: 60,
+            }]
+        );
+        assert_eq!(edid.detailed_timings.len(), 1);
+        let t = edid.detailed_timings[0];
+        assert_eq!(t.pixel_clock, 148_500);
+        assert_eq!((t.h_active, t.h_blank), (1920, 280));
+        assert_eq!((t.v_active, t.v_blank), (1080, 45));
+        assert!(edid.extensions.is_empty());
+    }
+
+    #[test]
+    fn cta_extension() {
+        let mut raw = base(1);
+        raw.extend(cta());
+        let edid = Edid::parse(&raw);
+        let [Extension::Cta(ext)] = &edid.extensions[..] else {
+            panic!("{:?}", edid.extensions);
+        };
+        assert_eq!(ext.revision, 3);
+        assert!(ext.underscan && ext.basic_audio && ext.ycbcr444 && ext.ycbcr422);
+        assert_eq!(ext.native_timings, 1);
+        assert_eq!(
+            ext.data_blocks,
+            [
+                CtaDataBlock::Video(vec![(16, true), (4, false)]),
+                CtaDataBlock::Audio(vec![[0x09, 0x07, 0x07]]),
+                CtaDataBlock::VendorSpecific {
+                    oui: 0x000C03,
+                    payload: vec![0x10, 0x00],
+                },
+                CtaDataBlock::Extended {
+                    tag: 5,
+                    payload: vec![0x40],
+                },
+            ]
+        );
+        assert_eq!(ext.detailed_timings.len(), 1);
+        assert_eq!(ext.detailed_timings[0].h_active, 1920);
+    }
+
+    #[test]
+    fn display_id_extension() {
+        let mut raw = base(2);
+        raw.extend(display_id());
+        raw.extend(cta());
+        let edid = Edid::parse(&raw);
+        assert_eq!(edid.extensions.len(), 2);
+        let Extension::DisplayId(ext) = &edid.extensions[0] else {
+            panic!("{:?}", edid.extensions[0]);
+        };
+        assert_eq!(ext.version, 0x20);
+        assert_eq!(ext.product_type, 0x03);
+        assert_eq!(
+            ext.data_blocks,
+            [
+                DisplayIdBlock {
+                    tag: 0x22,
+                    revision: 0,
+                    payload: vec![1, 2, 3],
+                },
+                DisplayIdBlock {
+                    tag: 0x81,
+                    revision: 1,
+                    payload: vec![],
+                },
+            ]
+        );
+        assert!(matches!(edid.extensions[1], Extension::Cta(_)));
+    }
+
+    #[test]
+    fn unknown_extension() {
+        let mut raw = base(1);
+        let mut block = vec![0; BLOCK_SIZE];
+        block[0] = 0x10;
+        raw.extend(&block);
+        let edid = Edid::parse(&raw);
+        assert!(matches!(
+            edid.extensions[..],
+            [Extension::Other { tag: 0x10, .. }]
+        ));
+    }
+}
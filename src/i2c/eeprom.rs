@@ -0,0 +1,413 @@
+//! 24Cxx 系列 I2C EEPROM
+//!
+//! - 24C01 ~ 24C16 用 1 字节字地址, 超过 256 字节的部分把高位地址放进器件地址的 A0 ~ A2 (块选择)
+//! - 24C32 ~ 24C512 用 2 字节字地址
+//! - 写入按页对齐, 每写完一页用 ACK 轮询等内部写周期结束
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::i2c::{Error as _, ErrorKind, I2c};
+
+use crate::util::first_mismatch;
+
+/// 内部写周期一般 5ms, 最多等这么久
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    At24C01,
+    At24C02,
+    At24C04,
+    At24C08,
+    At24C16,
+    At24C32,
+    At24C64,
+    At24C128,
+    At24C256,
+    At24C512,
+}
+
+impl Chip {
+    /// 容量, 字节
+    pub fn size(&self) -> usize {
+        match self {
+            Chip::At24C01 => 128,
+            Chip::At24C02 => 256,
+            Chip::At24C04 => 512,
+            Chip::At24C08 => 1024,
+            Chip::At24C16 => 2048,
+            Chip::At24C32 => 4096,
+            Chip::At24C64 => 8192,
+            Chip::At24C128 => 16384,
+            Chip::At24C256 => 32768,
+            Chip::At24C512 => 65536,
+        }
+    }
+
+    pub fn page_size(&self) -> usize {
+        match self {
+            Chip::At24C01 | Chip::At24C02 => 8,
+            Chip::At24C04 | Chip::At24C08 | Chip::At24C16 => 16,
+            Chip::At24C32 | Chip::At24C64 => 32,
+            Chip::At24C128 | Chip::At24C256 => 64,
+            Chip::At24C512 => 128,
+        }
+    }
+
+    /// 字地址的字节数
+    pub fn address_bytes(&self) -> usize {
+        if self.size() <= 2048 { 1 } else { 2 }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// 地址超出芯片容量
+    OutOfRange,
+    /// 写完之后一直没有 ACK
+    Timeout,
+    /// 校验失败的第一个地址
+    Verify {
+        offset: usize,
+        expected: u8,
+        found: u8,
+    },
+}
+
+pub struct Eeprom<I2C> {
+    i2c: I2C,
+    chip: Chip,
+    address: u8,
+}
+
+impl<I2C: I2c> Eeprom<I2C> {
+    /// A0 ~ A2 都接地, 器件地址 0x50
+    pub fn new(i2c: I2C, chip: Chip) -> Self {
+        Self::with_address(i2c, chip, 0x50)
+    }
+
+    /// `address` 是 7 位器件地址, 块选择位会在访问时自动加上
+    pub fn with_address(i2c: I2C, chip: Chip, address: u8) -> Self {
+        Self { i2c, chip, address }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    /// 器件地址 (带块选择位) 和字地址
+    fn target(&self, offset: usize) -> (u8, Vec<u8>) {
+        if self.chip.address_bytes() == 1 {
+            let block = (offset >> 8) as u8 & 0x07;
+            (self.address | block, vec![offset as u8])
+        } else {
+            (self.address, (offset as u16).to_be_bytes().to_vec())
+        }
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), Error<I2C::Error>> {
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > self.chip.size())
+        {
+            Err(Error::OutOfRange)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// 随机读, 1 字节地址的芯片按 256 字节的块分开读
+    pub fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        self.check_range(offset, buf.len())?;
+
+        let block = if self.chip.address_bytes() == 1 {
+            256
+        } else {
+            self.chip.size()
+        };
+        let mut done = 0;
+        while done < buf.len() {
+            let at = offset + done;
+            let len = (block - at % block).min(buf.len() - done);
+            let (device, word) = self.target(at);
+            self.i2c
+                .write_read(device, &word, &mut buf[done..done + len])
+                .map_err(Error::I2c)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// 按页拆开写, 每页写完等 ACK
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        self.check_range(offset, data.len())?;
+
+        let page = self.chip.page_size();
+        let mut done = 0;
+        while done < data.len() {
+            let at = offset + done;
+            let len = (page - at % page).min(data.len() - done);
+            let (device, mut obuf) = self.target(at);
+            obuf.extend_from_slice(&data[done..done + len]);
+            self.i2c.write(device, &obuf).map_err(Error::I2c)?;
+            self.wait_ready(device)?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// 写周期期间芯片不应答, 发空写直到有 ACK
+    fn wait_ready(&mut self, device: u8) -> Result<(), Error<I2C::Error>> {
+        let start = Instant::now();
+        loop {
+            match self.i2c.write(device, &[]) {
+                Ok(()) => return Ok(()),
+                Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => {
+                    if start.elapsed() > WRITE_TIMEOUT {
+                        return Err(Error::Timeout);
+                    }
+                    sleep(Duration::from_micros(500));
+                }
+                Err(e) => return Err(Error::I2c(e)),
+            }
+        }
+    }
+
+    /// 读出整片, `progress(done, total)`
+    pub fn read_image(
+        &mut self,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<u8>, Error<I2C::Error>> {
+        let size = self.chip.size();
+        let mut image = vec![0; size];
+        for (i, chunk) in image.chunks_mut(256).enumerate() {
+            self.read(i * 256, chunk)?;
+            progress(i * 256 + chunk.len(), size);
+        }
+        Ok(image)
+    }
+
+    /// 从 0 地址开始写入整个镜像, `progress(done, total)`
+    pub fn write_image(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<I2C::Error>> {
+        self.check_range(0, image.len())?;
+        let page = self.chip.page_size();
+        for (i, chunk) in image.chunks(page).enumerate() {
+            self.write(i * page, chunk)?;
+            progress(i * page + chunk.len(), image.len());
+        }
+        Ok(())
+    }
+
+    /// 整片镜像读回比较, 出错时带上偏移和两边的值
+    pub fn verify_image(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<I2C::Error>> {
+        self.check_range(0, image.len())?;
+        let mut buf = [0; 256];
+        for (i, expected) in image.chunks(256).enumerate() {
+            let found = &mut buf[..expected.len()];
+            self.read(i * 256, found)?;
+            if let Some((j, expected, found)) = first_mismatch(expected, found) {
+                return Err(Error::Verify {
+                    offset: i * 256 + j,
+                    expected,
+                    found,
+                });
+            }
+            progress(i * 256 + expected.len(), image.len());
+        }
+        Ok(())
+    }
+}
+
+mod embedded_storage_impl {
+    use embedded_hal::i2c::I2c;
+    use embedded_storage::{ReadStorage, Storage};
+
+    use super::{Eeprom, Error};
+
+    impl<I2C: I2c> ReadStorage for Eeprom<I2C> {
+        type Error = Error<I2C::Error>;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            Eeprom::read(self, offset as usize, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.chip.size()
+        }
+    }
+
+    impl<I2C: I2c> Storage for Eeprom<I2C> {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            Eeprom::write(self, offset as usize, bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::i2c::{ErrorType, Operation};
+
+    use super::*;
+
+    /// 模拟的 24Cxx: 按芯片规则解码块选择和字地址, 页内写会回绕
+    struct Mock {
+        chip: Chip,
+        memory: Vec<u8>,
+        pointer: usize,
+        /// 每次写的器件地址和数据长度
+        writes: Vec<(u8, usize)>,
+        /// 每次读的器件地址和长度
+        reads: Vec<(u8, usize)>,
+    }
+
+    impl Mock {
+        fn new(chip: Chip) -> Self {
+            Self {
+                chip,
+                memory: vec![0xFF; chip.size()],
+                pointer: 0,
+                writes: Vec::new(),
+                reads: Vec::new(),
+            }
+        }
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Mock {
+        fn transaction(
+            &mut self,
+            address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            for op in operations {
+                match op {
+                    // ACK 轮询
+                    Operation::Write([]) => {}
+                    Operation::Write(buf) => {
+                        let n = self.chip.address_bytes();
+                        let (word, data) = buf.split_at(n);
+                        self.pointer = if n == 1 {
+                            usize::from(address & 0x07) << 8 | usize::from(word[0])
+                        } else {
+                            usize::from(u16::from_be_bytes([word[0], word[1]]))
+                        } % self.chip.size();
+                        if !data.is_empty() {
+                            self.writes.push((address, data.len()));
+                        }
+                        let page = self.chip.page_size();
+                        let base = self.pointer - self.pointer % page;
+                        for (i, &byte) in data.iter().enumerate() {
+                            self.memory[base + (self.pointer + i) % page] = byte;
+                        }
+                    }
+                    Operation::Read(buf) => {
+                        self.reads.push((address, buf.len()));
+                        for byte in buf.iter_mut() {
+                            *byte = self.memory[self.pointer];
+                            self.pointer = (self.pointer + 1) % self.chip.size();
+                        }
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn target() {
+        let cases: [(Chip, usize, u8, &[u8]); 7] = [
+            (Chip::At24C01, 0x7F, 0x50, &[0x7F]),
+            (Chip::At24C02, 0xFF, 0x50, &[0xFF]),
+            (Chip::At24C04, 0x1AB, 0x51, &[0xAB]),
+            (Chip::At24C08, 0x3CD, 0x53, &[0xCD]),
+            (Chip::At24C16, 0x7FF, 0x57, &[0xFF]),
+            (Chip::At24C32, 0xABC, 0x50, &[0x0A, 0xBC]),
+            (Chip::At24C512, 0xFFFF, 0x50, &[0xFF, 0xFF]),
+        ];
+        for (chip, offset, device, word) in cases {
+            let eeprom = Eeprom::new(Mock::new(chip), chip);
+            assert_eq!(eeprom.target(offset), (device, word.to_vec()), "{chip:?}");
+        }
+
+        // A2 接高电平的 24C04, 块选择位加在上面
+        let eeprom = Eeprom::with_address(Mock::new(Chip::At24C04), Chip::At24C04, 0x54);
+        assert_eq!(eeprom.target(0x100), (0x55, vec![0x00]));
+    }
+
+    #[test]
+    fn write_splits_pages() {
+        let mut eeprom = Eeprom::new(Mock::new(Chip::At24C02), Chip::At24C02);
+        let data: Vec<u8> = (0..20).collect();
+        eeprom.write(5, &data).unwrap();
+        let mock = eeprom.release();
+        assert_eq!(mock.writes, [(0x50, 3), (0x50, 8), (0x50, 8), (0x50, 1)]);
+        assert_eq!(&mock.memory[5..25], &data);
+        assert_eq!(mock.memory[4], 0xFF);
+        assert_eq!(mock.memory[25], 0xFF);
+    }
+
+    #[test]
+    fn write_crosses_block() {
+        let mut eeprom = Eeprom::new(Mock::new(Chip::At24C04), Chip::At24C04);
+        let data = [0xA5; 32];
+        eeprom.write(0xF8, &data).unwrap();
+        let mock = eeprom.release();
+        assert_eq!(mock.writes, [(0x50, 8), (0x51, 16), (0x51, 8)]);
+        assert_eq!(&mock.memory[0xF8..0x118], &data);
+    }
+
+    #[test]
+    fn read_splits_blocks() {
+        let mut mock = Mock::new(Chip::At24C08);
+        for (i, byte) in mock.memory.iter_mut().enumerate() {
+            *byte = i as u8 ^ (i >> 8) as u8;
+        }
+        let expected = mock.memory[0xF0..0x210].to_vec();
+        let mut eeprom = Eeprom::new(mock, Chip::At24C08);
+        let mut buf = [0; 0x120];
+        eeprom.read(0xF0, &mut buf).unwrap();
+        assert_eq!(&buf[..], &expected[..]);
+        let mock = eeprom.release();
+        assert_eq!(mock.reads, [(0x50, 0x10), (0x51, 0x100), (0x52, 0x10)]);
+
+        // 2 字节地址的芯片一次读完
+        let mut eeprom = Eeprom::new(Mock::new(Chip::At24C32), Chip::At24C32);
+        let mut buf = [0; 0x300];
+        eeprom.read(0x80, &mut buf).unwrap();
+        assert_eq!(eeprom.release().reads, [(0x50, 0x300)]);
+    }
+
+    #[test]
+    fn range() {
+        let mut eeprom = Eeprom::new(Mock::new(Chip::At24C02), Chip::At24C02);
+        let mut buf = [0; 2];
+        assert!(eeprom.read(255, &mut buf[..1]).is_ok());
+        assert!(matches!(eeprom.read(255, &mut buf), Err(Error::OutOfRange)));
+        // offset + len 溢出也不能绕过检查
+        assert!(matches!(
+            eeprom.read(usize::MAX, &mut buf),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            eeprom.write(usize::MAX - 1, &buf),
+            Err(Error::OutOfRange)
+        ));
+    }
+}
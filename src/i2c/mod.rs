@@ -1,73 +1,102 @@
 use std::marker::PhantomData;
 
 use embassy_hal_internal::Peripheral;
+use embedded_hal::i2c::ErrorKind;
 
-use crate::ch347;
+pub mod ddc;
+pub mod eeprom;
+pub mod pmbus;
+mod scan;
+pub mod smbus;
+pub mod stm32boot;
+mod stream;
+
+pub use scan::{Found, Part, Probe, ScanConfig, ScanReport, guess};
 
 pub mod instance {
+    use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+
+    use super::stream::{self, Address, Stream};
     use crate::ch347;
+
     pub trait Instance {
-        fn write_with_address(address: u8, buf: &[u8]) {
-            let mut obuf = vec![address << 1];
-            obuf.extend_from_slice(buf);
-            let mut left = obuf.len();
-            let mut ptr = 0;
-            let mut is_first = true;
-
-            // 剩余发送数据
-            while left > 0 {
-                // 发多少数据就多少个ACK, 返回数据没有命令头
-                let mut ibuf = [0; 63];
-                // 一次只能发送 63 byte
-                let wlen = left.min(63);
-
-                let chunk = &obuf[ptr..wlen];
-
-                // Stream start
-                let mut command = vec![0xAA];
-
-                // 如果不是开始则不用Sta信号
-                if is_first {
-                    command.push(0x74);
-                    is_first = false;
-                }
-
-                command.push(0x80 | chunk.len() as u8);
-                command.extend_from_slice(chunk);
-
-                // 如果是最后的数据包则发送 Stop 信号
-                if left == wlen {
-                    command.push(0x75);
-                }
-                command.push(0x00);
-
-                ch347::write(&command).unwrap();
-
-                let _rev = ch347::read(&mut ibuf).unwrap();
-
-                ptr += wlen;
-                left -= wlen;
+        /// 0xE2 设置 I2C 引擎, 再用流命令设置速度
+        fn setup(speed: u8) -> Result<(), ErrorKind> {
+            // 我也不知道具体是什么，可能是设置引脚复用
+            ch347::write(&[
+                0xE2, 0x08, 0x00, 0x00, 0x00, 0x81, 0x81, 0x00, 0x00, 0x00, 0x00,
+            ])
+            .map_err(|_| ErrorKind::Other)?;
+            let mut _ibuf = [0; 4];
+            ch347::read(&mut _ibuf).map_err(|_| ErrorKind::Other)?;
+
+            // set speed
+            let buf = [0xAA, 0x60 | speed, 0x00];
+            ch347::write(&buf).map_err(|_| ErrorKind::Other)?;
+            Ok(())
+        }
+
+        /// 0x7C ~ 0x7F 是保留地址, 不会有从机应答, 读到 ACK 说明 SDA 被拉低了.
+        /// 引擎没有回复也当作总线被占住
+        fn is_stuck() -> bool {
+            let mut stream = Stream::new();
+            stream.start();
+            stream.write(&[0x7F << 1], NoAcknowledgeSource::Address);
+            stream.stop();
+            match stream.execute_raw() {
+                Ok(replies) => replies.first().is_none_or(|&r| stream::is_ack(r)),
+                Err(_) => true,
             }
         }
 
-        fn read_with_address(address: u8, buf: &mut [u8]) {
-            // 读取时序是发送读i2c从机地址和寄存器地址，然后接受
-            // 反正一次最多接收63字节
-            let mut ibuf = [0; 64];
-
-            let command = vec![
-                0xAA,
-                0x74,
-                0x81,
-                (address << 1) | 1,
-                0xC0 | buf.len() as u8,
-                0x75,
-                0x00,
-            ];
-            ch347::write(&command).unwrap();
-            let rev = ch347::read(&mut ibuf).unwrap();
-            // assert_eq!(rev, ibuf.len()); // 1 个 ACK + 数据接收
-            buf.copy_from_slice(&ibuf[1..rev]);
+        /// 读一个字节并回 NACK, 刚好 9 个 SCL 时钟, 然后发 Stop.
+        /// 读回的数据有任何一位是 1 说明从机已经松开 SDA
+        fn clock_out() -> Result<bool, ErrorKind> {
+            let mut stream = Stream::new();
+            stream.read(1, true);
+            stream.stop();
+            let replies = stream.execute_raw()?;
+            Ok(replies.first().is_some_and(|&r| r != 0x00))
+        }
+
+        /// 一个 Start, 方向改变时 Repeated Start, 最后一个 Stop, 尽量放在一个 USB 包里
+        fn transaction(address: u8, operations: &mut [Operation<'_>]) -> Result<(), ErrorKind> {
+            stream::transaction(Address::Seven(address), operations)
+        }
+
+        /// 10 位地址, 只用低 10 位
+        fn transaction_ten_bit(
+            address: u16,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), ErrorKind> {
+            stream::transaction(Address::Ten(address & 0x3FF), operations)
+        }
+
+        /// 7 位地址的多个从机放在一次传输里, 换地址时发 Repeated Start
+        fn transaction_chain(parts: &mut [(u8, &mut [Operation<'_>])]) -> Result<(), ErrorKind> {
+            let mut parts: Vec<(Address, &mut [Operation<'_>])> = parts
+                .iter_mut()
+                .map(|(address, operations)| (Address::Seven(*address), &mut **operations))
+                .collect();
+            stream::chain(&mut parts)
+        }
+
+        /// SMBus 块读, 见 [`crate::i2c::smbus::BlockRead`]
+        fn counted_read(
+            address: u8,
+            written: &[u8],
+            max: usize,
+            extra: usize,
+        ) -> Result<Vec<u8>, ErrorKind> {
+            stream::counted_read(address, written, max, extra)
+        }
+
+        fn write_with_address(address: u8, buf: &[u8]) -> Result<(), ErrorKind> {
+            Self::transaction(address, &mut [Operation::Write(buf)])
+        }
+
+        fn read_with_address(address: u8, buf: &mut [u8]) -> Result<(), ErrorKind> {
+            Self::transaction(address, &mut [Operation::Read(buf)])
         }
     }
 }
@@ -78,30 +107,62 @@ impl Instance for crate::hal::peripherals::I2C {}
 
 pub struct I2cbus<'d, T: Instance> {
     _i2c: PhantomData<&'d T>,
+    speed: u8,
 }
 
 impl<'d, T: Instance> I2cbus<'d, T> {
     pub fn new(_i2c: impl Peripheral<P = T>, config: Config) -> Self {
-        // 我也不知道具体是什么，可能是设置引脚复用
-        ch347::write(&[
-            0xE2, 0x08, 0x00, 0x00, 0x00, 0x81, 0x81, 0x00, 0x00, 0x00, 0x00,
-        ])
-        .unwrap();
-        let mut _ibuf = [0; 4];
-        ch347::read(&mut _ibuf).unwrap();
-
-        // set speed
-        let buf = [0xAA, 0x60 | config.speed, 0x00];
-        ch347::write(&buf).unwrap();
-        Self { _i2c: PhantomData }
+        T::setup(config.speed).unwrap();
+        Self {
+            _i2c: PhantomData,
+            speed: config.speed,
+        }
     }
 
-    pub fn write_with_address(&self, address: u8, buf: &[u8]) {
-        T::write_with_address(address, buf);
+    /// 从机没有应答时返回 [`ErrorKind::NoAcknowledge`]
+    pub fn write_with_address(&self, address: u8, buf: &[u8]) -> Result<(), ErrorKind> {
+        self.checked(T::write_with_address(address, buf))
     }
 
-    pub fn read_with_address(&self, address: u8, buf: &mut [u8]) {
-        T::read_with_address(address, buf);
+    pub fn read_with_address(&self, address: u8, buf: &mut [u8]) -> Result<(), ErrorKind> {
+        self.checked(T::read_with_address(address, buf))
+    }
+
+    /// 一次传输里依次访问多个从机, 只在最后发 Stop
+    pub fn transaction_chain(
+        &mut self,
+        parts: &mut [(u8, &mut [embedded_hal::i2c::Operation<'_>])],
+    ) -> Result<(), ErrorKind> {
+        self.checked(T::transaction_chain(parts))
+    }
+
+    /// 从机在传输中途复位时可能一直拉着 SDA, 之后所有传输都会失败
+    ///
+    /// 总线被占住时最多打 9 个 SCL 时钟让从机把这个字节送完, 发 Stop,
+    /// 然后重新初始化 I2C 引擎. 总线空闲时直接返回, 恢复不了返回 [`ErrorKind::Bus`]
+    pub fn recover(&self) -> Result<(), ErrorKind> {
+        if !T::is_stuck() {
+            return Ok(());
+        }
+        log::info!("i2c bus is stuck, try to recover");
+
+        // 引擎只能按字节打时钟, 读一个字节回 NACK 正好 9 个时钟
+        let released = T::clock_out().unwrap_or(false);
+        T::setup(self.speed)?;
+
+        if !released && T::is_stuck() {
+            return Err(ErrorKind::Bus);
+        }
+        Ok(())
+    }
+
+    /// 仲裁丢失或总线错误 (引擎没有回复) 说明总线可能被占住了, 自动恢复一次,
+    /// 原来的错误照样返回
+    fn checked<R>(&self, result: Result<R, ErrorKind>) -> Result<R, ErrorKind> {
+        if let Err(ErrorKind::Bus | ErrorKind::ArbitrationLoss) = result {
+            let _ = self.recover();
+        }
+        result
     }
 }
 
@@ -130,7 +191,7 @@ mod embedded_hal_v100_impl {
     use super::I2cbus;
 
     impl<'d, T: Instance> ErrorType for I2cbus<'d, T> {
-        type Error = core::convert::Infallible;
+        type Error = ErrorKind;
     }
 
     impl<'d, T: Instance> I2c for I2cbus<'d, T> {
@@ -139,28 +200,29 @@ mod embedded_hal_v100_impl {
             address: u8,
             operations: &mut [Operation<'_>],
         ) -> Result<(), Self::Error> {
-            for op in operations.iter_mut() {
-                match op {
-                    Operation::Read(buf) => {
-                        self.read_with_address(address, buf);
-                    }
-                    Operation::Write(buf) => {
-                        self.write_with_address(address, buf);
-                    }
-                }
-            }
-            Ok(())
+            self.checked(T::transaction(address, operations))
+        }
+    }
+
+    impl<'d, T: Instance> I2c<TenBitAddress> for I2cbus<'d, T> {
+        fn transaction(
+            &mut self,
+            address: u16,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            self.checked(T::transaction_ten_bit(address, operations))
         }
     }
 }
 
 mod embedded_hal_v027_impl {
+    use embedded_hal::i2c::ErrorKind;
     use embedded_hal_027::blocking::i2c::*;
 
     use crate::i2c::{I2cbus, Instance};
 
     impl<'d, T: Instance> WriteRead for I2cbus<'d, T> {
-        type Error = core::convert::Infallible;
+        type Error = ErrorKind;
         fn write_read(
             &mut self,
             address: u8,
@@ -173,10 +235,9 @@ mod embedded_hal_v027_impl {
     }
 
     impl<'d, T: Instance> Write for I2cbus<'d, T> {
-        type Error = core::convert::Infallible;
+        type Error = ErrorKind;
         fn write(&mut self, address: u8, bytes: &[u8]) -> Result<(), Self::Error> {
-            <Self as embedded_hal::i2c::I2c>::write(self, address, bytes).unwrap();
-            Ok(())
+            <Self as embedded_hal::i2c::I2c>::write(self, address, bytes)
         }
     }
 }
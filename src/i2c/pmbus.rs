@@ -0,0 +1,632 @@
+//! PMBus 遥测和配置, 建在 [`super::smbus::SmBus`] 之上
+//!
+//! 支持 LINEAR11, LINEAR16 (指数来自 VOUT_MODE), DIRECT 和 IEEE754 半精度格式,
+//! STATUS_WORD 和各个子状态寄存器都解码成标志位
+
+use std::collections::HashMap;
+use std::fmt;
+
+use embedded_hal::i2c::I2c;
+
+use super::smbus::{self, BlockRead, SmBus};
+
+/// 标准命令码
+pub mod command {
+    pub const PAGE: u8 = 0x00;
+    pub const OPERATION: u8 = 0x01;
+    pub const ON_OFF_CONFIG: u8 = 0x02;
+    pub const CLEAR_FAULTS: u8 = 0x03;
+    pub const VOUT_MODE: u8 = 0x20;
+    pub const VOUT_COMMAND: u8 = 0x21;
+    pub const COEFFICIENTS: u8 = 0x30;
+    pub const STATUS_BYTE: u8 = 0x78;
+    pub const STATUS_WORD: u8 = 0x79;
+    pub const STATUS_VOUT: u8 = 0x7A;
+    pub const STATUS_IOUT: u8 = 0x7B;
+    pub const STATUS_INPUT: u8 = 0x7C;
+    pub const STATUS_TEMPERATURE: u8 = 0x7D;
+    pub const STATUS_CML: u8 = 0x7E;
+    pub const STATUS_OTHER: u8 = 0x7F;
+    pub const STATUS_MFR_SPECIFIC: u8 = 0x80;
+    pub const STATUS_FANS_1_2: u8 = 0x81;
+    pub const READ_VIN: u8 = 0x88;
+    pub const READ_IIN: u8 = 0x89;
+    pub const READ_VOUT: u8 = 0x8B;
+    pub const READ_IOUT: u8 = 0x8C;
+    pub const READ_TEMPERATURE_1: u8 = 0x8D;
+    pub const READ_TEMPERATURE_2: u8 = 0x8E;
+    pub const READ_POUT: u8 = 0x96;
+    pub const READ_PIN: u8 = 0x97;
+    pub const PMBUS_REVISION: u8 = 0x98;
+    pub const MFR_ID: u8 = 0x99;
+    pub const MFR_MODEL: u8 = 0x9A;
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    SmBus(smbus::Error<E>),
+    /// VID 模式需要芯片手册里的 VID 表, 这里没法换算
+    UnsupportedVoutMode(VoutMode),
+}
+
+impl<E> From<smbus::Error<E>> for Error<E> {
+    fn from(value: smbus::Error<E>) -> Self {
+        Self::SmBus(value)
+    }
+}
+
+/// LINEAR11: 高 5 位有符号指数, 低 11 位有符号尾数
+pub fn from_linear11(raw: u16) -> f32 {
+    let exponent = (raw as i16) >> 11;
+    let mantissa = ((raw << 5) as i16) >> 5;
+    f32::from(mantissa) * 2f32.powi(i32::from(exponent))
+}
+
+/// 选尾数能放下的最小指数, 精度最高
+pub fn to_linear11(value: f32) -> u16 {
+    let encode = |exponent: i32, mantissa: f32| {
+        (((exponent as u16) & 0x1F) << 11) | ((mantissa as i16 as u16) & 0x7FF)
+    };
+    for exponent in -16..=15 {
+        let mantissa = (value / 2f32.powi(exponent)).round();
+        if (-1024.0..=1023.0).contains(&mantissa) {
+            return encode(exponent, mantissa);
+        }
+    }
+    encode(15, (value / 2f32.powi(15)).clamp(-1024.0, 1023.0))
+}
+
+/// LINEAR16: 16 位无符号尾数, 指数在 VOUT_MODE 里
+pub fn from_linear16(raw: u16, exponent: i8) -> f32 {
+    f32::from(raw) * 2f32.powi(i32::from(exponent))
+}
+
+pub fn to_linear16(value: f32, exponent: i8) -> u16 {
+    (value / 2f32.powi(i32::from(exponent)))
+        .round()
+        .clamp(0.0, 65535.0) as u16
+}
+
+/// IEEE754 半精度
+pub fn from_half(raw: u16) -> f32 {
+    let sign = if raw & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = i32::from((raw >> 10) & 0x1F);
+    let fraction = f32::from(raw & 0x3FF);
+    match exponent {
+        0 => sign * fraction * 2f32.powi(-24),
+        0x1F if fraction == 0.0 => sign * f32::INFINITY,
+        0x1F => f32::NAN,
+        _ => sign * (1.0 + fraction / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// DIRECT 格式的系数, X = (Y * 10^-R - b) / m
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Coefficients {
+    pub m: i16,
+    pub b: i16,
+    pub r: i8,
+}
+
+impl Coefficients {
+    pub fn decode(&self, raw: u16) -> f32 {
+        let y = f32::from(raw as i16);
+        (y * 10f32.powi(-i32::from(self.r)) - f32::from(self.b)) / f32::from(self.m)
+    }
+
+    /// Y = (m * X + b) * 10^R
+    pub fn encode(&self, value: f32) -> u16 {
+        let y = (f32::from(self.m) * value + f32::from(self.b)) * 10f32.powi(i32::from(self.r));
+        y.round().clamp(-32768.0, 32767.0) as i16 as u16
+    }
+}
+
+/// VOUT_MODE 的 bit6:5 是数据格式, 低 5 位是参数
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoutFormat {
+    /// LINEAR16, 带 5 位有符号指数
+    Linear(i8),
+    /// VID, 带 VID 码表编号
+    Vid(u8),
+    Direct,
+    /// PMBus 1.3 的半精度浮点
+    Ieee754Half,
+}
+
+/// VOUT_MODE (0x20)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VoutMode {
+    pub format: VoutFormat,
+    /// PMBus 1.3 的 bit7, 输出电压相关的命令是相对 VOUT_COMMAND 的值
+    pub relative: bool,
+}
+
+impl From<u8> for VoutMode {
+    fn from(value: u8) -> Self {
+        let parameter = value & 0x1F;
+        let format = match (value >> 5) & 0b11 {
+            0b00 => VoutFormat::Linear(((parameter << 3) as i8) >> 3),
+            0b01 => VoutFormat::Vid(parameter),
+            0b10 => VoutFormat::Direct,
+            _ => VoutFormat::Ieee754Half,
+        };
+        Self {
+            format,
+            relative: value & 0x80 != 0,
+        }
+    }
+}
+
+macro_rules! status_register {
+    ($(#[$meta: meta])* $name: ident: $ty: ty { $($flag: ident = $bit: expr),* $(,)? }) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+        pub struct $name(pub $ty);
+
+        impl $name {
+            $(pub const $flag: $ty = 1 << $bit;)*
+
+            pub fn contains(&self, flag: $ty) -> bool {
+                self.0 & flag == flag
+            }
+
+            /// 置位的标志名
+            pub fn flags(&self) -> Vec<&'static str> {
+                let mut flags = Vec::new();
+                $(
+                    if self.contains(Self::$flag) {
+                        flags.push(stringify!($flag));
+                    }
+                )*
+                flags
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                let flags = self.flags();
+                if flags.is_empty() {
+                    f.write_str("-")
+                } else {
+                    f.write_str(&flags.join(" | "))
+                }
+            }
+        }
+    };
+}
+
+status_register! {
+    /// STATUS_WORD (0x79), 低字节就是 STATUS_BYTE
+    StatusWord: u16 {
+        VOUT = 15,
+        IOUT_POUT = 14,
+        INPUT = 13,
+        MFR_SPECIFIC = 12,
+        POWER_GOOD_N = 11,
+        FANS = 10,
+        OTHER = 9,
+        UNKNOWN = 8,
+        BUSY = 7,
+        OFF = 6,
+        VOUT_OV_FAULT = 5,
+        IOUT_OC_FAULT = 4,
+        VIN_UV_FAULT = 3,
+        TEMPERATURE = 2,
+        CML = 1,
+        NONE_OF_THE_ABOVE = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_VOUT (0x7A)
+    StatusVout: u8 {
+        VOUT_OV_FAULT = 7,
+        VOUT_OV_WARNING = 6,
+        VOUT_UV_WARNING = 5,
+        VOUT_UV_FAULT = 4,
+        VOUT_MAX_MIN_WARNING = 3,
+        TON_MAX_FAULT = 2,
+        TOFF_MAX_WARNING = 1,
+        VOUT_TRACKING_ERROR = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_IOUT (0x7B)
+    StatusIout: u8 {
+        IOUT_OC_FAULT = 7,
+        IOUT_OC_LV_FAULT = 6,
+        IOUT_OC_WARNING = 5,
+        IOUT_UC_FAULT = 4,
+        CURRENT_SHARE_FAULT = 3,
+        POWER_LIMITING = 2,
+        POUT_OP_FAULT = 1,
+        POUT_OP_WARNING = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_INPUT (0x7C)
+    StatusInput: u8 {
+        VIN_OV_FAULT = 7,
+        VIN_OV_WARNING = 6,
+        VIN_UV_WARNING = 5,
+        VIN_UV_FAULT = 4,
+        UNIT_OFF_LOW_VIN = 3,
+        IIN_OC_FAULT = 2,
+        IIN_OC_WARNING = 1,
+        PIN_OP_WARNING = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_TEMPERATURE (0x7D)
+    StatusTemperature: u8 {
+        OT_FAULT = 7,
+        OT_WARNING = 6,
+        UT_WARNING = 5,
+        UT_FAULT = 4,
+    }
+}
+
+status_register! {
+    /// STATUS_CML (0x7E), 通信/存储/逻辑错误
+    StatusCml: u8 {
+        INVALID_COMMAND = 7,
+        INVALID_DATA = 6,
+        PEC_FAILED = 5,
+        MEMORY_FAULT = 4,
+        PROCESSOR_FAULT = 3,
+        OTHER_COMMUNICATION_FAULT = 1,
+        OTHER_MEMORY_OR_LOGIC_FAULT = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_OTHER (0x7F)
+    StatusOther: u8 {
+        INPUT_A_FUSE_FAULT = 5,
+        INPUT_B_FUSE_FAULT = 4,
+        INPUT_A_OR_FET_FAULT = 3,
+        INPUT_B_OR_FET_FAULT = 2,
+        OUTPUT_OR_FET_FAULT = 1,
+        FIRST_TO_ASSERT_SMBALERT = 0,
+    }
+}
+
+status_register! {
+    /// STATUS_FANS_1_2 (0x81)
+    StatusFans: u8 {
+        FAN1_FAULT = 7,
+        FAN2_FAULT = 6,
+        FAN1_WARNING = 5,
+        FAN2_WARNING = 4,
+        FAN1_SPEED_OVERRIDDEN = 3,
+        FAN2_SPEED_OVERRIDDEN = 2,
+        AIRFLOW_FAULT = 1,
+        AIRFLOW_WARNING = 0,
+    }
+}
+
+/// STATUS_WORD 加上它指出的子状态寄存器, 没有置位的子寄存器不读
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Status {
+    pub word: StatusWord,
+    pub vout: Option<StatusVout>,
+    pub iout: Option<StatusIout>,
+    pub input: Option<StatusInput>,
+    pub temperature: Option<StatusTemperature>,
+    pub cml: Option<StatusCml>,
+    pub other: Option<StatusOther>,
+    /// 厂商自定义, 不解码
+    pub mfr_specific: Option<u8>,
+    pub fans: Option<StatusFans>,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "STATUS_WORD        {:#06x}: {}", self.word.0, self.word)?;
+        if let Some(s) = self.vout {
+            writeln!(f, "STATUS_VOUT        {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.iout {
+            writeln!(f, "STATUS_IOUT        {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.input {
+            writeln!(f, "STATUS_INPUT       {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.temperature {
+            writeln!(f, "STATUS_TEMPERATURE {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.cml {
+            writeln!(f, "STATUS_CML         {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.other {
+            writeln!(f, "STATUS_OTHER       {:#04x}: {}", s.0, s)?;
+        }
+        if let Some(s) = self.mfr_specific {
+            writeln!(f, "STATUS_MFR         {:#04x}", s)?;
+        }
+        if let Some(s) = self.fans {
+            writeln!(f, "STATUS_FANS_1_2    {:#04x}: {}", s.0, s)?;
+        }
+        Ok(())
+    }
+}
+
+/// 标准遥测值, 单位 V / A / °C / W
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Telemetry {
+    pub vin: f32,
+    pub vout: f32,
+    pub iout: f32,
+    pub temperature: f32,
+    pub pout: f32,
+}
+
+impl fmt::Display for Telemetry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "VIN   {:>10.3} V", self.vin)?;
+        writeln!(f, "VOUT  {:>10.3} V", self.vout)?;
+        writeln!(f, "IOUT  {:>10.3} A", self.iout)?;
+        writeln!(f, "TEMP  {:>10.3} °C", self.temperature)?;
+        writeln!(f, "POUT  {:>10.3} W", self.pout)
+    }
+}
+
+pub struct PmBus<I2C> {
+    smbus: SmBus<I2C>,
+    address: u8,
+    /// 用 DIRECT 格式的命令和它们的系数, 其余按 LINEAR11
+    coefficients: HashMap<u8, Coefficients>,
+}
+
+impl<I2C: I2c> PmBus<I2C> {
+    pub fn new(smbus: SmBus<I2C>, address: u8) -> Self {
+        Self {
+            smbus,
+            address,
+            coefficients: HashMap::new(),
+        }
+    }
+
+    pub fn release(self) -> SmBus<I2C> {
+        self.smbus
+    }
+
+    pub fn smbus(&mut self) -> &mut SmBus<I2C> {
+        &mut self.smbus
+    }
+
+    /// 指定某个命令用 DIRECT 格式, 系数一般在芯片手册里
+    pub fn set_coefficients(&mut self, command: u8, coefficients: Coefficients) {
+        self.coefficients.insert(command, coefficients);
+    }
+
+    /// 多路输出的芯片用 PAGE 选择后面的命令作用在哪一路, 0xFF 表示所有路
+    pub fn set_page(&mut self, page: u8) -> Result<(), Error<I2C::Error>> {
+        Ok(self.smbus.write_byte(self.address, command::PAGE, page)?)
+    }
+
+    pub fn page(&mut self) -> Result<u8, Error<I2C::Error>> {
+        Ok(self.smbus.read_byte(self.address, command::PAGE)?)
+    }
+
+    pub fn clear_faults(&mut self) -> Result<(), Error<I2C::Error>> {
+        Ok(self.smbus.send_byte(self.address, command::CLEAR_FAULTS)?)
+    }
+
+    pub fn vout_mode(&mut self) -> Result<VoutMode, Error<I2C::Error>> {
+        Ok(VoutMode::from(
+            self.smbus.read_byte(self.address, command::VOUT_MODE)?,
+        ))
+    }
+
+    /// 按 LINEAR11 或者设置过的 DIRECT 系数读一个值
+    pub fn read_value(&mut self, command: u8) -> Result<f32, Error<I2C::Error>> {
+        let raw = self.smbus.read_word(self.address, command)?;
+        Ok(match self.coefficients.get(&command) {
+            Some(coefficients) => coefficients.decode(raw),
+            None => from_linear11(raw),
+        })
+    }
+
+    /// 输出电压相关的命令按 VOUT_MODE 读
+    pub fn read_vout_value(&mut self, command: u8) -> Result<f32, Error<I2C::Error>> {
+        let mode = self.vout_mode()?;
+        let raw = self.smbus.read_word(self.address, command)?;
+        match mode.format {
+            VoutFormat::Linear(exponent) => Ok(from_linear16(raw, exponent)),
+            VoutFormat::Ieee754Half => Ok(from_half(raw)),
+            VoutFormat::Direct => match self.coefficients.get(&command) {
+                Some(coefficients) => Ok(coefficients.decode(raw)),
+                None => Err(Error::UnsupportedVoutMode(mode)),
+            },
+            VoutFormat::Vid(_) => Err(Error::UnsupportedVoutMode(mode)),
+        }
+    }
+
+    /// 设置输出电压 (VOUT_COMMAND), 只支持 LINEAR16 和 DIRECT
+    pub fn set_vout(&mut self, volts: f32) -> Result<(), Error<I2C::Error>> {
+        let mode = self.vout_mode()?;
+        let raw = match mode.format {
+            VoutFormat::Linear(exponent) => to_linear16(volts, exponent),
+            VoutFormat::Direct if self.coefficients.contains_key(&command::VOUT_COMMAND) => {
+                self.coefficients[&command::VOUT_COMMAND].encode(volts)
+            }
+            _ => return Err(Error::UnsupportedVoutMode(mode)),
+        };
+        Ok(self
+            .smbus
+            .write_word(self.address, command::VOUT_COMMAND, raw)?)
+    }
+
+    pub fn read_vin(&mut self) -> Result<f32, Error<I2C::Error>> {
+        self.read_value(command::READ_VIN)
+    }
+
+    pub fn read_vout(&mut self) -> Result<f32, Error<I2C::Error>> {
+        self.read_vout_value(command::READ_VOUT)
+    }
+
+    pub fn read_iout(&mut self) -> Result<f32, Error<I2C::Error>> {
+        self.read_value(command::READ_IOUT)
+    }
+
+    pub fn read_temperature_1(&mut self) -> Result<f32, Error<I2C::Error>> {
+        self.read_value(command::READ_TEMPERATURE_1)
+    }
+
+    pub fn read_pout(&mut self) -> Result<f32, Error<I2C::Error>> {
+        self.read_value(command::READ_POUT)
+    }
+
+    /// 当前页的所有标准遥测
+    pub fn telemetry(&mut self) -> Result<Telemetry, Error<I2C::Error>> {
+        Ok(Telemetry {
+            vin: self.read_vin()?,
+            vout: self.read_vout()?,
+            iout: self.read_iout()?,
+            temperature: self.read_temperature_1()?,
+            pout: self.read_pout()?,
+        })
+    }
+
+    pub fn status_word(&mut self) -> Result<StatusWord, Error<I2C::Error>> {
+        Ok(StatusWord(
+            self.smbus.read_word(self.address, command::STATUS_WORD)?,
+        ))
+    }
+
+    /// 读 STATUS_WORD, 再读它指出的子状态寄存器
+    pub fn status(&mut self) -> Result<Status, Error<I2C::Error>> {
+        let word = self.status_word()?;
+        let mut status = Status {
+            word,
+            ..Default::default()
+        };
+
+        let address = self.address;
+        let mut read = |flag: u16, command: u8| -> Result<Option<u8>, Error<I2C::Error>> {
+            if word.contains(flag) {
+                Ok(Some(self.smbus.read_byte(address, command)?))
+            } else {
+                Ok(None)
+            }
+        };
+        status.vout = read(StatusWord::VOUT, command::STATUS_VOUT)?.map(StatusVout);
+        status.iout = read(StatusWord::IOUT_POUT, command::STATUS_IOUT)?.map(StatusIout);
+        status.input = read(StatusWord::INPUT, command::STATUS_INPUT)?.map(StatusInput);
+        status.temperature =
+            read(StatusWord::TEMPERATURE, command::STATUS_TEMPERATURE)?.map(StatusTemperature);
+        status.cml = read(StatusWord::CML, command::STATUS_CML)?.map(StatusCml);
+        status.other = read(StatusWord::OTHER, command::STATUS_OTHER)?.map(StatusOther);
+        status.mfr_specific = read(StatusWord::MFR_SPECIFIC, command::STATUS_MFR_SPECIFIC)?;
+        status.fans = read(StatusWord::FANS, command::STATUS_FANS_1_2)?.map(StatusFans);
+        Ok(status)
+    }
+}
+
+/// 查询系数要用块读
+impl<I2C: BlockRead> PmBus<I2C> {
+    /// 用 COEFFICIENTS (0x30) 命令向芯片查询系数并记下来, 不是所有芯片都支持
+    pub fn query_coefficients(
+        &mut self,
+        command: u8,
+        read: bool,
+    ) -> Result<Coefficients, Error<I2C::Error>> {
+        let mut buf = [0; 5];
+        self.smbus.block_process_call(
+            self.address,
+            command::COEFFICIENTS,
+            &[command, u8::from(read)],
+            &mut buf,
+        )?;
+        let coefficients = Coefficients {
+            m: i16::from_le_bytes([buf[0], buf[1]]),
+            b: i16::from_le_bytes([buf[2], buf[3]]),
+            r: buf[4] as i8,
+        };
+        self.coefficients.insert(command, coefficients);
+        Ok(coefficients)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn vout_mode_formats() {
+        let cases = [
+            (0x17, VoutFormat::Linear(-9), false),
+            (0x97, VoutFormat::Linear(-9), true),
+            (0x0F, VoutFormat::Linear(15), false),
+            (0x10, VoutFormat::Linear(-16), false),
+            (0x21, VoutFormat::Vid(1), false),
+            (0x40, VoutFormat::Direct, false),
+            (0xC0, VoutFormat::Direct, true),
+            (0x60, VoutFormat::Ieee754Half, false),
+            (0xE0, VoutFormat::Ieee754Half, true),
+        ];
+        for (raw, format, relative) in cases {
+            assert_eq!(
+                VoutMode::from(raw),
+                VoutMode { format, relative },
+                "{raw:#04x}"
+            );
+        }
+    }
+
+    #[test]
+    fn linear11_decode() {
+        // 指数 -2, 尾数 5
+        assert_eq!(from_linear11(0xF005), 1.25);
+        // 指数 0, 尾数 -1
+        assert_eq!(from_linear11(0x07FF), -1.0);
+        // 指数 1, 尾数 -1024
+        assert_eq!(from_linear11(0x0C00), -2048.0);
+    }
+
+    #[test]
+    fn linear11_round_trip() {
+        for value in [0.0, 1.0, -1.0, 0.125, 12.0, 3.3, -7.5, 1000.0, 65000.0] {
+            let decoded = from_linear11(to_linear11(value));
+            // 11 位尾数, 相对误差小于 1/1024
+            assert!(
+                (decoded - value).abs() <= value.abs() / 1000.0 + 1e-6,
+                "{value} -> {decoded}"
+            );
+        }
+    }
+
+    #[test]
+    fn linear16_with_negative_exponent() {
+        let VoutFormat::Linear(exponent) = VoutMode::from(0x17).format else {
+            panic!("not linear");
+        };
+        assert_eq!(from_linear16(0x0A00, exponent), 5.0);
+        assert_eq!(to_linear16(3.3, exponent), 1690);
+        assert_eq!(to_linear16(-1.0, exponent), 0);
+    }
+
+    #[test]
+    fn direct_coefficients() {
+        // Y = (m * X + b) * 10^R
+        let c = Coefficients {
+            m: 200,
+            b: 100,
+            r: -2,
+        };
+        assert_eq!(c.encode(12.0), 25);
+        assert_eq!(c.decode(25), 12.0);
+
+        let c = Coefficients { m: 1, b: 0, r: 2 };
+        assert_eq!(c.encode(3.5), 350);
+        assert_eq!(c.decode(350), 3.5);
+
+        let c = Coefficients { m: -5, b: 0, r: 0 };
+        assert_eq!(c.encode(4.0), (-20i16) as u16);
+        assert_eq!(c.decode((-20i16) as u16), 4.0);
+    }
+}
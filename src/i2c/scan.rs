@@ -0,0 +1,283 @@
+//! 扫描总线, 和 `i2cdetect` 差不多
+
+use std::fmt;
+
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+
+use super::stream::{self, Stream};
+use super::{I2cbus, Instance};
+
+/// 探测方式, 和 `i2cdetect` 的选项对应
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Probe {
+    /// 和 `i2cdetect` 默认一样, 0x30 ~ 0x37 和 0x50 ~ 0x5F 用读, 其他用写
+    #[default]
+    Auto,
+    /// 只发地址 + 写然后 Stop (SMBus Quick Write), 对应 `i2cdetect -q`.
+    /// 有些写保护芯片会被这样误写
+    Write,
+    /// 读一个字节, 对应 `i2cdetect -r`. 有些只写的芯片会被读卡住
+    Read,
+}
+
+impl Probe {
+    fn is_read(&self, address: u8) -> bool {
+        match self {
+            Probe::Auto => matches!(address, 0x30..=0x37 | 0x50..=0x5F),
+            Probe::Write => false,
+            Probe::Read => true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct ScanConfig {
+    pub probe: Probe,
+    /// 默认跳过 0x00 ~ 0x07 和 0x78 ~ 0x7F 这些保留地址
+    pub include_reserved: bool,
+    /// 尝试识别常见芯片, 会多发几次读写
+    pub identify: bool,
+}
+
+/// 能认出来的芯片
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Part {
+    /// 24Cxx 系列, 0x50 ~ 0x57. 没有 ID 寄存器, 只会作为 [`guess`] 的结果
+    Eeprom,
+    /// WHO_AM_I (0x75) 读出 0x68
+    Mpu6050,
+    /// 没有可靠的探测办法, 只会作为 [`guess`] 的结果
+    Ssd1306,
+}
+
+impl fmt::Display for Part {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Part::Eeprom => "24Cxx EEPROM",
+            Part::Mpu6050 => "MPU6050",
+            Part::Ssd1306 => "SSD1306",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Found {
+    pub address: u8,
+    /// 探测寄存器确认过的型号
+    pub part: Option<Part>,
+    /// 认不出来时只凭地址猜的型号
+    pub guess: Option<Part>,
+}
+
+/// 只凭地址猜型号, 不发任何命令
+pub fn guess(address: u8) -> Option<Part> {
+    match address {
+        0x3C | 0x3D => Some(Part::Ssd1306),
+        0x50..=0x57 => Some(Part::Eeprom),
+        0x68 | 0x69 => Some(Part::Mpu6050),
+        _ => None,
+    }
+}
+
+/// 能靠读寄存器确认的型号: 寄存器, 期望读到的值, 型号
+fn probe(address: u8) -> Option<(u8, u8, Part)> {
+    match address {
+        0x68 | 0x69 => Some((0x75, 0x68, Part::Mpu6050)),
+        _ => None,
+    }
+}
+
+/// 扫描结果, `Display` 输出和 `i2cdetect` 一样的表格
+#[derive(Debug, Clone)]
+pub struct ScanReport {
+    pub first: u8,
+    pub last: u8,
+    pub devices: Vec<Found>,
+}
+
+impl ScanReport {
+    pub fn addresses(&self) -> Vec<u8> {
+        self.devices.iter().map(|d| d.address).collect()
+    }
+}
+
+impl fmt::Display for ScanReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "    ")?;
+        for col in 0..16 {
+            write!(f, "  {:x}", col)?;
+        }
+        writeln!(f)?;
+
+        for row in (0..0x80u8).step_by(16) {
+            write!(f, "{:02x}:", row)?;
+            for address in row..row + 16 {
+                if address < self.first || address > self.last {
+                    write!(f, "   ")?;
+                } else if self.devices.iter().any(|d| d.address == address) {
+                    write!(f, " {:02x}", address)?;
+                } else {
+                    write!(f, " --")?;
+                }
+            }
+            writeln!(f)?;
+        }
+
+        for device in self.devices.iter() {
+            if let Some(part) = device.part {
+                writeln!(f, "{:#04x}: {}", device.address, part)?;
+            } else if let Some(part) = device.guess {
+                writeln!(f, "{:#04x}: {}? (by address)", device.address, part)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'d, T: Instance> I2cbus<'d, T> {
+    /// 返回有应答的 7 位地址
+    ///
+    /// 所有地址的探测编在同一个流里, 一两个 USB 包就扫完了.
+    /// USB 出错时返回错误, 不会当成总线上没有设备
+    pub fn scan(&mut self, config: &ScanConfig) -> Result<Vec<u8>, ErrorKind> {
+        let (first, last) = if config.include_reserved {
+            (0x00, 0x7F)
+        } else {
+            (0x08, 0x77)
+        };
+
+        let mut stream = Stream::new();
+        // 每个地址在回复里的位置
+        let mut index = Vec::new();
+        let mut replies = 0;
+        for address in first..=last {
+            let is_read = config.probe.is_read(address);
+            stream.start();
+            stream.write(
+                &[(address << 1) | u8::from(is_read)],
+                NoAcknowledgeSource::Address,
+            );
+            index.push((address, replies));
+            replies += 1;
+            if is_read {
+                stream.read(1, true);
+                replies += 1;
+            }
+            stream.stop();
+        }
+
+        let replies = stream.execute_raw()?;
+        Ok(index
+            .into_iter()
+            .filter(|&(_, i)| replies.get(i).is_some_and(|&r| stream::is_ack(r)))
+            .map(|(address, _)| address)
+            .collect())
+    }
+
+    /// 扫描并尝试识别芯片
+    pub fn detect(&mut self, config: &ScanConfig) -> Result<ScanReport, ErrorKind> {
+        let (first, last) = if config.include_reserved {
+            (0x00, 0x7F)
+        } else {
+            (0x08, 0x77)
+        };
+        let devices = self
+            .scan(config)?
+            .into_iter()
+            .map(|address| {
+                let part = if config.identify {
+                    self.identify(address)
+                } else {
+                    None
+                };
+                Found {
+                    address,
+                    part,
+                    guess: if config.identify && part.is_none() {
+                        guess(address)
+                    } else {
+                        None
+                    },
+                }
+            })
+            .collect();
+
+        Ok(ScanReport {
+            first,
+            last,
+            devices,
+        })
+    }
+
+    /// 读几个简单的寄存器确认芯片型号, 确认不了返回 `None`, 只凭地址猜的见 [`guess`]
+    pub fn identify(&mut self, address: u8) -> Option<Part> {
+        let (register, expected, part) = probe(address)?;
+        let mut value = [0; 1];
+        T::transaction(
+            address,
+            &mut [Operation::Write(&[register]), Operation::Read(&mut value)],
+        )
+        .ok()
+        .filter(|_| value[0] == expected)
+        .map(|_| part)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guess_table() {
+        let cases = [
+            (0x3B, None),
+            (0x3C, Some(Part::Ssd1306)),
+            (0x3D, Some(Part::Ssd1306)),
+            (0x4F, None),
+            (0x50, Some(Part::Eeprom)),
+            (0x57, Some(Part::Eeprom)),
+            (0x58, None),
+            (0x68, Some(Part::Mpu6050)),
+            (0x69, Some(Part::Mpu6050)),
+            (0x6A, None),
+        ];
+        for (address, part) in cases {
+            assert_eq!(guess(address), part, "{address:#04x}");
+        }
+    }
+
+    #[test]
+    fn only_registers_confirm() {
+        // EEPROM 和 SSD1306 只有地址可看, 不能算确认
+        for address in [0x3C, 0x3D, 0x50, 0x53, 0x57] {
+            assert_eq!(probe(address), None, "{address:#04x}");
+        }
+        assert_eq!(probe(0x68), Some((0x75, 0x68, Part::Mpu6050)));
+        assert_eq!(probe(0x69), Some((0x75, 0x68, Part::Mpu6050)));
+    }
+
+    #[test]
+    fn report_marks_guesses() {
+        let report = ScanReport {
+            first: 0x08,
+            last: 0x77,
+            devices: vec![
+                Found {
+                    address: 0x50,
+                    part: None,
+                    guess: Some(Part::Eeprom),
+                },
+                Found {
+                    address: 0x68,
+                    part: Some(Part::Mpu6050),
+                    guess: None,
+                },
+            ],
+        };
+        let text = report.to_string();
+        assert!(text.contains("50: 50 --"));
+        assert!(text.contains("0x50: 24Cxx EEPROM? (by address)"));
+        assert!(text.contains("0x68: MPU6050\n"));
+    }
+}
@@ -0,0 +1,342 @@
+//! SMBus 协议层
+//!
+//! 在任意 `embedded_hal::i2c::I2c` 上实现 SMBus 2.0/3.x 的各种传输,
+//! 开启 PEC 后写的时候追加 CRC-8, 读的时候校验
+
+use embedded_hal::i2c::{Error as _, ErrorKind, I2c};
+
+use super::{I2cbus, Instance};
+
+/// SMBus 块传输最多 32 字节 (SMBus 3.x 之前)
+pub const BLOCK_MAX: usize = 32;
+/// Alert Response Address
+pub const ALERT_RESPONSE_ADDRESS: u8 = 0x0C;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// PEC 校验失败
+    Pec {
+        expected: u8,
+        found: u8,
+    },
+    /// 从机返回的块长度不合法 (0 或大于 32, 或者放不进缓冲区)
+    BlockLength(usize),
+    /// 从机返回的块比要求的短
+    ShortBlock {
+        expected: usize,
+        found: usize,
+    },
+}
+
+/// CRC-8, 多项式 x^8 + x^2 + x + 1, 初值 0
+pub fn pec(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// 读之前不知道块有多长, 要先读长度字节再在同一次传输里接着读,
+/// 最后一个字节回 NACK. `embedded_hal::i2c::I2c` 做不到这一点, 需要总线自己支持
+pub trait BlockRead: I2c {
+    /// `written` 不为空时先写它, 然后 Repeated Start 读长度字节,
+    /// 再读 min(长度, `max`) + `extra` 个字节. 返回长度字节和后面的字节
+    fn counted_read(
+        &mut self,
+        address: u8,
+        written: &[u8],
+        max: usize,
+        extra: usize,
+    ) -> Result<Vec<u8>, Self::Error>;
+}
+
+impl<T: Instance> BlockRead for I2cbus<'_, T> {
+    fn counted_read(
+        &mut self,
+        address: u8,
+        written: &[u8],
+        max: usize,
+        extra: usize,
+    ) -> Result<Vec<u8>, Self::Error> {
+        self.checked(T::counted_read(address, written, max, extra))
+    }
+}
+
+impl<B: BlockRead + ?Sized> BlockRead for &mut B {
+    fn counted_read(
+        &mut self,
+        address: u8,
+        written: &[u8],
+        max: usize,
+        extra: usize,
+    ) -> Result<Vec<u8>, Self::Error> {
+        B::counted_read(self, address, written, max, extra)
+    }
+}
+
+pub struct SmBus<I2C> {
+    i2c: I2C,
+    pec: bool,
+}
+
+impl<I2C: I2c> SmBus<I2C> {
+    pub fn new(i2c: I2C) -> Self {
+        Self { i2c, pec: false }
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    /// 开关 PEC, 对之后所有传输生效
+    pub fn set_pec(&mut self, enable: bool) {
+        self.pec = enable;
+    }
+
+    pub fn pec_enabled(&self) -> bool {
+        self.pec
+    }
+
+    /// 写传输, 需要的话在末尾加 PEC
+    fn write(&mut self, address: u8, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        let mut obuf = data.to_vec();
+        if self.pec {
+            let mut covered = vec![address << 1];
+            covered.extend_from_slice(data);
+            obuf.push(pec(&covered));
+        }
+        self.i2c.write(address, &obuf).map_err(Error::I2c)
+    }
+
+    /// 写 `data` 之后 Repeated Start 读 `len` 字节, `data` 为空时直接读
+    fn write_read(
+        &mut self,
+        address: u8,
+        data: &[u8],
+        len: usize,
+    ) -> Result<Vec<u8>, Error<I2C::Error>> {
+        let extra = usize::from(self.pec);
+        let mut ibuf = vec![0; len + extra];
+        if data.is_empty() {
+            self.i2c.read(address, &mut ibuf).map_err(Error::I2c)?;
+        } else {
+            self.i2c
+                .write_read(address, data, &mut ibuf)
+                .map_err(Error::I2c)?;
+        }
+
+        if self.pec {
+            self.check_pec(address, data, &ibuf[..len], ibuf[len])?;
+        }
+        ibuf.truncate(len);
+        Ok(ibuf)
+    }
+
+    /// PEC 覆盖写地址, 写的数据, 读地址和读到的数据
+    fn check_pec(
+        &self,
+        address: u8,
+        written: &[u8],
+        read: &[u8],
+        found: u8,
+    ) -> Result<(), Error<I2C::Error>> {
+        let mut covered = Vec::new();
+        if !written.is_empty() {
+            covered.push(address << 1);
+            covered.extend_from_slice(written);
+        }
+        covered.push((address << 1) | 0x01);
+        covered.extend_from_slice(read);
+        let expected = pec(&covered);
+        if expected != found {
+            return Err(Error::Pec { expected, found });
+        }
+        Ok(())
+    }
+
+    /// Quick Command, 数据就是地址里的 R/W 位
+    pub fn quick_command(&mut self, address: u8, read: bool) -> Result<(), Error<I2C::Error>> {
+        if read {
+            self.i2c.read(address, &mut []).map_err(Error::I2c)
+        } else {
+            self.i2c.write(address, &[]).map_err(Error::I2c)
+        }
+    }
+
+    pub fn send_byte(&mut self, address: u8, byte: u8) -> Result<(), Error<I2C::Error>> {
+        self.write(address, &[byte])
+    }
+
+    pub fn receive_byte(&mut self, address: u8) -> Result<u8, Error<I2C::Error>> {
+        Ok(self.write_read(address, &[], 1)?[0])
+    }
+
+    pub fn write_byte(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u8,
+    ) -> Result<(), Error<I2C::Error>> {
+        self.write(address, &[command, value])
+    }
+
+    /// 字都是小端
+    pub fn write_word(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u16,
+    ) -> Result<(), Error<I2C::Error>> {
+        let [lo, hi] = value.to_le_bytes();
+        self.write(address, &[command, lo, hi])
+    }
+
+    pub fn read_byte(&mut self, address: u8, command: u8) -> Result<u8, Error<I2C::Error>> {
+        Ok(self.write_read(address, &[command], 1)?[0])
+    }
+
+    pub fn read_word(&mut self, address: u8, command: u8) -> Result<u16, Error<I2C::Error>> {
+        let rev = self.write_read(address, &[command], 2)?;
+        Ok(u16::from_le_bytes([rev[0], rev[1]]))
+    }
+
+    pub fn process_call(
+        &mut self,
+        address: u8,
+        command: u8,
+        value: u16,
+    ) -> Result<u16, Error<I2C::Error>> {
+        let [lo, hi] = value.to_le_bytes();
+        let rev = self.write_read(address, &[command, lo, hi], 2)?;
+        Ok(u16::from_le_bytes([rev[0], rev[1]]))
+    }
+
+    pub fn block_write(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        if data.is_empty() || data.len() > BLOCK_MAX {
+            return Err(Error::BlockLength(data.len()));
+        }
+        let mut obuf = vec![command, data.len() as u8];
+        obuf.extend_from_slice(data);
+        self.write(address, &obuf)
+    }
+
+    /// 读 Alert Response Address, 返回拉 SMBALERT# 的从机地址, 没有从机应答时返回 `None`.
+    /// 多个从机同时报警时地址最小的那个赢得仲裁, 需要反复调用直到返回 `None`
+    pub fn alert_response(&mut self) -> Result<Option<u8>, Error<I2C::Error>> {
+        match self.write_read(ALERT_RESPONSE_ADDRESS, &[], 1) {
+            Ok(rev) => Ok(Some(rev[0] >> 1)),
+            Err(Error::I2c(e)) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// 块读需要总线支持 [`BlockRead`]
+impl<I2C: BlockRead> SmBus<I2C> {
+    /// 返回实际读到的字节数
+    pub fn block_read(
+        &mut self,
+        address: u8,
+        command: u8,
+        buf: &mut [u8],
+    ) -> Result<usize, Error<I2C::Error>> {
+        self.block_transfer(address, &[command], buf)
+    }
+
+    /// 要求从机正好返回 `buf.len()` 个字节
+    pub fn block_read_exact(
+        &mut self,
+        address: u8,
+        command: u8,
+        buf: &mut [u8],
+    ) -> Result<(), Error<I2C::Error>> {
+        let found = self.block_read(address, command, buf)?;
+        if found != buf.len() {
+            return Err(Error::ShortBlock {
+                expected: buf.len(),
+                found,
+            });
+        }
+        Ok(())
+    }
+
+    /// 写一个块再读回一个块, 返回读到的字节数
+    pub fn block_process_call(
+        &mut self,
+        address: u8,
+        command: u8,
+        data: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<I2C::Error>> {
+        if data.is_empty() || data.len() > BLOCK_MAX {
+            return Err(Error::BlockLength(data.len()));
+        }
+        let mut obuf = vec![command, data.len() as u8];
+        obuf.extend_from_slice(data);
+        self.block_transfer(address, &obuf, buf)
+    }
+
+    /// 长度字节之后按长度读, 开启 PEC 时多读一个字节
+    fn block_transfer(
+        &mut self,
+        address: u8,
+        written: &[u8],
+        buf: &mut [u8],
+    ) -> Result<usize, Error<I2C::Error>> {
+        let ibuf = self
+            .i2c
+            .counted_read(address, written, BLOCK_MAX, usize::from(self.pec))
+            .map_err(Error::I2c)?;
+
+        let count = ibuf[0] as usize;
+        if count == 0 || count > BLOCK_MAX || count > buf.len() {
+            return Err(Error::BlockLength(count));
+        }
+        if self.pec {
+            self.check_pec(address, written, &ibuf[..1 + count], ibuf[1 + count])?;
+        }
+        buf[..count].copy_from_slice(&ibuf[1..1 + count]);
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::pec;
+
+    #[test]
+    fn pec_check_value() {
+        // CRC-8/SMBUS 的标准校验值
+        assert_eq!(pec(b"123456789"), 0xF4);
+    }
+
+    #[test]
+    fn pec_short_inputs() {
+        assert_eq!(pec(&[]), 0x00);
+        assert_eq!(pec(&[0x00]), 0x00);
+        assert_eq!(pec(&[0x01]), 0x07);
+        assert_eq!(pec(&[0xFF]), 0xF3);
+    }
+
+    #[test]
+    fn pec_appended_gives_zero() {
+        // 数据后面接上自己的 PEC, 整体 CRC 为 0
+        let mut frame = vec![0x5A << 1, 0x8B, 0x12, 0x34];
+        frame.push(pec(&frame));
+        assert_eq!(pec(&frame), 0);
+    }
+}
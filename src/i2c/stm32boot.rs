@@ -0,0 +1,620 @@
+//! STM32 系统存储器里的 I2C bootloader (AN4221)
+//!
+//! 每个命令先写 `[cmd, !cmd]`, 然后读一个字节的应答: 0x79 ACK, 0x1F NACK.
+//! No-Stretch 命令在操作完成前回 0x76 BUSY, 要一直读到 ACK/NACK.
+//! bootloader 的 7 位地址跟型号有关, 见 AN2606
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::i2c::{Error as _, ErrorKind, I2c};
+
+use crate::ihex::Image;
+use crate::util::first_mismatch;
+
+pub const ACK: u8 = 0x79;
+pub const NACK: u8 = 0x1F;
+pub const BUSY: u8 = 0x76;
+
+/// 读写内存每次最多 256 字节
+pub const CHUNK_SIZE: usize = 256;
+
+const ACK_TIMEOUT: Duration = Duration::from_secs(1);
+/// 整片擦除可能要几十秒
+const ERASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub mod command {
+    pub const GET: u8 = 0x00;
+    pub const GET_VERSION: u8 = 0x01;
+    pub const GET_ID: u8 = 0x02;
+    pub const READ_MEMORY: u8 = 0x11;
+    pub const GO: u8 = 0x21;
+    pub const WRITE_MEMORY: u8 = 0x31;
+    pub const NO_STRETCH_WRITE_MEMORY: u8 = 0x32;
+    pub const ERASE: u8 = 0x43;
+    pub const EXTENDED_ERASE: u8 = 0x44;
+    pub const NO_STRETCH_ERASE: u8 = 0x45;
+    pub const WRITE_PROTECT: u8 = 0x63;
+    pub const NO_STRETCH_WRITE_PROTECT: u8 = 0x64;
+    pub const WRITE_UNPROTECT: u8 = 0x73;
+    pub const NO_STRETCH_WRITE_UNPROTECT: u8 = 0x74;
+    pub const READOUT_PROTECT: u8 = 0x82;
+    pub const NO_STRETCH_READOUT_PROTECT: u8 = 0x83;
+    pub const READOUT_UNPROTECT: u8 = 0x92;
+    pub const NO_STRETCH_READOUT_UNPROTECT: u8 = 0x93;
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    I2c(E),
+    /// 命令被拒绝, 比如读保护时读内存
+    Nack(u8),
+    /// 应答不是 ACK/NACK/BUSY
+    Unexpected(u8),
+    /// 等应答超时
+    Timeout,
+    /// bootloader 不支持这个命令
+    Unsupported(u8),
+    /// 页数或扇区数不合法
+    InvalidLength(usize),
+    /// 写内存的地址没有 4 字节对齐
+    Unaligned(u32),
+    Verify {
+        address: u32,
+        expected: u8,
+        found: u8,
+    },
+}
+
+/// Get 命令的结果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Info {
+    /// 0x10 表示 1.0
+    pub version: u8,
+    pub commands: Vec<u8>,
+}
+
+impl Info {
+    pub fn supports(&self, command: u8) -> bool {
+        self.commands.contains(&command)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Erase {
+    /// 整片擦除
+    Global,
+    Bank1,
+    Bank2,
+    /// 页号, 旧的 Erase (0x43) 命令只支持 0 ~ 254
+    Pages(Vec<u16>),
+}
+
+pub struct Bootloader<I2C> {
+    i2c: I2C,
+    address: u8,
+    info: Info,
+}
+
+fn xor(data: &[u8]) -> u8 {
+    data.iter().fold(0, |x, &b| x ^ b)
+}
+
+/// 地址 4 字节大端加校验
+fn address_frame(address: u32) -> [u8; 5] {
+    let a = address.to_be_bytes();
+    [a[0], a[1], a[2], a[3], xor(&a)]
+}
+
+impl<I2C: I2c> Bootloader<I2C> {
+    /// 先发 Get 拿到支持的命令, 之后自动选择 No-Stretch 版本
+    pub fn new(i2c: I2C, address: u8) -> Result<Self, Error<I2C::Error>> {
+        let mut bl = Self {
+            i2c,
+            address,
+            info: Info {
+                version: 0,
+                commands: Vec::new(),
+            },
+        };
+        bl.info = bl.get()?;
+        Ok(bl)
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+
+    pub fn info(&self) -> &Info {
+        &self.info
+    }
+
+    /// 读一个应答字节, BUSY 或者地址没有应答 (正在写 Flash) 时继续等
+    fn wait_ack(&mut self, timeout: Duration) -> Result<(), Error<I2C::Error>> {
+        let start = Instant::now();
+        loop {
+            let mut rev = [0];
+            match self.i2c.read(self.address, &mut rev) {
+                Ok(()) => match rev[0] {
+                    ACK => return Ok(()),
+                    NACK => return Err(Error::Nack(NACK)),
+                    BUSY => {}
+                    other => return Err(Error::Unexpected(other)),
+                },
+                Err(e) if matches!(e.kind(), ErrorKind::NoAcknowledge(_)) => {}
+                Err(e) => return Err(Error::I2c(e)),
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn send(&mut self, data: &[u8], timeout: Duration) -> Result<(), Error<I2C::Error>> {
+        self.i2c.write(self.address, data).map_err(Error::I2c)?;
+        self.wait_ack(timeout)
+    }
+
+    fn command(&mut self, cmd: u8) -> Result<(), Error<I2C::Error>> {
+        self.send(&[cmd, !cmd], ACK_TIMEOUT).map_err(|e| match e {
+            Error::Nack(_) => Error::Nack(cmd),
+            e => e,
+        })
+    }
+
+    fn read_bytes(&mut self, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        self.i2c.read(self.address, buf).map_err(Error::I2c)
+    }
+
+    /// 有 No-Stretch 版本就用, 否则用普通版本, 都不支持时报错.
+    /// 还没有 Get 结果时直接用普通版本
+    fn pick(&self, normal: u8, no_stretch: u8) -> Result<u8, Error<I2C::Error>> {
+        if self.info.supports(no_stretch) {
+            Ok(no_stretch)
+        } else if self.info.commands.is_empty() || self.info.supports(normal) {
+            Ok(normal)
+        } else {
+            Err(Error::Unsupported(normal))
+        }
+    }
+
+    pub fn get(&mut self) -> Result<Info, Error<I2C::Error>> {
+        self.command(command::GET)?;
+        let mut n = [0];
+        self.read_bytes(&mut n)?;
+        let mut buf = vec![0; n[0] as usize + 1];
+        self.read_bytes(&mut buf)?;
+        self.wait_ack(ACK_TIMEOUT)?;
+        Ok(Info {
+            version: buf[0],
+            commands: buf[1..].to_vec(),
+        })
+    }
+
+    pub fn get_version(&mut self) -> Result<u8, Error<I2C::Error>> {
+        self.command(command::GET_VERSION)?;
+        let mut version = [0];
+        self.read_bytes(&mut version)?;
+        self.wait_ack(ACK_TIMEOUT)?;
+        Ok(version[0])
+    }
+
+    /// 产品 ID, 比如 STM32F40x 是 0x413
+    pub fn get_id(&mut self) -> Result<u16, Error<I2C::Error>> {
+        self.command(command::GET_ID)?;
+        let mut n = [0];
+        self.read_bytes(&mut n)?;
+        let mut pid = vec![0; n[0] as usize + 1];
+        self.read_bytes(&mut pid)?;
+        self.wait_ack(ACK_TIMEOUT)?;
+        Ok(pid.iter().fold(0, |id, &b| (id << 8) | u16::from(b)))
+    }
+
+    /// 一次最多 256 字节
+    fn read_chunk(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        if buf.is_empty() || buf.len() > CHUNK_SIZE {
+            return Err(Error::InvalidLength(buf.len()));
+        }
+        self.command(command::READ_MEMORY)?;
+        self.send(&address_frame(address), ACK_TIMEOUT)?;
+        let n = (buf.len() - 1) as u8;
+        self.send(&[n, !n], ACK_TIMEOUT)?;
+        self.read_bytes(buf)
+    }
+
+    pub fn read_memory(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<I2C::Error>> {
+        for (i, chunk) in buf.chunks_mut(CHUNK_SIZE).enumerate() {
+            self.read_chunk(address + (i * CHUNK_SIZE) as u32, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 地址和长度都要 4 字节对齐, 地址不对齐返回 [`Error::Unaligned`], 不对齐的尾巴补 0xFF
+    fn write_chunk(&mut self, address: u32, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        if data.is_empty() || data.len() > CHUNK_SIZE {
+            return Err(Error::InvalidLength(data.len()));
+        }
+        if !address.is_multiple_of(4) {
+            return Err(Error::Unaligned(address));
+        }
+        let cmd = self.pick(command::WRITE_MEMORY, command::NO_STRETCH_WRITE_MEMORY)?;
+        self.command(cmd)?;
+        self.send(&address_frame(address), ACK_TIMEOUT)?;
+
+        let mut obuf = vec![0];
+        obuf.extend_from_slice(data);
+        obuf.resize(1 + data.len().div_ceil(4) * 4, 0xFF);
+        obuf[0] = (obuf.len() - 2) as u8;
+        obuf.push(xor(&obuf));
+        self.send(&obuf, ACK_TIMEOUT)
+    }
+
+    pub fn write_memory(&mut self, address: u32, data: &[u8]) -> Result<(), Error<I2C::Error>> {
+        for (i, chunk) in data.chunks(CHUNK_SIZE).enumerate() {
+            self.write_chunk(address + (i * CHUNK_SIZE) as u32, chunk)?;
+        }
+        Ok(())
+    }
+
+    /// 支持 Extended Erase 时用 2 字节页号, 否则用旧的 Erase
+    pub fn erase(&mut self, erase: &Erase) -> Result<(), Error<I2C::Error>> {
+        if self.info.supports(command::NO_STRETCH_ERASE)
+            || self.info.supports(command::EXTENDED_ERASE)
+        {
+            return self.extended_erase(erase);
+        }
+        if !self.info.supports(command::ERASE) {
+            return Err(Error::Unsupported(command::ERASE));
+        }
+
+        self.command(command::ERASE)?;
+        match erase {
+            Erase::Global => self.send(&[0xFF, 0x00], ERASE_TIMEOUT),
+            Erase::Pages(pages) => {
+                if pages.is_empty() || pages.len() > 255 || pages.iter().any(|&p| p > 0xFF) {
+                    return Err(Error::InvalidLength(pages.len()));
+                }
+                let mut obuf = vec![(pages.len() - 1) as u8];
+                obuf.extend(pages.iter().map(|&p| p as u8));
+                obuf.push(xor(&obuf));
+                self.send(&obuf, ERASE_TIMEOUT)
+            }
+            Erase::Bank1 | Erase::Bank2 => Err(Error::Unsupported(command::ERASE)),
+        }
+    }
+
+    /// 先发页数, ACK 之后再发页号
+    fn extended_erase(&mut self, erase: &Erase) -> Result<(), Error<I2C::Error>> {
+        let cmd = self.pick(command::EXTENDED_ERASE, command::NO_STRETCH_ERASE)?;
+        let special = match erase {
+            Erase::Global => Some(0xFFFFu16),
+            Erase::Bank1 => Some(0xFFFE),
+            Erase::Bank2 => Some(0xFFFD),
+            Erase::Pages(_) => None,
+        };
+
+        self.command(cmd)?;
+        if let Some(code) = special {
+            let c = code.to_be_bytes();
+            return self.send(&[c[0], c[1], xor(&c)], ERASE_TIMEOUT);
+        }
+
+        let Erase::Pages(pages) = erase else {
+            unreachable!()
+        };
+        if pages.is_empty() || pages.len() > 0xFFF0 {
+            return Err(Error::InvalidLength(pages.len()));
+        }
+        let n = ((pages.len() - 1) as u16).to_be_bytes();
+        self.send(&[n[0], n[1], xor(&n)], ACK_TIMEOUT)?;
+
+        let mut obuf: Vec<u8> = pages.iter().flat_map(|p| p.to_be_bytes()).collect();
+        obuf.push(xor(&obuf));
+        self.send(&obuf, ERASE_TIMEOUT)
+    }
+
+    /// 从向量表 `address` 启动: 栈指针取自 `address`, PC 取自 `address + 4`,
+    /// 所以不能直接用 HEX 文件 05 记录里的入口地址. 之后 bootloader 不再应答
+    pub fn go(&mut self, address: u32) -> Result<(), Error<I2C::Error>> {
+        if !self.info.supports(command::GO) {
+            return Err(Error::Unsupported(command::GO));
+        }
+        self.command(command::GO)?;
+        self.send(&address_frame(address), ACK_TIMEOUT)
+    }
+
+    /// 完成后芯片会复位
+    pub fn write_protect(&mut self, sectors: &[u8]) -> Result<(), Error<I2C::Error>> {
+        if sectors.is_empty() || sectors.len() > 256 {
+            return Err(Error::InvalidLength(sectors.len()));
+        }
+        let cmd = self.pick(command::WRITE_PROTECT, command::NO_STRETCH_WRITE_PROTECT)?;
+        self.command(cmd)?;
+        let n = (sectors.len() - 1) as u8;
+        self.send(&[n, !n], ACK_TIMEOUT)?;
+        let mut obuf = sectors.to_vec();
+        obuf.push(xor(sectors));
+        self.send(&obuf, ERASE_TIMEOUT)
+    }
+
+    /// 完成后芯片会复位
+    pub fn write_unprotect(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.simple(
+            command::WRITE_UNPROTECT,
+            command::NO_STRETCH_WRITE_UNPROTECT,
+        )
+    }
+
+    /// 完成后芯片会复位
+    pub fn readout_protect(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.simple(
+            command::READOUT_PROTECT,
+            command::NO_STRETCH_READOUT_PROTECT,
+        )
+    }
+
+    /// 会整片擦除, 完成后芯片会复位
+    pub fn readout_unprotect(&mut self) -> Result<(), Error<I2C::Error>> {
+        self.simple(
+            command::READOUT_UNPROTECT,
+            command::NO_STRETCH_READOUT_UNPROTECT,
+        )
+    }
+
+    /// 只有命令和两次 ACK 的命令
+    fn simple(&mut self, normal: u8, no_stretch: u8) -> Result<(), Error<I2C::Error>> {
+        let cmd = self.pick(normal, no_stretch)?;
+        self.command(cmd)?;
+        self.wait_ack(ERASE_TIMEOUT)
+    }
+
+    /// 写入所有段, `progress(done, total)`
+    pub fn write_image(
+        &mut self,
+        image: &Image,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<I2C::Error>> {
+        let total = image.len();
+        let mut done = 0;
+        for seg in image.segments.iter() {
+            for (i, chunk) in seg.data.chunks(CHUNK_SIZE).enumerate() {
+                self.write_chunk(seg.address + (i * CHUNK_SIZE) as u32, chunk)?;
+                done += chunk.len();
+                progress(done, total);
+            }
+        }
+        Ok(())
+    }
+
+    /// 用 Read Memory 按 256 字节读回每一段比较
+    pub fn verify_image(
+        &mut self,
+        image: &Image,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<I2C::Error>> {
+        let total = image.len();
+        let mut done = 0;
+        let mut buf = [0; CHUNK_SIZE];
+        for seg in image.segments.iter() {
+            for (i, expected) in seg.data.chunks(CHUNK_SIZE).enumerate() {
+                let address = seg.address + (i * CHUNK_SIZE) as u32;
+                let found = &mut buf[..expected.len()];
+                self.read_chunk(address, found)?;
+                if let Some((j, expected, found)) = first_mismatch(expected, found) {
+                    return Err(Error::Verify {
+                        address: address + j as u32,
+                        expected,
+                        found,
+                    });
+                }
+                done += expected.len();
+                progress(done, total);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::i2c::{ErrorType, Operation};
+
+    use super::*;
+
+    /// 记下每次写的内容, 单字节读回 ACK, 其他读回 0xAB
+    #[derive(Default)]
+    struct Mock {
+        writes: Vec<Vec<u8>>,
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl I2c for Mock {
+        fn transaction(
+            &mut self,
+            _address: u8,
+            operations: &mut [Operation<'_>],
+        ) -> Result<(), Self::Error> {
+            for op in operations {
+                match op {
+                    Operation::Write(buf) => self.writes.push(buf.to_vec()),
+                    Operation::Read([ack]) => *ack = ACK,
+                    Operation::Read(buf) => buf.fill(0xAB),
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn bootloader(commands: &[u8]) -> Bootloader<Mock> {
+        Bootloader {
+            i2c: Mock::default(),
+            address: 0x56,
+            info: Info {
+                version: 0x11,
+                commands: commands.to_vec(),
+            },
+        }
+    }
+
+    #[test]
+    fn address_checksum() {
+        assert_eq!(address_frame(0x0800_0000), [0x08, 0x00, 0x00, 0x00, 0x08]);
+        assert_eq!(address_frame(0x1234_5678), [0x12, 0x34, 0x56, 0x78, 0x08]);
+        assert_eq!(address_frame(0), [0; 5]);
+    }
+
+    #[test]
+    fn pick() {
+        use command::{NO_STRETCH_WRITE_MEMORY as NO_STRETCH, WRITE_MEMORY as NORMAL};
+
+        assert_eq!(
+            bootloader(&[NORMAL, NO_STRETCH])
+                .pick(NORMAL, NO_STRETCH)
+                .unwrap(),
+            NO_STRETCH
+        );
+        assert_eq!(
+            bootloader(&[NO_STRETCH]).pick(NORMAL, NO_STRETCH).unwrap(),
+            NO_STRETCH
+        );
+        assert_eq!(
+            bootloader(&[NORMAL]).pick(NORMAL, NO_STRETCH).unwrap(),
+            NORMAL
+        );
+        // 还没有 Get 结果
+        assert_eq!(bootloader(&[]).pick(NORMAL, NO_STRETCH).unwrap(), NORMAL);
+        assert!(matches!(
+            bootloader(&[command::GET]).pick(NORMAL, NO_STRETCH),
+            Err(Error::Unsupported(NORMAL))
+        ));
+    }
+
+    #[test]
+    fn write_frames() {
+        let mut bl = bootloader(&[command::NO_STRETCH_WRITE_MEMORY]);
+        bl.write_memory(0x0800_0000, &[1, 2, 3, 4, 5]).unwrap();
+        // 长度补到 4 的倍数, N - 1 之后跟数据和 XOR
+        assert_eq!(
+            bl.release().writes,
+            [
+                vec![0x32, 0xCD],
+                vec![0x08, 0x00, 0x00, 0x00, 0x08],
+                vec![
+                    7,
+                    1,
+                    2,
+                    3,
+                    4,
+                    5,
+                    0xFF,
+                    0xFF,
+                    0xFF,
+                    7 ^ 1 ^ 2 ^ 3 ^ 4 ^ 5 ^ 0xFF
+                ],
+            ]
+        );
+
+        let mut bl = bootloader(&[]);
+        assert!(matches!(
+            bl.write_memory(0x0800_0002, &[0]),
+            Err(Error::Unaligned(0x0800_0002))
+        ));
+        assert!(bl.release().writes.is_empty());
+    }
+
+    #[test]
+    fn write_splits_chunks() {
+        let mut bl = bootloader(&[command::WRITE_MEMORY]);
+        bl.write_memory(0x0800_0000, &[0x5A; 300]).unwrap();
+        let writes = bl.release().writes;
+        assert_eq!(writes.len(), 6);
+        assert_eq!(writes[0], [0x31, 0xCE]);
+        assert_eq!(writes[2].len(), 1 + 256 + 1);
+        assert_eq!(writes[2][0], 255);
+        assert_eq!(writes[4], address_frame(0x0800_0100));
+        assert_eq!(writes[5].len(), 1 + 44 + 1);
+        assert_eq!(writes[5][0], 43);
+    }
+
+    #[test]
+    fn read_frames() {
+        let mut bl = bootloader(&[]);
+        let mut buf = [0; 16];
+        bl.read_memory(0x2000_0000, &mut buf).unwrap();
+        assert_eq!(buf, [0xAB; 16]);
+        assert_eq!(
+            bl.release().writes,
+            [
+                vec![0x11, 0xEE],
+                vec![0x20, 0x00, 0x00, 0x00, 0x20],
+                vec![15, !15],
+            ]
+        );
+    }
+
+    #[test]
+    fn extended_erase_frames() {
+        let mut bl = bootloader(&[command::EXTENDED_ERASE]);
+        bl.erase(&Erase::Pages(vec![0x0001, 0x0102])).unwrap();
+        bl.erase(&Erase::Global).unwrap();
+        assert_eq!(
+            bl.release().writes,
+            [
+                vec![0x44, 0xBB],
+                vec![0x00, 0x01, 0x01],
+                vec![0x00, 0x01, 0x01, 0x02, 0x02],
+                vec![0x44, 0xBB],
+                vec![0xFF, 0xFF, 0x00],
+            ]
+        );
+
+        let mut bl = bootloader(&[command::EXTENDED_ERASE, command::NO_STRETCH_ERASE]);
+        bl.erase(&Erase::Bank2).unwrap();
+        assert_eq!(
+            bl.release().writes,
+            [vec![0x45, 0xBA], vec![0xFF, 0xFD, 0x02]]
+        );
+    }
+
+    #[test]
+    fn legacy_erase_frames() {
+        let mut bl = bootloader(&[command::ERASE]);
+        bl.erase(&Erase::Pages(vec![3, 4, 7])).unwrap();
+        bl.erase(&Erase::Global).unwrap();
+        assert_eq!(
+            bl.release().writes,
+            [
+                vec![0x43, 0xBC],
+                vec![2, 3, 4, 7, 2 ^ 3 ^ 4 ^ 7],
+                vec![0x43, 0xBC],
+                vec![0xFF, 0x00],
+            ]
+        );
+
+        let mut bl = bootloader(&[command::ERASE]);
+        assert!(matches!(
+            bl.erase(&Erase::Pages(vec![0x100])),
+            Err(Error::InvalidLength(1))
+        ));
+    }
+
+    #[test]
+    fn write_protect_frames() {
+        let mut bl = bootloader(&[command::WRITE_PROTECT, command::NO_STRETCH_WRITE_PROTECT]);
+        bl.write_protect(&[0, 1, 5]).unwrap();
+        assert_eq!(
+            bl.release().writes,
+            [vec![0x64, 0x9B], vec![2, !2], vec![0, 1, 5, 1 ^ 5]]
+        );
+
+        let mut bl = bootloader(&[command::WRITE_PROTECT]);
+        assert!(matches!(
+            bl.write_protect(&[]),
+            Err(Error::InvalidLength(0))
+        ));
+    }
+}
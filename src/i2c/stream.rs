@@ -0,0 +1,477 @@
+//! 把一次 I2C transaction 编成 CH347 的 0xAA 流命令
+//!
+//! 流命令格式和 CH341 一样:
+//! - 0x74: Start (也用作 Repeated Start)
+//! - 0x75: Stop
+//! - 0x80 | n: 发送后面 n 个字节, 每个字节回一个 ACK 状态
+//! - 0xC0 | n: 读 n 个字节, 每个字节都回 ACK
+//! - 0xC0: 读 1 个字节, 回 NACK, 用在最后一个字节
+//! - 0x00: 流结束
+
+use embedded_hal::i2c::{ErrorKind, NoAcknowledgeSource, Operation};
+
+use crate::ch347;
+
+const STREAM: u8 = 0xAA;
+const START: u8 = 0x74;
+const STOP: u8 = 0x75;
+const OUT: u8 = 0x80;
+const IN: u8 = 0xC0;
+const END: u8 = 0x00;
+
+/// 单条 OUT/IN 命令最多带 63 个字节
+const MAX_CHUNK: usize = 63;
+/// 一个 USB 包的大小, 命令和回复都不能超过
+const PACKET_SIZE: usize = 510;
+/// 去掉 0xAA 和 0x00 之后一个包里能放的命令
+const PAYLOAD_SIZE: usize = PACKET_SIZE - 2;
+
+/// 发送字节的回复, bit0 为 1 表示从机 ACK
+pub(crate) fn is_ack(reply: u8) -> bool {
+    reply & 0x01 == 0x01
+}
+
+/// 回复中每个字节的含义
+#[derive(Debug, Clone, Copy)]
+enum Reply {
+    Ack(NoAcknowledgeSource),
+    Data,
+}
+
+#[derive(Default)]
+struct Packet {
+    command: Vec<u8>,
+    replies: Vec<Reply>,
+}
+
+/// 一次总线传输, 太长时拆成多个 USB 包, 包与包之间不发 Stop, 总线一直被占着
+#[derive(Default)]
+pub(crate) struct Stream {
+    packets: Vec<Packet>,
+}
+
+impl Stream {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// 当前包放不下时开一个新包
+    fn packet(&mut self, command: usize, replies: usize) -> &mut Packet {
+        let full = match self.packets.last() {
+            Some(p) => {
+                p.command.len() + command > PAYLOAD_SIZE || p.replies.len() + replies > PACKET_SIZE
+            }
+            None => true,
+        };
+        if full {
+            self.packets.push(Packet::default());
+        }
+        self.packets.last_mut().unwrap()
+    }
+
+    pub(crate) fn start(&mut self) {
+        self.packet(1, 0).command.push(START);
+    }
+
+    pub(crate) fn stop(&mut self) {
+        self.packet(1, 0).command.push(STOP);
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8], source: NoAcknowledgeSource) {
+        for chunk in bytes.chunks(MAX_CHUNK) {
+            let packet = self.packet(1 + chunk.len(), chunk.len());
+            packet.command.push(OUT | chunk.len() as u8);
+            packet.command.extend_from_slice(chunk);
+            packet
+                .replies
+                .extend(std::iter::repeat_n(Reply::Ack(source), chunk.len()));
+        }
+    }
+
+    /// `nack_last` 为 true 时最后一个字节回 NACK, 告诉从机读完了
+    pub(crate) fn read(&mut self, len: usize, nack_last: bool) {
+        let acked = if nack_last {
+            len.saturating_sub(1)
+        } else {
+            len
+        };
+        let mut left = acked;
+        while left > 0 {
+            let n = left.min(MAX_CHUNK);
+            let packet = self.packet(1, n);
+            packet.command.push(IN | n as u8);
+            packet.replies.extend(std::iter::repeat_n(Reply::Data, n));
+            left -= n;
+        }
+        if nack_last && len > 0 {
+            let packet = self.packet(1, 1);
+            packet.command.push(IN);
+            packet.replies.push(Reply::Data);
+        }
+    }
+
+    /// 逐包发出, 检查 ACK, 返回读到的数据
+    ///
+    /// 中途出现 NACK 时后面的包不再发送, 补一个 Stop 释放总线
+    pub(crate) fn execute(&self) -> Result<Vec<u8>, ErrorKind> {
+        let mut data = Vec::new();
+        for (i, packet) in self.packets.iter().enumerate() {
+            let result = Self::execute_packet(packet, &mut data);
+            if result.is_err() && i + 1 < self.packets.len() {
+                Self::release();
+            }
+            result?;
+        }
+        Ok(data)
+    }
+
+    /// 单独发一个 Stop 释放总线
+    fn release() {
+        let _ = Self::exchange(&Packet {
+            command: vec![STOP],
+            replies: Vec::new(),
+        });
+    }
+
+    /// 不检查 ACK, 原样返回所有回复字节, 扫描总线时用
+    pub(crate) fn execute_raw(&self) -> Result<Vec<u8>, ErrorKind> {
+        let mut replies = Vec::new();
+        for packet in self.packets.iter() {
+            replies.extend(Self::exchange(packet)?);
+        }
+        Ok(replies)
+    }
+
+    fn exchange(packet: &Packet) -> Result<Vec<u8>, ErrorKind> {
+        let mut obuf = Vec::with_capacity(packet.command.len() + 2);
+        obuf.push(STREAM);
+        obuf.extend_from_slice(&packet.command);
+        obuf.push(END);
+        ch347::write(&obuf).map_err(|_| ErrorKind::Other)?;
+
+        let mut ibuf = Vec::with_capacity(packet.replies.len());
+        let mut buf = [0; PACKET_SIZE];
+        // 引擎收不齐回复一般是 SCL/SDA 被从机拉住了
+        while ibuf.len() < packet.replies.len() {
+            let rev = ch347::read(&mut buf).map_err(|_| ErrorKind::Bus)?;
+            if rev == 0 {
+                return Err(ErrorKind::Bus);
+            }
+            ibuf.extend_from_slice(&buf[..rev]);
+        }
+        ibuf.truncate(packet.replies.len());
+        Ok(ibuf)
+    }
+
+    fn execute_packet(packet: &Packet, data: &mut Vec<u8>) -> Result<(), ErrorKind> {
+        let ibuf = Self::exchange(packet)?;
+        for (reply, &byte) in packet.replies.iter().zip(ibuf.iter()) {
+            match *reply {
+                Reply::Ack(source) => {
+                    if !is_ack(byte) {
+                        return Err(ErrorKind::NoAcknowledge(source));
+                    }
+                }
+                Reply::Data => data.push(byte),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 从机地址
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Address {
+    Seven(u8),
+    /// 先发头 11110 A9 A8 R/W, 写方向再发 A7 ~ A0
+    Ten(u16),
+}
+
+impl Address {
+    /// 方向改变时发的起始位和地址, `addressed` 表示这次 transaction 里已经用写方向寻址过
+    fn encode(&self, stream: &mut Stream, is_read: bool, addressed: bool) {
+        match *self {
+            Address::Seven(address) => {
+                stream.start();
+                stream.write(
+                    &[(address << 1) | u8::from(is_read)],
+                    NoAcknowledgeSource::Address,
+                );
+            }
+            Address::Ten(address) => {
+                let header = 0xF0 | ((address >> 7) as u8 & 0x06);
+                let low = address as u8;
+                // 读之前必须先用写方向发完整地址, 然后 Repeated Start 只发读头
+                if !is_read || !addressed {
+                    stream.start();
+                    stream.write(&[header, low], NoAcknowledgeSource::Address);
+                }
+                if is_read {
+                    stream.start();
+                    stream.write(&[header | 0x01], NoAcknowledgeSource::Address);
+                }
+            }
+        }
+    }
+}
+
+/// 整个 transaction 只有一个 Start 和一个 Stop, 读写方向改变时发 Repeated Start
+pub(crate) fn transaction(
+    address: Address,
+    operations: &mut [Operation<'_>],
+) -> Result<(), ErrorKind> {
+    chain(&mut [(address, operations)])
+}
+
+/// 多个从机放在同一次传输里, 换地址时也发 Repeated Start.
+/// E-DDC 的段指针这种 Stop 之后就失效的东西需要这样访问
+pub(crate) fn chain(parts: &mut [(Address, &mut [Operation<'_>])]) -> Result<(), ErrorKind> {
+    let mut operations: Vec<(Address, &mut Operation<'_>)> = parts
+        .iter_mut()
+        .flat_map(|(address, operations)| {
+            let address = *address;
+            operations.iter_mut().map(move |op| (address, op))
+        })
+        .collect();
+    if operations.is_empty() {
+        return Ok(());
+    }
+
+    let data = encode(&operations).execute()?;
+    let mut data = data.into_iter();
+    for (_, op) in operations.iter_mut() {
+        if let Operation::Read(buf) = op {
+            for byte in buf.iter_mut() {
+                *byte = data.next().unwrap_or_default();
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 把 transaction 编成流, 读操作只用到长度
+fn encode(operations: &[(Address, &mut Operation<'_>)]) -> Stream {
+    let mut stream = Stream::new();
+    let mut prev: Option<(Address, bool)> = None;
+    for i in 0..operations.len() {
+        let (address, ref op) = operations[i];
+        let is_read = matches!(op, Operation::Read(_));
+        if prev != Some((address, is_read)) {
+            let addressed = prev.is_some_and(|(a, _)| a == address);
+            address.encode(&mut stream, is_read, addressed);
+        }
+
+        match op {
+            Operation::Write(buf) => stream.write(buf, NoAcknowledgeSource::Data),
+            Operation::Read(buf) => {
+                // 相邻的读会合并, 只有最后一个读的最后一个字节回 NACK
+                let next_is_read = operations
+                    .get(i + 1)
+                    .is_some_and(|(a, op)| *a == address && matches!(op, Operation::Read(_)));
+                stream.read(buf.len(), !next_is_read);
+            }
+        }
+        prev = Some((address, is_read));
+    }
+    stream.stop();
+    stream
+}
+
+/// SMBus 块读: 先读长度字节, 再在同一次传输里读 min(长度, `max`) + `extra` 个字节,
+/// 最后一个字节回 NACK. `written` 不为空时先写它再 Repeated Start.
+/// 返回长度字节和后面读到的字节
+pub(crate) fn counted_read(
+    address: u8,
+    written: &[u8],
+    max: usize,
+    extra: usize,
+) -> Result<Vec<u8>, ErrorKind> {
+    let address = Address::Seven(address);
+    let mut head = Stream::new();
+    if !written.is_empty() {
+        address.encode(&mut head, false, false);
+        head.write(written, NoAcknowledgeSource::Data);
+    }
+    address.encode(&mut head, true, !written.is_empty());
+    head.read(1, false);
+    // 这一段没有 Stop, 出错时要自己释放总线
+    let count = match head.execute() {
+        Ok(data) => data[0],
+        Err(e) => {
+            Stream::release();
+            return Err(e);
+        }
+    };
+
+    let len = usize::from(count).min(max) + extra;
+    let mut tail = Stream::new();
+    // 长度字节已经 ACK 了, 至少再读一个字节回 NACK 才能结束
+    tail.read(len.max(1), true);
+    tail.stop();
+    let mut data = tail.execute()?;
+    data.truncate(len);
+    data.insert(0, count);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encoded(address: Address, operations: &mut [Operation<'_>]) -> Stream {
+        let operations: Vec<_> = operations.iter_mut().map(|op| (address, op)).collect();
+        encode(&operations)
+    }
+
+    /// 每个包的命令
+    fn commands(stream: &Stream) -> Vec<Vec<u8>> {
+        stream.packets.iter().map(|p| p.command.clone()).collect()
+    }
+
+    #[test]
+    fn write_read_seven_bit() {
+        let mut buf = [0; 3];
+        let stream = encoded(
+            Address::Seven(0x50),
+            &mut [Operation::Write(&[0x12, 0x34]), Operation::Read(&mut buf)],
+        );
+        assert_eq!(
+            commands(&stream),
+            [[
+                START, 0x81, 0xA0, 0x82, 0x12, 0x34, START, 0x81, 0xA1, 0xC2, 0xC0, STOP
+            ]]
+        );
+        let replies = &stream.packets[0].replies;
+        assert_eq!(replies.len(), 7);
+        assert!(matches!(
+            replies[0],
+            Reply::Ack(NoAcknowledgeSource::Address)
+        ));
+        assert!(matches!(replies[1], Reply::Ack(NoAcknowledgeSource::Data)));
+        assert!(matches!(
+            replies[3],
+            Reply::Ack(NoAcknowledgeSource::Address)
+        ));
+        assert!(matches!(replies[4], Reply::Data));
+        assert!(matches!(replies[6], Reply::Data));
+    }
+
+    #[test]
+    fn ten_bit_header() {
+        // 0x2A5: A9 A8 = 10, 头是 11110 10 R/W
+        let stream = encoded(Address::Ten(0x2A5), &mut [Operation::Write(&[0x01])]);
+        assert_eq!(
+            commands(&stream),
+            [[START, 0x82, 0xF4, 0xA5, 0x81, 0x01, STOP]]
+        );
+        let stream = encoded(Address::Ten(0x3FF), &mut [Operation::Write(&[])]);
+        assert_eq!(commands(&stream), [[START, 0x82, 0xF6, 0xFF, STOP]]);
+    }
+
+    #[test]
+    fn ten_bit_read() {
+        // 先用写方向发完整地址, 再 Repeated Start 发读头
+        let mut buf = [0; 1];
+        let stream = encoded(Address::Ten(0x155), &mut [Operation::Read(&mut buf)]);
+        assert_eq!(
+            commands(&stream),
+            [[START, 0x82, 0xF2, 0x55, START, 0x81, 0xF3, 0xC0, STOP]]
+        );
+
+        // 写过之后读只发读头
+        let stream = encoded(
+            Address::Ten(0x155),
+            &mut [Operation::Write(&[0x01]), Operation::Read(&mut buf)],
+        );
+        assert_eq!(
+            commands(&stream),
+            [[
+                START, 0x82, 0xF2, 0x55, 0x81, 0x01, START, 0x81, 0xF3, 0xC0, STOP
+            ]]
+        );
+    }
+
+    #[test]
+    fn chunks() {
+        let data: Vec<u8> = (0..100).collect();
+        let mut buf = [0; 130];
+        let stream = encoded(
+            Address::Seven(0x50),
+            &mut [Operation::Write(&data), Operation::Read(&mut buf)],
+        );
+        let command = &commands(&stream)[0];
+        assert_eq!(command[3], OUT | 63);
+        assert_eq!(&command[4..67], &data[..63]);
+        assert_eq!(command[67], OUT | 37);
+        assert_eq!(&command[68..105], &data[63..]);
+        // 129 个字节回 ACK, 最后一个回 NACK
+        assert_eq!(
+            &command[105..],
+            [START, 0x81, 0xA1, IN | 63, IN | 63, IN | 3, IN, STOP]
+        );
+    }
+
+    #[test]
+    fn adjacent_reads_nack_once() {
+        let (mut a, mut b) = ([0; 2], [0; 2]);
+        let stream = encoded(
+            Address::Seven(0x50),
+            &mut [Operation::Read(&mut a), Operation::Read(&mut b)],
+        );
+        assert_eq!(
+            commands(&stream),
+            [[START, 0x81, 0xA1, IN | 2, IN | 1, IN, STOP]]
+        );
+    }
+
+    #[test]
+    fn empty_read() {
+        // 只发地址和 Stop, 不读字节
+        let stream = encoded(Address::Seven(0x50), &mut [Operation::Read(&mut [])]);
+        assert_eq!(commands(&stream), [[START, 0x81, 0xA1, STOP]]);
+        assert_eq!(stream.packets[0].replies.len(), 1);
+    }
+
+    #[test]
+    fn packets() {
+        let data = vec![0x55; 1000];
+        let mut buf = vec![0; 600];
+        let stream = encoded(
+            Address::Seven(0x50),
+            &mut [Operation::Write(&data), Operation::Read(&mut buf)],
+        );
+        assert!(stream.packets.len() > 2);
+        for packet in stream.packets.iter() {
+            assert!(packet.command.len() <= PAYLOAD_SIZE);
+            assert!(packet.replies.len() <= PACKET_SIZE);
+        }
+        // 拆包不改变命令本身, 也不会把 OUT 命令和它的数据分开
+        let flat: Vec<u8> = stream
+            .packets
+            .iter()
+            .flat_map(|p| p.command.clone())
+            .collect();
+        let mut expected = vec![START, 0x81, 0xA0];
+        for chunk in data.chunks(MAX_CHUNK) {
+            expected.push(OUT | chunk.len() as u8);
+            expected.extend_from_slice(chunk);
+        }
+        expected.extend_from_slice(&[START, 0x81, 0xA1]);
+        expected.extend(std::iter::repeat_n(IN | 63, 9));
+        expected.extend_from_slice(&[IN | 32, IN, STOP]);
+        assert_eq!(flat, expected);
+        for packet in stream.packets.iter() {
+            let mut i = 0;
+            while i < packet.command.len() {
+                let command = packet.command[i];
+                i += 1;
+                if command & 0xC0 == OUT {
+                    i += usize::from(command & 0x3F);
+                }
+            }
+            assert_eq!(i, packet.command.len());
+        }
+        let replies: usize = stream.packets.iter().map(|p| p.replies.len()).sum();
+        assert_eq!(replies, 1 + 1000 + 1 + 600);
+    }
+}
@@ -0,0 +1,333 @@
+//! Intel HEX 解析, 固件下载时共用
+//!
+//! 支持 00 数据, 01 结束, 02 扩展段地址, 03 段起始地址, 04 扩展线性地址, 05 线性起始地址.
+//! 地址连续的数据记录合并成一个 [`Segment`]
+
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    /// 行号从 1 开始
+    Syntax {
+        line: usize,
+    },
+    Checksum {
+        line: usize,
+    },
+    RecordType {
+        line: usize,
+        kind: u8,
+    },
+    /// 没有 01 结束记录
+    NoEof,
+}
+
+/// 一段地址连续的数据
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub address: u32,
+    pub data: Vec<u8>,
+}
+
+impl Segment {
+    /// 结束地址 (不含), 用 u64 是因为段可以一直到 4GiB 的顶端
+    pub fn end(&self) -> u64 {
+        u64::from(self.address) + self.data.len() as u64
+    }
+}
+
+/// 要下载的固件, 段按地址排好序且互不重叠
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Image {
+    pub segments: Vec<Segment>,
+    /// 03/05 记录给出的入口地址, ARM 上是复位函数 (带 Thumb 位), 不是向量表
+    pub start: Option<u32>,
+}
+
+impl Image {
+    /// 裸的二进制文件, 放在 `address` 开始的地方
+    pub fn from_bin(address: u32, data: Vec<u8>) -> Self {
+        Self {
+            segments: vec![Segment { address, data }],
+            start: None,
+        }
+    }
+
+    pub fn parse(hex: &str) -> Result<Self, Error> {
+        let mut image = Self::default();
+        let mut base = 0u32;
+
+        for (i, line) in hex.lines().enumerate() {
+            let line_no = i + 1;
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let record = decode_line(line).ok_or(Error::Syntax { line: line_no })?;
+            if record.len() < 5 || record.len() != 5 + record[0] as usize {
+                return Err(Error::Syntax { line: line_no });
+            }
+            if record.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) != 0 {
+                return Err(Error::Checksum { line: line_no });
+            }
+
+            let offset = u16::from_be_bytes([record[1], record[2]]);
+            let kind = record[3];
+            let data = &record[4..record.len() - 1];
+            match (kind, data.len()) {
+                (0x00, _) => image.insert(base.wrapping_add(u32::from(offset)), data),
+                (0x01, _) => {
+                    image.normalize();
+                    return Ok(image);
+                }
+                (0x02, 2) => base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 4,
+                (0x04, 2) => base = u32::from(u16::from_be_bytes([data[0], data[1]])) << 16,
+                (0x03, 4) => {
+                    let cs = u32::from(u16::from_be_bytes([data[0], data[1]]));
+                    let ip = u32::from(u16::from_be_bytes([data[2], data[3]]));
+                    image.start = Some((cs << 4) + ip);
+                }
+                (0x05, 4) => {
+                    image.start = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]))
+                }
+                (0x02..=0x05, _) => return Err(Error::Syntax { line: line_no }),
+                (kind, _) => {
+                    return Err(Error::RecordType {
+                        line: line_no,
+                        kind,
+                    });
+                }
+            }
+        }
+        Err(Error::NoEof)
+    }
+
+    /// 扩展名是 hex/ihx 时按 Intel HEX 解析, 否则当作放在 `address` 的二进制文件
+    pub fn load(path: impl AsRef<Path>, address: u32) -> Result<Self, Error> {
+        let path = path.as_ref();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("hex" | "ihx") => Self::parse(&std::fs::read_to_string(path).map_err(Error::Io)?),
+            _ => Ok(Self::from_bin(
+                address,
+                std::fs::read(path).map_err(Error::Io)?,
+            )),
+        }
+    }
+
+    /// 总字节数
+    pub fn len(&self) -> usize {
+        self.segments.iter().map(|s| s.data.len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn insert(&mut self, address: u32, data: &[u8]) {
+        if let Some(last) = self.segments.last_mut()
+            && last.end() == u64::from(address)
+        {
+            last.data.extend_from_slice(data);
+            return;
+        }
+        self.segments.push(Segment {
+            address,
+            data: data.to_vec(),
+        });
+    }
+
+    /// 排序, 合并相邻和重叠的段, 重叠的部分以文件里后出现的为准
+    fn normalize(&mut self) {
+        let records = std::mem::take(&mut self.segments);
+
+        // 先按地址排出合并后的范围
+        let mut sorted: Vec<&Segment> = records.iter().collect();
+        sorted.sort_by_key(|s| s.address);
+        for seg in sorted {
+            match self.segments.last_mut() {
+                Some(last) if u64::from(seg.address) <= last.end() => {
+                    let end = (seg.end() - u64::from(last.address)) as usize;
+                    if end > last.data.len() {
+                        last.data.resize(end, 0xFF);
+                    }
+                }
+                _ => self.segments.push(Segment {
+                    address: seg.address,
+                    data: vec![0xFF; seg.data.len()],
+                }),
+            }
+        }
+
+        // 再按文件顺序填数据
+        for seg in records.iter() {
+            let i = self.segments.partition_point(|s| s.address <= seg.address) - 1;
+            let target = &mut self.segments[i];
+            let at = (seg.address - target.address) as usize;
+            target.data[at..at + seg.data.len()].copy_from_slice(&seg.data);
+        }
+    }
+}
+
+fn decode_line(line: &str) -> Option<Vec<u8>> {
+    let hex = line.strip_prefix(':')?;
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(kind: u8, offset: u16, data: &[u8]) -> String {
+        let mut bytes = vec![data.len() as u8];
+        bytes.extend_from_slice(&offset.to_be_bytes());
+        bytes.push(kind);
+        bytes.extend_from_slice(data);
+        bytes.push(0u8.wrapping_sub(bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b))));
+        let hex: String = bytes.iter().map(|b| format!("{b:02X}")).collect();
+        format!(":{hex}\n")
+    }
+
+    fn eof() -> String {
+        record(0x01, 0, &[])
+    }
+
+    #[test]
+    fn known_line() {
+        assert_eq!(eof(), ":00000001FF\n");
+        let hex = ":0300300002337A1E\n:00000001FF\n";
+        let image = Image::parse(hex).unwrap();
+        assert_eq!(
+            image.segments,
+            [Segment {
+                address: 0x30,
+                data: vec![0x02, 0x33, 0x7A],
+            }]
+        );
+    }
+
+    #[test]
+    fn extended_linear_address() {
+        let hex = [
+            record(0x04, 0, &[0x08, 0x00]),
+            record(0x00, 0x0000, &[1, 2, 3, 4]),
+            record(0x00, 0x0004, &[5, 6]),
+            record(0x05, 0, &[0x08, 0x00, 0x01, 0x01]),
+            eof(),
+        ]
+        .concat();
+        let image = Image::parse(&hex).unwrap();
+        assert_eq!(
+            image.segments,
+            [Segment {
+                address: 0x0800_0000,
+                data: vec![1, 2, 3, 4, 5, 6],
+            }]
+        );
+        assert_eq!(image.start, Some(0x0800_0101));
+        assert_eq!(image.len(), 6);
+    }
+
+    #[test]
+    fn extended_segment_address() {
+        let hex = [
+            record(0x02, 0, &[0x12, 0x34]),
+            record(0x00, 0x0010, &[0xAA]),
+            record(0x03, 0, &[0x12, 0x34, 0x00, 0x10]),
+            eof(),
+        ]
+        .concat();
+        let image = Image::parse(&hex).unwrap();
+        assert_eq!(image.segments[0].address, 0x12350);
+        assert_eq!(image.start, Some(0x12350));
+    }
+
+    #[test]
+    fn bad_checksum() {
+        let mut line = record(0x00, 0, &[1, 2, 3]);
+        line.replace_range(9..11, "FF");
+        let hex = format!("{}{}{}", record(0x04, 0, &[0, 0]), line, eof());
+        assert!(matches!(
+            Image::parse(&hex),
+            Err(Error::Checksum { line: 2 })
+        ));
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(matches!(
+            Image::parse("0000000100\n"),
+            Err(Error::Syntax { line: 1 })
+        ));
+        // 04 记录的数据必须是 2 字节
+        let hex = format!("{}{}", record(0x04, 0, &[0]), eof());
+        assert!(matches!(Image::parse(&hex), Err(Error::Syntax { line: 1 })));
+        let hex = format!("{}{}", record(0x06, 0, &[]), eof());
+        assert!(matches!(
+            Image::parse(&hex),
+            Err(Error::RecordType { line: 1, kind: 6 })
+        ));
+        assert!(matches!(
+            Image::parse(&record(0x00, 0, &[1])),
+            Err(Error::NoEof)
+        ));
+    }
+
+    #[test]
+    fn later_record_wins() {
+        let hex = [
+            record(0x00, 0x10, &[1, 1, 1, 1]),
+            // 地址更低但在文件里更靠后
+            record(0x00, 0x0E, &[2, 2, 2, 2]),
+            record(0x00, 0x20, &[3]),
+            record(0x00, 0x13, &[4, 4]),
+            eof(),
+        ]
+        .concat();
+        let image = Image::parse(&hex).unwrap();
+        assert_eq!(
+            image.segments,
+            [
+                Segment {
+                    address: 0x0E,
+                    data: vec![2, 2, 2, 2, 1, 4, 4],
+                },
+                Segment {
+                    address: 0x20,
+                    data: vec![3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn top_of_address_space() {
+        // 段一直到 0xFFFFFFFF, 结束地址在 u32 里放不下
+        let hex = [
+            record(0x04, 0, &[0xFF, 0xFF]),
+            record(0x00, 0xFFF0, &[1; 8]),
+            record(0x00, 0xFFF8, &[2; 8]),
+            record(0x00, 0xFFFC, &[3; 4]),
+            eof(),
+        ]
+        .concat();
+        let image = Image::parse(&hex).unwrap();
+        let mut data = vec![1; 8];
+        data.extend_from_slice(&[2; 4]);
+        data.extend_from_slice(&[3; 4]);
+        assert_eq!(
+            image.segments,
+            [Segment {
+                address: 0xFFFF_FFF0,
+                data,
+            }]
+        );
+        assert_eq!(image.segments[0].end(), 0x1_0000_0000);
+    }
+}
@@ -1,13 +1,17 @@
 use std::{thread::sleep, time::Duration};
 
+pub mod bitbang;
 pub mod ch347;
 pub mod command;
 pub mod gpio;
 pub mod hal;
 pub mod i2c;
+pub mod ihex;
 pub mod jtag;
+pub mod regmap;
 pub mod spi;
 pub mod swd;
+mod util;
 
 pub fn format_u8_array(arr: &[u8]) -> String {
     let formatted: Vec<String> = arr.iter().map(|&byte| format!("0x{:02x}", byte)).collect();
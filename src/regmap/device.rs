@@ -0,0 +1,300 @@
+use std::fmt;
+
+use super::{Access, Endian, Interface, LoadError, Register, RegisterMap, TypedRegister};
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Bus(E),
+    UnknownRegister(String),
+    /// 寄存器名.位域名
+    UnknownField(String),
+    /// 读只写寄存器或者写只读寄存器
+    Access {
+        register: String,
+        access: Access,
+    },
+    /// 值超出寄存器或位域的宽度
+    Overflow {
+        value: u64,
+        width: u8,
+    },
+}
+
+/// 按寄存器表访问一个器件
+pub struct Device<IF> {
+    iface: IF,
+    map: RegisterMap,
+}
+
+impl<IF: Interface> Device<IF> {
+    /// 先用 [`RegisterMap::validate`] 检查寄存器表, 手写的表也可能有超过 64 位的寄存器
+    pub fn new(iface: IF, map: RegisterMap) -> Result<Self, LoadError> {
+        map.validate()?;
+        Ok(Self { iface, map })
+    }
+
+    pub fn map(&self) -> &RegisterMap {
+        &self.map
+    }
+
+    pub fn release(self) -> IF {
+        self.iface
+    }
+
+    fn lookup(&self, name: &str) -> Result<Register, Error<IF::Error>> {
+        self.map
+            .register(name)
+            .cloned()
+            .ok_or_else(|| Error::UnknownRegister(name.to_string()))
+    }
+
+    fn read_at(&mut self, address: u32, width: u8) -> Result<u64, Error<IF::Error>> {
+        let mut buf = vec![0; width as usize / 8];
+        self.iface.read(address, &mut buf).map_err(Error::Bus)?;
+        if self.map.endian == Endian::Little {
+            buf.reverse();
+        }
+        Ok(buf.iter().fold(0, |v, &b| (v << 8) | u64::from(b)))
+    }
+
+    fn write_at(&mut self, address: u32, width: u8, value: u64) -> Result<(), Error<IF::Error>> {
+        let bytes = width as usize / 8;
+        let mut buf = value.to_be_bytes()[8 - bytes..].to_vec();
+        if self.map.endian == Endian::Little {
+            buf.reverse();
+        }
+        self.iface.write(address, &buf).map_err(Error::Bus)
+    }
+
+    pub fn read_register(&mut self, reg: &Register) -> Result<u64, Error<IF::Error>> {
+        if !reg.access.readable() {
+            return Err(Error::Access {
+                register: reg.name.clone(),
+                access: reg.access,
+            });
+        }
+        self.read_at(reg.address, reg.width)
+    }
+
+    pub fn write_register(&mut self, reg: &Register, value: u64) -> Result<(), Error<IF::Error>> {
+        if !reg.access.writable() {
+            return Err(Error::Access {
+                register: reg.name.clone(),
+                access: reg.access,
+            });
+        }
+        if value & !reg.mask() != 0 {
+            return Err(Error::Overflow {
+                value,
+                width: reg.width,
+            });
+        }
+        self.write_at(reg.address, reg.width, value)
+    }
+
+    pub fn read(&mut self, name: &str) -> Result<u64, Error<IF::Error>> {
+        let reg = self.lookup(name)?;
+        self.read_register(&reg)
+    }
+
+    pub fn write(&mut self, name: &str, value: u64) -> Result<(), Error<IF::Error>> {
+        let reg = self.lookup(name)?;
+        self.write_register(&reg, value)
+    }
+
+    pub fn read_field(&mut self, register: &str, field: &str) -> Result<u64, Error<IF::Error>> {
+        let reg = self.lookup(register)?;
+        let f = reg
+            .field(field)
+            .ok_or_else(|| Error::UnknownField(format!("{}.{}", register, field)))?;
+        Ok(f.extract(self.read_register(&reg)?))
+    }
+
+    /// 读-改-写. 只写寄存器从复位值开始改, 同一寄存器里的 W1C 位写 0 以免被误清
+    pub fn write_field(
+        &mut self,
+        register: &str,
+        field: &str,
+        value: u64,
+    ) -> Result<(), Error<IF::Error>> {
+        let reg = self.lookup(register)?;
+        let f = reg
+            .field(field)
+            .ok_or_else(|| Error::UnknownField(format!("{}.{}", register, field)))?;
+        if value & !(f.mask() >> f.offset) != 0 {
+            return Err(Error::Overflow {
+                value,
+                width: f.width,
+            });
+        }
+
+        let old = if reg.access.readable() {
+            self.read_register(&reg)?
+        } else {
+            reg.reset.unwrap_or(0)
+        };
+        let new = f.insert(old & !reg.w1c_mask(), value);
+        self.write_register(&reg, new)
+    }
+
+    pub fn read_typed<R: TypedRegister>(&mut self) -> Result<R, Error<IF::Error>> {
+        if !R::ACCESS.readable() {
+            return Err(Error::Access {
+                register: R::NAME.to_string(),
+                access: R::ACCESS,
+            });
+        }
+        Ok(R::from_bits(self.read_at(R::ADDRESS, R::WIDTH)?))
+    }
+
+    pub fn write_typed<R: TypedRegister>(&mut self, value: R) -> Result<(), Error<IF::Error>> {
+        if !R::ACCESS.writable() {
+            return Err(Error::Access {
+                register: R::NAME.to_string(),
+                access: R::ACCESS,
+            });
+        }
+        self.write_at(R::ADDRESS, R::WIDTH, value.bits())
+    }
+
+    /// 读出来交给 `f` 修改再写回
+    pub fn modify<R: TypedRegister>(
+        &mut self,
+        f: impl FnOnce(R) -> R,
+    ) -> Result<(), Error<IF::Error>> {
+        let value = self.read_typed::<R>()?;
+        self.write_typed(f(value))
+    }
+
+    /// 读出所有可读的寄存器
+    pub fn dump(&mut self) -> Result<Dump, Error<IF::Error>> {
+        let mut values = Vec::with_capacity(self.map.registers.len());
+        for reg in self.map.registers.clone().iter() {
+            values.push(if reg.access.readable() {
+                Some(self.read_register(reg)?)
+            } else {
+                None
+            });
+        }
+        Ok(Dump {
+            map: self.map.clone(),
+            values,
+        })
+    }
+}
+
+/// 一次导出的寄存器值, 只写寄存器为 `None`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dump {
+    pub map: RegisterMap,
+    pub values: Vec<Option<u64>>,
+}
+
+impl Dump {
+    pub fn get(&self, name: &str) -> Option<u64> {
+        let i = self.map.registers.iter().position(|r| r.name == name)?;
+        self.values[i]
+    }
+
+    /// 和另一次导出比较, 两次都读到的寄存器才比较
+    pub fn diff(&self, other: &Dump) -> Vec<Change> {
+        self.map
+            .registers
+            .iter()
+            .zip(self.values.iter())
+            .filter_map(|(reg, &before)| {
+                let after = other.get(&reg.name)?;
+                Change::new(reg, before?, after)
+            })
+            .collect()
+    }
+
+    /// 和复位值比较, 没有复位值的寄存器跳过
+    pub fn diff_reset(&self) -> Vec<Change> {
+        self.map
+            .registers
+            .iter()
+            .zip(self.values.iter())
+            .filter_map(|(reg, &value)| Change::new(reg, reg.reset?, value?))
+            .collect()
+    }
+}
+
+fn write_register(f: &mut fmt::Formatter<'_>, reg: &Register, value: Option<u64>) -> fmt::Result {
+    let digits = reg.bytes() * 2;
+    match value {
+        Some(v) => writeln!(
+            f,
+            "{:<16} @ {:#06x} = {:#0w$x}",
+            reg.name,
+            reg.address,
+            v,
+            w = digits + 2
+        )?,
+        None => writeln!(f, "{:<16} @ {:#06x} = (write only)", reg.name, reg.address)?,
+    }
+    if let Some(v) = value {
+        for field in reg.fields.iter() {
+            writeln!(f, "    {:<20} = {:#x}", field.to_string(), field.extract(v))?;
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Dump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.map.name)?;
+        for (reg, &value) in self.map.registers.iter().zip(self.values.iter()) {
+            write_register(f, reg, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// 一个寄存器的变化
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub register: Register,
+    pub before: u64,
+    pub after: u64,
+}
+
+impl Change {
+    fn new(reg: &Register, before: u64, after: u64) -> Option<Self> {
+        (before != after).then(|| Self {
+            register: reg.clone(),
+            before,
+            after,
+        })
+    }
+
+    /// 变了的位域: (名字, 之前, 之后)
+    pub fn fields(&self) -> Vec<(&str, u64, u64)> {
+        self.register
+            .fields
+            .iter()
+            .map(|f| {
+                (
+                    f.name.as_str(),
+                    f.extract(self.before),
+                    f.extract(self.after),
+                )
+            })
+            .filter(|(_, before, after)| before != after)
+            .collect()
+    }
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {:#x} -> {:#x}",
+            self.register.name, self.before, self.after
+        )?;
+        for (name, before, after) in self.fields() {
+            writeln!(f, "    {}: {:#x} -> {:#x}", name, before, after)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,113 @@
+//! 寄存器访问的总线部分, 寄存器地址按大端发出
+
+use embedded_hal::{
+    i2c::I2c,
+    spi::{Operation, SpiDevice},
+};
+
+pub trait Interface {
+    type Error;
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error>;
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error>;
+}
+
+fn encode_address(address: u32, bytes: usize) -> Vec<u8> {
+    address.to_be_bytes()[4 - bytes..].to_vec()
+}
+
+/// 先写寄存器地址, 再 Repeated Start 读; 写的时候地址后面直接跟数据
+pub struct I2cInterface<I2C> {
+    i2c: I2C,
+    address: u8,
+    address_bytes: usize,
+}
+
+impl<I2C: I2c> I2cInterface<I2C> {
+    /// 1 字节寄存器地址
+    pub fn new(i2c: I2C, address: u8) -> Self {
+        Self {
+            i2c,
+            address,
+            address_bytes: 1,
+        }
+    }
+
+    /// 寄存器地址的字节数, 1 ~ 4
+    pub fn with_address_bytes(mut self, bytes: usize) -> Self {
+        self.address_bytes = bytes.clamp(1, 4);
+        self
+    }
+
+    pub fn release(self) -> I2C {
+        self.i2c
+    }
+}
+
+impl<I2C: I2c> Interface for I2cInterface<I2C> {
+    type Error = I2C::Error;
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let obuf = encode_address(address, self.address_bytes);
+        self.i2c.write_read(self.address, &obuf, buf)
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let mut obuf = encode_address(address, self.address_bytes);
+        obuf.extend_from_slice(data);
+        self.i2c.write(self.address, &obuf)
+    }
+}
+
+/// 常见的 SPI 传感器格式: 第一个字节最高位为 1 表示读, 后面跟数据
+pub struct SpiInterface<SPI> {
+    spi: SPI,
+    address_bytes: usize,
+    read_mask: u8,
+    write_mask: u8,
+}
+
+impl<SPI: SpiDevice> SpiInterface<SPI> {
+    pub fn new(spi: SPI) -> Self {
+        Self {
+            spi,
+            address_bytes: 1,
+            read_mask: 0x80,
+            write_mask: 0x00,
+        }
+    }
+
+    pub fn with_address_bytes(mut self, bytes: usize) -> Self {
+        self.address_bytes = bytes.clamp(1, 4);
+        self
+    }
+
+    /// 读写时或到地址第一个字节上的位
+    pub fn with_masks(mut self, read_mask: u8, write_mask: u8) -> Self {
+        self.read_mask = read_mask;
+        self.write_mask = write_mask;
+        self
+    }
+
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+}
+
+impl<SPI: SpiDevice> Interface for SpiInterface<SPI> {
+    type Error = SPI::Error;
+
+    fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Self::Error> {
+        let mut obuf = encode_address(address, self.address_bytes);
+        obuf[0] |= self.read_mask;
+        self.spi
+            .transaction(&mut [Operation::Write(&obuf), Operation::Read(buf)])
+    }
+
+    fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Self::Error> {
+        let mut obuf = encode_address(address, self.address_bytes);
+        obuf[0] |= self.write_mask;
+        obuf.extend_from_slice(data);
+        self.spi.write(&obuf)
+    }
+}
@@ -0,0 +1,164 @@
+/// 在编译期描述寄存器表, 生成每个寄存器的类型、位域常量和运行期用的 `map()`
+///
+/// ```ignore
+/// ch347_rs::register_map! {
+///     pub mod mpu6050 : Big {
+///         /// 电源管理 1
+///         PWR_MGMT_1 @ 0x6B: u8, RW, reset = 0x40 {
+///             DEVICE_RESET: 7,
+///             SLEEP: 6,
+///             CLKSEL: 0..3,
+///         }
+///         WHO_AM_I @ 0x75: u8, RO, reset = 0x68 {}
+///     }
+/// }
+///
+/// let pwr = device.read_typed::<mpu6050::PWR_MGMT_1>()?;
+/// device.write_typed(pwr.with(mpu6050::PWR_MGMT_1::SLEEP, 0))?;
+/// ```
+///
+/// 位域写 `NAME: 低位` 或 `NAME: 低位..高位+1`, 后面可以跟 `as W1C` 等覆盖寄存器的访问属性.
+/// 位域超出寄存器宽度时编译不过:
+///
+/// ```compile_fail
+/// ch347_rs::register_map! {
+///     pub mod bad {
+///         CTRL @ 0x00: u8, RW {
+///             MODE: 6..10,
+///         }
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! register_map {
+    (
+        $(#[$meta:meta])*
+        $vis:vis mod $module:ident $(: $endian:ident)? {
+            $(
+                $(#[$rmeta:meta])*
+                $reg:ident @ $addr:literal : $ty:ty, $access:ident $(, reset = $reset:literal)? {
+                    $(
+                        $(#[$fmeta:meta])*
+                        $field:ident : $off:literal $(.. $end:literal)? $(as $faccess:ident)?
+                    ),* $(,)?
+                }
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        $vis mod $module {
+            $(
+                $(#[$rmeta])*
+                #[allow(non_camel_case_types)]
+                #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+                pub struct $reg(pub $ty);
+
+                impl $reg {
+                    $(
+                        $(#[$fmeta])*
+                        pub const $field: $crate::regmap::BitField = $crate::regmap::BitField {
+                            name: stringify!($field),
+                            offset: $off,
+                            width: {
+                                let _end = $off + 1;
+                                $(let _end = $end;)?
+                                _end - $off
+                            },
+                            access: {
+                                let _access: Option<$crate::regmap::Access> = None;
+                                $(let _access = Some($crate::regmap::Access::$faccess);)?
+                                _access
+                            },
+                        };
+                    )*
+
+                    pub fn get(&self, field: $crate::regmap::BitField) -> $ty {
+                        field.extract(u64::from(self.0)) as $ty
+                    }
+
+                    pub fn set(&mut self, field: $crate::regmap::BitField, value: $ty) {
+                        self.0 = field.insert(u64::from(self.0), u64::from(value)) as $ty;
+                    }
+
+                    pub fn with(mut self, field: $crate::regmap::BitField, value: $ty) -> Self {
+                        self.set(field, value);
+                        self
+                    }
+                }
+
+                // 位域和复位值超出寄存器宽度时编译不过
+                $(
+                    const _: () = assert!(
+                        $reg::$field.width > 0
+                            && $reg::$field.offset as usize + $reg::$field.width as usize
+                                <= ::core::mem::size_of::<$ty>() * 8,
+                        concat!(stringify!($reg), ".", stringify!($field), ": bits out of register"),
+                    );
+                )*
+                const _: () = {
+                    use $crate::regmap::TypedRegister;
+                    if let Some(reset) = $reg::RESET {
+                        assert!(
+                            reset >> ($reg::WIDTH as u32 - 1) >> 1 == 0,
+                            concat!(stringify!($reg), ": reset value wider than register"),
+                        );
+                    }
+                };
+
+                impl $crate::regmap::TypedRegister for $reg {
+                    const NAME: &'static str = stringify!($reg);
+                    const ADDRESS: u32 = $addr;
+                    const WIDTH: u8 = (::core::mem::size_of::<$ty>() * 8) as u8;
+                    const ACCESS: $crate::regmap::Access = $crate::regmap::Access::$access;
+                    const RESET: Option<u64> = {
+                        let _reset: Option<u64> = None;
+                        $(let _reset = Some($reset);)?
+                        _reset
+                    };
+
+                    fn from_bits(bits: u64) -> Self {
+                        Self(bits as $ty)
+                    }
+
+                    fn bits(&self) -> u64 {
+                        u64::from(self.0)
+                    }
+                }
+
+                impl Default for $reg {
+                    /// 复位值
+                    fn default() -> Self {
+                        use $crate::regmap::TypedRegister;
+                        Self(Self::RESET.unwrap_or(0) as $ty)
+                    }
+                }
+            )*
+
+            /// 运行期的寄存器表, 用于导出和比较
+            pub fn map() -> $crate::regmap::RegisterMap {
+                use $crate::regmap::TypedRegister;
+                $crate::regmap::RegisterMap {
+                    name: stringify!($module).to_string(),
+                    endian: {
+                        let _endian = $crate::regmap::Endian::Big;
+                        $(let _endian = $crate::regmap::Endian::$endian;)?
+                        _endian
+                    },
+                    registers: vec![
+                        $(
+                            $crate::regmap::Register {
+                                name: $reg::NAME.to_string(),
+                                address: $reg::ADDRESS,
+                                width: $reg::WIDTH,
+                                access: $reg::ACCESS,
+                                reset: $reg::RESET,
+                                description: None,
+                                fields: vec![$($reg::$field.into()),*],
+                            }
+                        ),*
+                    ],
+                }
+            }
+        }
+    };
+}
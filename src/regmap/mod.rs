@@ -0,0 +1,521 @@
+//! 寄存器表
+//!
+//! 用 TOML/YAML 文件或者 [`register_map!`](crate::register_map) 描述器件的寄存器和位域,
+//! 然后通过 I2C 或 SPI 按名字读写位域, 整体导出和比较寄存器.
+//! 从文件加载需要打开 `regmap-files` feature
+//!
+//! ```toml
+//! name = "mpu6050"
+//! endian = "big"
+//!
+//! [[register]]
+//! name = "PWR_MGMT_1"
+//! address = 0x6B
+//! reset = 0x40
+//! field = [{ name = "SLEEP", offset = 6 }, { name = "CLKSEL", offset = 0, width = 3 }]
+//! ```
+
+use std::fmt;
+#[cfg(feature = "regmap-files")]
+use std::path::Path;
+
+#[cfg(feature = "regmap-files")]
+use serde::Deserialize;
+
+mod device;
+mod interface;
+mod macros;
+
+pub use device::{Change, Device, Dump, Error};
+pub use interface::{I2cInterface, Interface, SpiInterface};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "regmap-files", derive(Deserialize))]
+#[cfg_attr(feature = "regmap-files", serde(rename_all = "lowercase"))]
+pub enum Access {
+    #[cfg_attr(feature = "regmap-files", serde(alias = "r"))]
+    RO,
+    #[cfg_attr(feature = "regmap-files", serde(alias = "w"))]
+    WO,
+    #[default]
+    RW,
+    /// 写 1 清零, 改写同一个寄存器的其他位域时这些位写 0
+    W1C,
+}
+
+impl Access {
+    pub fn readable(&self) -> bool {
+        *self != Access::WO
+    }
+
+    pub fn writable(&self) -> bool {
+        *self != Access::RO
+    }
+}
+
+/// 多字节寄存器在总线上的字节序
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "regmap-files", derive(Deserialize))]
+#[cfg_attr(feature = "regmap-files", serde(rename_all = "lowercase"))]
+pub enum Endian {
+    #[default]
+    Big,
+    Little,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "regmap-files", derive(Deserialize))]
+#[cfg_attr(feature = "regmap-files", serde(deny_unknown_fields))]
+pub struct Field {
+    pub name: String,
+    /// 最低位的位置
+    pub offset: u8,
+    #[cfg_attr(feature = "regmap-files", serde(default = "default_field_width"))]
+    pub width: u8,
+    /// 不写时跟寄存器一样
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub access: Option<Access>,
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub description: Option<String>,
+}
+
+#[cfg(feature = "regmap-files")]
+fn default_field_width() -> u8 {
+    1
+}
+
+impl Field {
+    pub fn mask(&self) -> u64 {
+        mask(self.offset, self.width)
+    }
+
+    pub fn extract(&self, value: u64) -> u64 {
+        (value & self.mask()) >> self.offset
+    }
+
+    /// 超出位宽的部分被丢掉
+    pub fn insert(&self, value: u64, field: u64) -> u64 {
+        (value & !self.mask()) | ((field << self.offset) & self.mask())
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.width == 1 {
+            write!(f, "{}[{}]", self.name, self.offset)
+        } else {
+            write!(
+                f,
+                "{}[{}:{}]",
+                self.name,
+                self.offset + self.width - 1,
+                self.offset
+            )
+        }
+    }
+}
+
+fn mask(offset: u8, width: u8) -> u64 {
+    let bits = if width >= 64 {
+        u64::MAX
+    } else {
+        (1 << width) - 1
+    };
+    bits << offset
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "regmap-files", derive(Deserialize))]
+#[cfg_attr(feature = "regmap-files", serde(deny_unknown_fields))]
+pub struct Register {
+    pub name: String,
+    pub address: u32,
+    /// 位数, 8 的倍数
+    #[cfg_attr(feature = "regmap-files", serde(default = "default_register_width"))]
+    pub width: u8,
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub access: Access,
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub reset: Option<u64>,
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub description: Option<String>,
+    #[cfg_attr(feature = "regmap-files", serde(default, alias = "field"))]
+    pub fields: Vec<Field>,
+}
+
+#[cfg(feature = "regmap-files")]
+fn default_register_width() -> u8 {
+    8
+}
+
+impl Register {
+    pub fn bytes(&self) -> usize {
+        self.width as usize / 8
+    }
+
+    pub fn mask(&self) -> u64 {
+        mask(0, self.width)
+    }
+
+    pub fn field(&self, name: &str) -> Option<&Field> {
+        self.fields.iter().find(|f| f.name == name)
+    }
+
+    pub fn field_access(&self, field: &Field) -> Access {
+        field.access.unwrap_or(self.access)
+    }
+
+    /// W1C 位域占的位
+    pub fn w1c_mask(&self) -> u64 {
+        self.fields
+            .iter()
+            .filter(|f| self.field_access(f) == Access::W1C)
+            .fold(0, |mask, f| mask | f.mask())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "regmap-files", derive(Deserialize))]
+#[cfg_attr(feature = "regmap-files", serde(deny_unknown_fields))]
+pub struct RegisterMap {
+    pub name: String,
+    #[cfg_attr(feature = "regmap-files", serde(default))]
+    pub endian: Endian,
+    #[cfg_attr(feature = "regmap-files", serde(default, alias = "register"))]
+    pub registers: Vec<Register>,
+}
+
+#[derive(Debug)]
+pub enum LoadError {
+    #[cfg(feature = "regmap-files")]
+    Io(std::io::Error),
+    #[cfg(feature = "regmap-files")]
+    Toml(toml::de::Error),
+    #[cfg(feature = "regmap-files")]
+    Yaml(serde_norway::Error),
+    /// 扩展名不是 toml/yaml/yml
+    #[cfg(feature = "regmap-files")]
+    Format,
+    /// 描述本身有问题, 比如位域超出寄存器宽度
+    Invalid(String),
+}
+
+impl RegisterMap {
+    #[cfg(feature = "regmap-files")]
+    pub fn from_toml(s: &str) -> Result<Self, LoadError> {
+        let map: Self = toml::from_str(s).map_err(LoadError::Toml)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    #[cfg(feature = "regmap-files")]
+    pub fn from_yaml(s: &str) -> Result<Self, LoadError> {
+        let map: Self = serde_norway::from_str(s).map_err(LoadError::Yaml)?;
+        map.validate()?;
+        Ok(map)
+    }
+
+    /// 按扩展名选择格式
+    #[cfg(feature = "regmap-files")]
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, LoadError> {
+        let path = path.as_ref();
+        let s = std::fs::read_to_string(path).map_err(LoadError::Io)?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("toml") => Self::from_toml(&s),
+            Some("yaml" | "yml") => Self::from_yaml(&s),
+            _ => Err(LoadError::Format),
+        }
+    }
+
+    pub fn register(&self, name: &str) -> Option<&Register> {
+        self.registers.iter().find(|r| r.name == name)
+    }
+
+    pub fn validate(&self) -> Result<(), LoadError> {
+        for (i, reg) in self.registers.iter().enumerate() {
+            if reg.width == 0 || reg.width % 8 != 0 || reg.width > 64 {
+                return Err(LoadError::Invalid(format!(
+                    "{}: width {} is not 8, 16, .. 64",
+                    reg.name, reg.width
+                )));
+            }
+            if self.registers[..i].iter().any(|r| r.name == reg.name) {
+                return Err(LoadError::Invalid(format!(
+                    "duplicate register {}",
+                    reg.name
+                )));
+            }
+            if reg.reset.is_some_and(|r| r & !reg.mask() != 0) {
+                return Err(LoadError::Invalid(format!(
+                    "{}: reset value wider than register",
+                    reg.name
+                )));
+            }
+            for (j, field) in reg.fields.iter().enumerate() {
+                let end = field.offset.checked_add(field.width);
+                if field.width == 0 || end.is_none_or(|end| end > reg.width) {
+                    return Err(LoadError::Invalid(format!(
+                        "{}.{}: bits out of register",
+                        reg.name, field.name
+                    )));
+                }
+                if reg.fields[..j].iter().any(|f| f.name == field.name) {
+                    return Err(LoadError::Invalid(format!(
+                        "{}: duplicate field {}",
+                        reg.name, field.name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// [`register_map!`](crate::register_map) 生成的寄存器类型
+pub trait TypedRegister: Sized {
+    const NAME: &'static str;
+    const ADDRESS: u32;
+    const WIDTH: u8;
+    const ACCESS: Access;
+    const RESET: Option<u64>;
+
+    fn from_bits(bits: u64) -> Self;
+    fn bits(&self) -> u64;
+}
+
+/// 编译期的位域, 作为寄存器类型的关联常量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitField {
+    pub name: &'static str,
+    pub offset: u8,
+    pub width: u8,
+    pub access: Option<Access>,
+}
+
+impl BitField {
+    pub const fn mask(&self) -> u64 {
+        let bits = if self.width >= 64 {
+            u64::MAX
+        } else {
+            (1 << self.width) - 1
+        };
+        bits << self.offset
+    }
+
+    pub fn extract(&self, value: u64) -> u64 {
+        (value & self.mask()) >> self.offset
+    }
+
+    pub fn insert(&self, value: u64, field: u64) -> u64 {
+        (value & !self.mask()) | ((field << self.offset) & self.mask())
+    }
+}
+
+impl From<BitField> for Field {
+    fn from(value: BitField) -> Self {
+        Self {
+            name: value.name.to_string(),
+            offset: value.offset,
+            width: value.width,
+            access: value.access,
+            description: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::register_map! {
+        #[allow(dead_code, clippy::upper_case_acronyms)]
+        mod sample : Little {
+            CTRL @ 0x10: u16, RW, reset = 0x0100 {
+                EN: 0,
+                MODE: 4..7,
+                IRQ: 15 as W1C,
+            }
+            STATUS @ 0x11: u8, RO {}
+        }
+    }
+
+    fn field(name: &str, offset: u8, width: u8) -> Field {
+        Field {
+            name: name.to_string(),
+            offset,
+            width,
+            access: None,
+            description: None,
+        }
+    }
+
+    fn map(width: u8, fields: Vec<Field>) -> RegisterMap {
+        RegisterMap {
+            name: "test".to_string(),
+            endian: Endian::Big,
+            registers: vec![Register {
+                name: "REG".to_string(),
+                address: 0,
+                width,
+                access: Access::RW,
+                reset: None,
+                description: None,
+                fields,
+            }],
+        }
+    }
+
+    fn invalid(map: &RegisterMap) -> bool {
+        matches!(map.validate(), Err(LoadError::Invalid(_)))
+    }
+
+    #[test]
+    fn validate_accepts_full_width() {
+        assert!(map(8, vec![field("A", 0, 8)]).validate().is_ok());
+        assert!(map(64, vec![field("A", 0, 64)]).validate().is_ok());
+        assert!(
+            map(16, vec![field("A", 0, 4), field("B", 15, 1)])
+                .validate()
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn validate_register_width() {
+        assert!(invalid(&map(0, vec![])));
+        assert!(invalid(&map(12, vec![])));
+        assert!(invalid(&map(72, vec![])));
+    }
+
+    #[test]
+    fn validate_field_zero_width() {
+        assert!(invalid(&map(8, vec![field("A", 0, 0)])));
+    }
+
+    #[test]
+    fn validate_field_past_register() {
+        assert!(invalid(&map(8, vec![field("A", 7, 2)])));
+        assert!(invalid(&map(16, vec![field("A", 16, 1)])));
+    }
+
+    #[test]
+    fn validate_field_overflow() {
+        // offset + width 在 u8 里溢出
+        assert!(invalid(&map(8, vec![field("A", 200, 100)])));
+        assert!(invalid(&map(64, vec![field("A", 255, 255)])));
+    }
+
+    #[test]
+    fn validate_duplicates() {
+        assert!(invalid(&map(8, vec![field("A", 0, 1), field("A", 1, 1)])));
+
+        let mut m = map(8, vec![]);
+        m.registers.push(m.registers[0].clone());
+        assert!(invalid(&m));
+    }
+
+    #[test]
+    fn validate_reset_value() {
+        let mut m = map(8, vec![]);
+        m.registers[0].reset = Some(0x100);
+        assert!(invalid(&m));
+    }
+
+    #[test]
+    fn macro_map() {
+        let m = sample::map();
+        assert!(m.validate().is_ok());
+        assert_eq!(m.name, "sample");
+        assert_eq!(m.endian, Endian::Little);
+
+        let ctrl = m.register("CTRL").unwrap();
+        assert_eq!(
+            (ctrl.address, ctrl.width, ctrl.reset),
+            (0x10, 16, Some(0x0100))
+        );
+        assert_eq!(ctrl.field("MODE").unwrap().mask(), 0x0070);
+        assert_eq!(ctrl.w1c_mask(), 0x8000);
+
+        let status = m.register("STATUS").unwrap();
+        assert_eq!(
+            (status.width, status.access, status.reset),
+            (8, Access::RO, None)
+        );
+    }
+
+    #[test]
+    fn macro_register_type() {
+        use sample::CTRL;
+
+        assert_eq!(CTRL::default(), CTRL(0x0100));
+        assert_eq!(CTRL::MODE.width, 3);
+        assert_eq!(CTRL::IRQ.access, Some(Access::W1C));
+
+        let r = CTRL(0).with(CTRL::EN, 1).with(CTRL::MODE, 5);
+        assert_eq!(r.bits(), 0x0051);
+        assert_eq!(r.get(CTRL::MODE), 5);
+        // 超出位域的部分被丢掉
+        assert_eq!(r.with(CTRL::MODE, 0xFF).get(CTRL::MODE), 7);
+    }
+
+    struct Nop;
+
+    impl Interface for Nop {
+        type Error = ();
+
+        fn read(&mut self, _: u32, _: &mut [u8]) -> Result<(), ()> {
+            Ok(())
+        }
+
+        fn write(&mut self, _: u32, _: &[u8]) -> Result<(), ()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn device_validates_map() {
+        // 72 位的寄存器到不了 write_at
+        assert!(matches!(
+            Device::new(Nop, map(72, vec![])),
+            Err(LoadError::Invalid(_))
+        ));
+        assert!(Device::new(Nop, map(64, vec![])).is_ok());
+        assert!(Device::new(Nop, sample::map()).is_ok());
+    }
+
+    #[cfg(feature = "regmap-files")]
+    #[test]
+    fn load_toml_and_yaml() {
+        let toml = r#"
+            name = "dev"
+            endian = "little"
+
+            [[register]]
+            name = "CTRL"
+            address = 0x10
+            width = 16
+            field = [{ name = "EN", offset = 0 }, { name = "MODE", offset = 4, width = 3, access = "w1c" }]
+        "#;
+        let yaml = "
+name: dev
+endian: little
+registers:
+  - name: CTRL
+    address: 0x10
+    width: 16
+    fields:
+      - { name: EN, offset: 0 }
+      - { name: MODE, offset: 4, width: 3, access: w1c }
+";
+        let a = RegisterMap::from_toml(toml).unwrap();
+        let b = RegisterMap::from_yaml(yaml).unwrap();
+        assert_eq!(a, b);
+        assert_eq!(a.register("CTRL").unwrap().w1c_mask(), 0x0070);
+
+        let bad = toml.replace("width = 3", "width = 255");
+        assert!(matches!(
+            RegisterMap::from_toml(&bad),
+            Err(LoadError::Invalid(_))
+        ));
+    }
+}
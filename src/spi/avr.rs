@@ -0,0 +1,701 @@
+//! AVR 串行编程 (ISP)
+//!
+//! RESET 拉低后用 SPI 发 4 字节的指令, SCK 要低于目标时钟的 1/4.
+//! CH347 最慢只有 468.75kHz (speed = 7), 目标时钟至少要 2MHz;
+//! 出厂 1MHz 内部 RC 的芯片 (CKDIV8) 编程不了. 片选没有用, 随便接一个空脚.
+//!
+//! Programming Enable 的第 3 个字节回显 0x53 才算同步上, 否则给 RESET 一个正脉冲再试.
+//! Flash 按字寻址, 先把一页装进页缓冲再整页写入; 写之前要整片擦除.
+//! 每次 USB 往返都很慢, 所以装页和读 Flash 时把很多条指令拼成一次传输
+
+use std::collections::BTreeMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::digital::OutputPin;
+use embedded_hal::spi::SpiDevice;
+
+use crate::ihex::Image;
+use crate::util::first_mismatch;
+
+pub mod command {
+    pub const PROGRAMMING_ENABLE: [u8; 2] = [0xAC, 0x53];
+    pub const CHIP_ERASE: [u8; 2] = [0xAC, 0x80];
+    pub const POLL_READY: u8 = 0xF0;
+    pub const LOAD_EXTENDED_ADDRESS: u8 = 0x4D;
+    pub const LOAD_PAGE_LOW: u8 = 0x40;
+    pub const LOAD_PAGE_HIGH: u8 = 0x48;
+    pub const WRITE_PAGE: u8 = 0x4C;
+    pub const READ_FLASH_LOW: u8 = 0x20;
+    pub const READ_FLASH_HIGH: u8 = 0x28;
+    pub const READ_EEPROM: u8 = 0xA0;
+    pub const WRITE_EEPROM: u8 = 0xC0;
+    pub const READ_SIGNATURE: u8 = 0x30;
+    pub const READ_CALIBRATION: u8 = 0x38;
+}
+
+/// 同步失败后给 RESET 正脉冲重试的次数
+const SYNC_RETRIES: usize = 32;
+const ERASE_TIMEOUT: Duration = Duration::from_millis(50);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(20);
+
+/// 读 Flash 时每次传输的字节数
+const READ_CHUNK: usize = 256;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    /// 设置 RESET 脚失败
+    Reset,
+    /// Programming Enable 一直没有回显 0x53
+    Sync,
+    /// 签名不在 [`PARTS`] 里
+    UnknownSignature([u8; 3]),
+    OutOfRange,
+    /// 这个型号没有扩展熔丝位
+    Unsupported,
+    /// 等 RDY/BSY 超时
+    Timeout,
+    Verify {
+        address: u32,
+        expected: u8,
+        found: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    pub name: &'static str,
+    pub signature: [u8; 3],
+    pub flash_size: u32,
+    /// Flash 页大小, 字节
+    pub page_size: u32,
+    pub eeprom_size: u32,
+    /// 有扩展熔丝
+    pub extended_fuse: bool,
+}
+
+macro_rules! part {
+    ($name:literal, $sig:expr, $flash:expr, $page:expr, $eeprom:expr, $ext:expr) => {
+        Part {
+            name: $name,
+            signature: $sig,
+            flash_size: $flash,
+            page_size: $page,
+            eeprom_size: $eeprom,
+            extended_fuse: $ext,
+        }
+    };
+}
+
+pub const PARTS: &[Part] = &[
+    part!("ATtiny13A", [0x1E, 0x90, 0x07], 1024, 32, 64, false),
+    part!("ATtiny25", [0x1E, 0x91, 0x08], 2048, 32, 128, true),
+    part!("ATtiny45", [0x1E, 0x92, 0x06], 4096, 64, 256, true),
+    part!("ATtiny85", [0x1E, 0x93, 0x0B], 8192, 64, 512, true),
+    part!("ATtiny24", [0x1E, 0x91, 0x0B], 2048, 32, 128, true),
+    part!("ATtiny44", [0x1E, 0x92, 0x07], 4096, 64, 256, true),
+    part!("ATtiny84", [0x1E, 0x93, 0x0C], 8192, 64, 512, true),
+    part!("ATtiny2313A", [0x1E, 0x91, 0x0A], 2048, 32, 128, true),
+    part!("ATtiny4313", [0x1E, 0x92, 0x0D], 4096, 64, 256, true),
+    part!("ATmega8", [0x1E, 0x93, 0x07], 8192, 64, 512, false),
+    part!("ATmega16", [0x1E, 0x94, 0x03], 16384, 128, 512, false),
+    part!("ATmega32", [0x1E, 0x95, 0x02], 32768, 128, 1024, false),
+    part!("ATmega48PA", [0x1E, 0x92, 0x0A], 4096, 64, 256, true),
+    part!("ATmega88PA", [0x1E, 0x93, 0x0F], 8192, 64, 512, true),
+    part!("ATmega168PA", [0x1E, 0x94, 0x0B], 16384, 128, 512, true),
+    part!("ATmega328", [0x1E, 0x95, 0x14], 32768, 128, 1024, true),
+    part!("ATmega328P", [0x1E, 0x95, 0x0F], 32768, 128, 1024, true),
+    part!("ATmega32U4", [0x1E, 0x95, 0x87], 32768, 128, 1024, true),
+    part!("ATmega644P", [0x1E, 0x96, 0x0A], 65536, 256, 2048, true),
+    part!("ATmega1284P", [0x1E, 0x97, 0x05], 131072, 256, 4096, true),
+    part!("ATmega2560", [0x1E, 0x98, 0x01], 262144, 256, 4096, true),
+];
+
+impl Part {
+    pub fn find(signature: [u8; 3]) -> Option<&'static Part> {
+        PARTS.iter().find(|p| p.signature == signature)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fuse {
+    Low,
+    High,
+    Extended,
+    Lock,
+}
+
+impl Fuse {
+    fn read(&self) -> [u8; 4] {
+        match self {
+            Fuse::Low => [0x50, 0x00, 0x00, 0x00],
+            Fuse::High => [0x58, 0x08, 0x00, 0x00],
+            Fuse::Extended => [0x50, 0x08, 0x00, 0x00],
+            Fuse::Lock => [0x58, 0x00, 0x00, 0x00],
+        }
+    }
+
+    fn write(&self, value: u8) -> [u8; 4] {
+        let op = match self {
+            Fuse::Low => 0xA0,
+            Fuse::High => 0xA8,
+            Fuse::Extended => 0xA4,
+            Fuse::Lock => 0xE0,
+        };
+        [0xAC, op, 0x00, value]
+    }
+}
+
+pub struct Programmer<SPI, RST> {
+    spi: SPI,
+    reset: RST,
+    part: Part,
+    /// 上一次发的扩展地址, 超过 128K 的芯片用. 传输失败时清掉
+    extended: Option<u8>,
+}
+
+impl<SPI: SpiDevice, RST: OutputPin> Programmer<SPI, RST> {
+    /// 进入编程模式, 读签名并在 [`PARTS`] 里查型号
+    pub fn new(spi: SPI, reset: RST) -> Result<Self, Error<SPI::Error>> {
+        let mut programmer = Self {
+            spi,
+            reset,
+            part: PARTS[0],
+            extended: None,
+        };
+        programmer.enter()?;
+        let signature = match programmer.read_signature() {
+            Ok(signature) => signature,
+            Err(e) => {
+                programmer.leave()?;
+                return Err(e);
+            }
+        };
+        match Part::find(signature) {
+            Some(part) => programmer.part = *part,
+            None => {
+                programmer.leave()?;
+                return Err(Error::UnknownSignature(signature));
+            }
+        }
+        Ok(programmer)
+    }
+
+    /// 表里没有的型号, 直接给出参数
+    pub fn with_part(spi: SPI, reset: RST, part: Part) -> Result<Self, Error<SPI::Error>> {
+        let mut programmer = Self {
+            spi,
+            reset,
+            part,
+            extended: None,
+        };
+        programmer.enter()?;
+        Ok(programmer)
+    }
+
+    /// 放开 RESET, 目标开始运行
+    pub fn release(mut self) -> Result<(SPI, RST), Error<SPI::Error>> {
+        self.leave()?;
+        Ok((self.spi, self.reset))
+    }
+
+    pub fn part(&self) -> &Part {
+        &self.part
+    }
+
+    fn set_reset(&mut self, high: bool) -> Result<(), Error<SPI::Error>> {
+        let result = if high {
+            self.reset.set_high()
+        } else {
+            self.reset.set_low()
+        };
+        result.map_err(|_| Error::Reset)
+    }
+
+    /// 同步失败时放开 RESET, 不让目标一直停在复位里
+    fn enter(&mut self) -> Result<(), Error<SPI::Error>> {
+        let result = self.sync();
+        if result.is_err() {
+            self.leave()?;
+        }
+        result
+    }
+
+    fn sync(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.set_reset(false)?;
+        sleep(Duration::from_millis(20));
+        for _ in 0..SYNC_RETRIES {
+            let [a, b] = command::PROGRAMMING_ENABLE;
+            if self.instruction([a, b, 0x00, 0x00])?[2] == b {
+                return Ok(());
+            }
+            // 正脉冲至少两个 CPU 时钟
+            self.set_reset(true)?;
+            sleep(Duration::from_millis(1));
+            self.set_reset(false)?;
+            sleep(Duration::from_millis(20));
+        }
+        Err(Error::Sync)
+    }
+
+    fn leave(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.set_reset(true)
+    }
+
+    /// 一条指令, 返回收到的 4 个字节
+    fn instruction(&mut self, cmd: [u8; 4]) -> Result<[u8; 4], Error<SPI::Error>> {
+        let mut buf = cmd;
+        self.spi.transfer_in_place(&mut buf).map_err(Error::Spi)?;
+        Ok(buf)
+    }
+
+    /// 多条指令拼成一次传输, 返回每条指令的第 4 个字节
+    fn batch(&mut self, cmds: &[[u8; 4]]) -> Result<Vec<u8>, Error<SPI::Error>> {
+        let mut buf = cmds.concat();
+        if let Err(e) = self.spi.transfer_in_place(&mut buf) {
+            // 不知道扩展地址有没有发到芯片, 下次重新发
+            self.extended = None;
+            return Err(Error::Spi(e));
+        }
+        Ok(buf.chunks_exact(4).map(|r| r[3]).collect())
+    }
+
+    /// 轮询 RDY/BSY
+    fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error<SPI::Error>> {
+        let start = Instant::now();
+        loop {
+            if self.instruction([command::POLL_READY, 0x00, 0x00, 0x00])?[3] & 0x01 == 0 {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_micros(500));
+        }
+    }
+
+    pub fn read_signature(&mut self) -> Result<[u8; 3], Error<SPI::Error>> {
+        let sig = self.batch(&[
+            [command::READ_SIGNATURE, 0x00, 0x00, 0x00],
+            [command::READ_SIGNATURE, 0x00, 0x01, 0x00],
+            [command::READ_SIGNATURE, 0x00, 0x02, 0x00],
+        ])?;
+        Ok([sig[0], sig[1], sig[2]])
+    }
+
+    /// 内部 RC 的校准字节
+    pub fn read_calibration(&mut self) -> Result<u8, Error<SPI::Error>> {
+        Ok(self.instruction([command::READ_CALIBRATION, 0x00, 0x00, 0x00])?[3])
+    }
+
+    /// 擦除 Flash 和 EEPROM (EESAVE 熔丝置位时保留 EEPROM), 同时清掉锁定位
+    pub fn chip_erase(&mut self) -> Result<(), Error<SPI::Error>> {
+        let [a, b] = command::CHIP_ERASE;
+        self.instruction([a, b, 0x00, 0x00])?;
+        self.wait_ready(ERASE_TIMEOUT)?;
+        // 擦除后有的芯片要重新进入编程模式
+        self.extended = None;
+        self.leave()?;
+        self.enter()
+    }
+
+    pub fn read_fuse(&mut self, fuse: Fuse) -> Result<u8, Error<SPI::Error>> {
+        self.check_fuse(fuse)?;
+        Ok(self.instruction(fuse.read())?[3])
+    }
+
+    /// 熔丝位写 0 表示编程, 写错了可能再也进不了 ISP
+    pub fn write_fuse(&mut self, fuse: Fuse, value: u8) -> Result<(), Error<SPI::Error>> {
+        self.check_fuse(fuse)?;
+        self.instruction(fuse.write(value))?;
+        self.wait_ready(WRITE_TIMEOUT)
+    }
+
+    fn check_fuse(&self, fuse: Fuse) -> Result<(), Error<SPI::Error>> {
+        if fuse == Fuse::Extended && !self.part.extended_fuse {
+            return Err(Error::Unsupported);
+        }
+        Ok(())
+    }
+
+    fn check_range(&self, address: u32, len: usize, size: u32) -> Result<(), Error<SPI::Error>> {
+        if u64::from(address) + len as u64 > u64::from(size) {
+            return Err(Error::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// 超过 64K 字时先设置扩展地址
+    fn extended_address(&mut self, word: u32) -> Option<[u8; 4]> {
+        if self.part.flash_size <= 0x20000 {
+            return None;
+        }
+        let ext = (word >> 16) as u8;
+        if self.extended == Some(ext) {
+            return None;
+        }
+        self.extended = Some(ext);
+        Some([command::LOAD_EXTENDED_ADDRESS, 0x00, ext, 0x00])
+    }
+
+    pub fn read_flash(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, buf.len(), self.part.flash_size)?;
+        let mut done = 0;
+        while done < buf.len() {
+            let at = address + done as u32;
+            // 不跨 128K 边界
+            let len = (READ_CHUNK - at as usize % READ_CHUNK).min(buf.len() - done);
+            let mut cmds = Vec::with_capacity(len + 1);
+            cmds.extend(self.extended_address(at / 2));
+            let skip = cmds.len();
+            for a in at..at + len as u32 {
+                let op = if a & 1 == 0 {
+                    command::READ_FLASH_LOW
+                } else {
+                    command::READ_FLASH_HIGH
+                };
+                let [_, _, hi, lo] = (a / 2).to_be_bytes();
+                cmds.push([op, hi, lo, 0x00]);
+            }
+            let data = self.batch(&cmds)?;
+            buf[done..done + len].copy_from_slice(&data[skip..]);
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// 装一整页再写入, `page` 长度等于页大小, `address` 按页对齐
+    fn write_page(&mut self, address: u32, page: &[u8]) -> Result<(), Error<SPI::Error>> {
+        let word = address / 2;
+        let mut cmds = Vec::with_capacity(page.len() + 2);
+        cmds.extend(self.extended_address(word));
+        for (i, pair) in page.chunks(2).enumerate() {
+            let offset = i as u8;
+            cmds.push([command::LOAD_PAGE_LOW, 0x00, offset, pair[0]]);
+            cmds.push([command::LOAD_PAGE_HIGH, 0x00, offset, pair[1]]);
+        }
+        let [_, _, hi, lo] = word.to_be_bytes();
+        cmds.push([command::WRITE_PAGE, hi, lo, 0x00]);
+        self.batch(&cmds)?;
+        self.wait_ready(WRITE_TIMEOUT)
+    }
+
+    /// 写入已经擦除过的 Flash, 不满一页的部分补 0xFF.
+    /// 全 0xFF 的页跳过
+    pub fn write_flash(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, data.len(), self.part.flash_size)?;
+        let pages = pages(&[(address, data)], self.part.page_size);
+        for (at, page) in pages {
+            if page.iter().any(|&b| b != 0xFF) {
+                self.write_page(at, &page)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn read_eeprom(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, buf.len(), self.part.eeprom_size)?;
+        for (i, chunk) in buf.chunks_mut(READ_CHUNK).enumerate() {
+            let base = address + (i * READ_CHUNK) as u32;
+            let cmds: Vec<[u8; 4]> = (base..base + chunk.len() as u32)
+                .map(|a| {
+                    let [_, _, hi, lo] = a.to_be_bytes();
+                    [command::READ_EEPROM, hi, lo, 0x00]
+                })
+                .collect();
+            chunk.copy_from_slice(&self.batch(&cmds)?);
+        }
+        Ok(())
+    }
+
+    /// 按字节写, 内容一样的字节跳过
+    pub fn write_eeprom(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, data.len(), self.part.eeprom_size)?;
+        let mut old = vec![0; data.len()];
+        self.read_eeprom(address, &mut old)?;
+        for (i, (&new, &old)) in data.iter().zip(old.iter()).enumerate() {
+            if new != old {
+                let [_, _, hi, lo] = (address + i as u32).to_be_bytes();
+                self.instruction([command::WRITE_EEPROM, hi, lo, new])?;
+                self.wait_ready(WRITE_TIMEOUT)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 整片擦除后按页写入镜像, `progress(done, total)`
+    pub fn write_image(
+        &mut self,
+        image: &Image,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        for seg in image.segments.iter() {
+            self.check_range(seg.address, seg.data.len(), self.part.flash_size)?;
+        }
+        self.chip_erase()?;
+
+        let segments: Vec<(u32, &[u8])> = image
+            .segments
+            .iter()
+            .map(|s| (s.address, s.data.as_slice()))
+            .collect();
+        let pages = pages(&segments, self.part.page_size);
+        let total = pages.len();
+        for (i, (at, page)) in pages.into_iter().enumerate() {
+            if page.iter().any(|&b| b != 0xFF) {
+                self.write_page(at, &page)?;
+            }
+            progress(i + 1, total);
+        }
+        Ok(())
+    }
+
+    /// 逐段读 Flash 比较, 镜像里没有的地址不管
+    pub fn verify_image(
+        &mut self,
+        image: &Image,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        let total = image.len();
+        let mut done = 0;
+        let mut buf = [0; READ_CHUNK];
+        for seg in image.segments.iter() {
+            for (i, expected) in seg.data.chunks(READ_CHUNK).enumerate() {
+                let address = seg.address + (i * READ_CHUNK) as u32;
+                let found = &mut buf[..expected.len()];
+                self.read_flash(address, found)?;
+                if let Some((j, expected, found)) = first_mismatch(expected, found) {
+                    return Err(Error::Verify {
+                        address: address + j as u32,
+                        expected,
+                        found,
+                    });
+                }
+                done += expected.len();
+                progress(done, total);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 把数据按页拆开, 同一页里的几段合在一起, 空的部分补 0xFF
+fn pages(segments: &[(u32, &[u8])], page_size: u32) -> BTreeMap<u32, Vec<u8>> {
+    let mut pages: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    for &(address, data) in segments {
+        for (i, &byte) in data.iter().enumerate() {
+            let at = address + i as u32;
+            let page = pages
+                .entry(at - at % page_size)
+                .or_insert_with(|| vec![0xFF; page_size as usize]);
+            page[(at % page_size) as usize] = byte;
+        }
+    }
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use embedded_hal::digital;
+    use embedded_hal::spi::{ErrorKind, ErrorType, Operation};
+
+    use super::*;
+
+    /// 模拟的目标芯片, 按 4 字节指令应答, 记下收到的每条指令
+    struct Mock {
+        part: &'static Part,
+        flash: Vec<u8>,
+        extended: u8,
+        log: Vec<[u8; 4]>,
+        /// 下一次传输失败
+        fail: bool,
+    }
+
+    impl Mock {
+        fn new(name: &str) -> Self {
+            let part = PARTS.iter().find(|p| p.name == name).unwrap();
+            Self {
+                part,
+                flash: (0..part.flash_size).map(|a| (a % 251) as u8).collect(),
+                extended: 0,
+                log: Vec::new(),
+                fail: false,
+            }
+        }
+
+        fn instruction(&mut self, cmd: &mut [u8]) {
+            let [op, a, b, c] = [cmd[0], cmd[1], cmd[2], cmd[3]];
+            self.log.push([op, a, b, c]);
+            let word = (usize::from(self.extended) << 16) | usize::from(u16::from_be_bytes([a, b]));
+            // 第 3 个字节回显第 2 个, Programming Enable 靠它同步
+            cmd[2] = a;
+            cmd[3] = match op {
+                command::READ_SIGNATURE => self.part.signature[usize::from(b)],
+                command::LOAD_EXTENDED_ADDRESS => {
+                    self.extended = b;
+                    0x00
+                }
+                command::READ_FLASH_LOW => self.flash[word * 2],
+                command::READ_FLASH_HIGH => self.flash[word * 2 + 1],
+                _ => 0x00,
+            };
+        }
+
+        fn extended(&self) -> Vec<u8> {
+            self.log
+                .iter()
+                .filter(|cmd| cmd[0] == command::LOAD_EXTENDED_ADDRESS)
+                .map(|cmd| cmd[2])
+                .collect()
+        }
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl SpiDevice for Mock {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            if std::mem::take(&mut self.fail) {
+                return Err(ErrorKind::Other);
+            }
+            for op in operations {
+                let Operation::TransferInPlace(buf) = op else {
+                    return Err(ErrorKind::Other);
+                };
+                for cmd in buf.chunks_exact_mut(4) {
+                    self.instruction(cmd);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    struct Pin;
+
+    impl digital::ErrorType for Pin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for Pin {
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn find() {
+        let cases = [
+            ([0x1E, 0x95, 0x0F], Some("ATmega328P")),
+            ([0x1E, 0x95, 0x14], Some("ATmega328")),
+            ([0x1E, 0x93, 0x0B], Some("ATtiny85")),
+            ([0x1E, 0x98, 0x01], Some("ATmega2560")),
+            ([0x1E, 0x95, 0x00], None),
+            ([0x00, 0x00, 0x00], None),
+            ([0xFF, 0xFF, 0xFF], None),
+        ];
+        for (signature, name) in cases {
+            assert_eq!(
+                Part::find(signature).map(|p| p.name),
+                name,
+                "{signature:02x?}"
+            );
+        }
+        // 签名不能重复, 否则后面的型号永远认不出来
+        for (i, part) in PARTS.iter().enumerate() {
+            assert_eq!(Part::find(part.signature), Some(&PARTS[i]), "{}", part.name);
+            assert!(part.flash_size % part.page_size == 0, "{}", part.name);
+        }
+    }
+
+    #[test]
+    fn fuse_opcodes() {
+        let cases = [
+            (Fuse::Low, [0x50, 0x00], [0xAC, 0xA0]),
+            (Fuse::High, [0x58, 0x08], [0xAC, 0xA8]),
+            (Fuse::Extended, [0x50, 0x08], [0xAC, 0xA4]),
+            (Fuse::Lock, [0x58, 0x00], [0xAC, 0xE0]),
+        ];
+        for (fuse, [r0, r1], [w0, w1]) in cases {
+            assert_eq!(fuse.read(), [r0, r1, 0x00, 0x00], "{fuse:?}");
+            assert_eq!(fuse.write(0x5A), [w0, w1, 0x00, 0x5A], "{fuse:?}");
+        }
+    }
+
+    #[test]
+    fn pages_merge() {
+        let segments: [(u32, &[u8]); 3] = [(0x10, &[1, 2]), (0x7E, &[3, 4, 5, 6]), (0x20, &[7])];
+        let pages = pages(&segments, 64);
+        assert_eq!(
+            pages.keys().copied().collect::<Vec<_>>(),
+            [0x00, 0x40, 0x80]
+        );
+
+        let mut first = vec![0xFF; 64];
+        first[0x10] = 1;
+        first[0x11] = 2;
+        first[0x20] = 7;
+        assert_eq!(pages[&0x00], first);
+        let mut second = vec![0xFF; 64];
+        second[0x3E] = 3;
+        second[0x3F] = 4;
+        assert_eq!(pages[&0x40], second);
+        let mut third = vec![0xFF; 64];
+        third[0] = 5;
+        third[1] = 6;
+        assert_eq!(pages[&0x80], third);
+    }
+
+    #[test]
+    fn extended_address() {
+        let mut avr = Programmer::new(Mock::new("ATmega2560"), Pin).unwrap();
+        assert_eq!(avr.part().name, "ATmega2560");
+
+        // 跨过 64K 字的边界, 先发扩展地址 0 再换成 1
+        let mut buf = [0; 4];
+        avr.read_flash(0x1FFFE, &mut buf).unwrap();
+        let expected: Vec<u8> = (0x1FFFE..0x20002u32).map(|a| (a % 251) as u8).collect();
+        assert_eq!(buf[..], expected[..]);
+        // 扩展地址没变时不重发
+        avr.read_flash(0x20010, &mut buf).unwrap();
+        avr.read_flash(0x100, &mut buf).unwrap();
+
+        let (mock, _) = avr.release().unwrap();
+        assert_eq!(mock.extended(), [0, 1, 0]);
+
+        // 128K 以内的芯片不发扩展地址
+        let mut avr = Programmer::new(Mock::new("ATmega328P"), Pin).unwrap();
+        avr.read_flash(0x7FFC, &mut buf).unwrap();
+        let (mock, _) = avr.release().unwrap();
+        assert!(mock.extended().is_empty());
+    }
+
+    #[test]
+    fn extended_address_after_error() {
+        let mut avr = Programmer::new(Mock::new("ATmega2560"), Pin).unwrap();
+        let mut buf = [0; 4];
+        avr.read_flash(0x20000, &mut buf).unwrap();
+
+        avr.spi.fail = true;
+        assert!(matches!(
+            avr.read_flash(0x100, &mut buf),
+            Err(Error::Spi(ErrorKind::Other))
+        ));
+        // 失败的那次没发出去, 芯片还停在 1, 重读时要重新发 0
+        avr.read_flash(0x100, &mut buf).unwrap();
+        assert_eq!(
+            buf,
+            [0x100, 0x101, 0x102, 0x103].map(|a: u32| (a % 251) as u8)
+        );
+
+        let (mock, _) = avr.release().unwrap();
+        assert_eq!(mock.extended(), [1, 0]);
+    }
+}
@@ -0,0 +1,425 @@
+//! 25xx 系列 SPI EEPROM 和 FRAM
+//!
+//! - 25xx010 ~ 25xx040 和 FM25L04 用 1 字节地址, 512 字节的芯片把 A8 放进读写命令的第 3 位
+//! - 1K ~ 64K 用 2 字节地址, 更大的用 3 字节
+//! - 每次写之前都要 WREN, 写完 WEL 自动清零
+//! - EEPROM 按页对齐写, 每页写完轮询 WIP; FRAM 没有页也没有写周期, 一次写完
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::util::first_mismatch;
+
+pub mod command {
+    pub const WRITE_STATUS: u8 = 0x01;
+    pub const WRITE: u8 = 0x02;
+    pub const READ: u8 = 0x03;
+    pub const WRITE_DISABLE: u8 = 0x04;
+    pub const READ_STATUS: u8 = 0x05;
+    pub const WRITE_ENABLE: u8 = 0x06;
+}
+
+/// 状态寄存器
+pub const STATUS_WIP: u8 = 0x01;
+pub const STATUS_WEL: u8 = 0x02;
+/// BP1:BP0, 保护 无/高 1/4/高 1/2/全部
+pub const STATUS_BP: u8 = 0x0C;
+pub const STATUS_WPEN: u8 = 0x80;
+
+/// 内部写周期一般 5ms, 最多等这么久
+const WRITE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Chip {
+    /// 25AA/25LC EEPROM
+    Eeprom25x010,
+    Eeprom25x020,
+    Eeprom25x040,
+    Eeprom25x080,
+    Eeprom25x160,
+    Eeprom25x320,
+    Eeprom25x640,
+    Eeprom25x128,
+    Eeprom25x256,
+    Eeprom25x512,
+    Eeprom25x1024,
+    /// Cypress/Ramtron FRAM
+    Fm25L04,
+    Fm25L16,
+    Fm25CL64,
+    Fm25V02,
+    Fm25V10,
+    /// Fujitsu FRAM
+    Mb85Rs64,
+    Mb85Rs256,
+    Mb85Rs1Mt,
+    Mb85Rs2Mt,
+}
+
+impl Chip {
+    /// 容量, 字节
+    pub fn size(&self) -> usize {
+        match self {
+            Chip::Eeprom25x010 => 128,
+            Chip::Eeprom25x020 => 256,
+            Chip::Eeprom25x040 | Chip::Fm25L04 => 512,
+            Chip::Eeprom25x080 => 1024,
+            Chip::Eeprom25x160 | Chip::Fm25L16 => 2048,
+            Chip::Eeprom25x320 => 4096,
+            Chip::Eeprom25x640 | Chip::Fm25CL64 | Chip::Mb85Rs64 => 8192,
+            Chip::Eeprom25x128 => 16384,
+            Chip::Eeprom25x256 | Chip::Fm25V02 | Chip::Mb85Rs256 => 32768,
+            Chip::Eeprom25x512 => 65536,
+            Chip::Eeprom25x1024 | Chip::Fm25V10 | Chip::Mb85Rs1Mt => 131072,
+            Chip::Mb85Rs2Mt => 262144,
+        }
+    }
+
+    pub fn is_fram(&self) -> bool {
+        matches!(
+            self,
+            Chip::Fm25L04
+                | Chip::Fm25L16
+                | Chip::Fm25CL64
+                | Chip::Fm25V02
+                | Chip::Fm25V10
+                | Chip::Mb85Rs64
+                | Chip::Mb85Rs256
+                | Chip::Mb85Rs1Mt
+                | Chip::Mb85Rs2Mt
+        )
+    }
+
+    /// FRAM 没有页, 按整片算
+    pub fn page_size(&self) -> usize {
+        match self {
+            _ if self.is_fram() => self.size(),
+            Chip::Eeprom25x010
+            | Chip::Eeprom25x020
+            | Chip::Eeprom25x040
+            | Chip::Eeprom25x080
+            | Chip::Eeprom25x160 => 16,
+            Chip::Eeprom25x320 | Chip::Eeprom25x640 => 32,
+            Chip::Eeprom25x128 | Chip::Eeprom25x256 => 64,
+            Chip::Eeprom25x512 => 128,
+            _ => 256,
+        }
+    }
+
+    /// 地址的字节数
+    pub fn address_bytes(&self) -> usize {
+        match self.size() {
+            0..=512 => 1,
+            513..=65536 => 2,
+            _ => 3,
+        }
+    }
+
+    /// 命令和地址, 1 字节地址的芯片把 A8 放进命令
+    fn header(&self, opcode: u8, offset: usize) -> Vec<u8> {
+        let n = self.address_bytes();
+        let a8 = if n == 1 {
+            ((offset >> 8) as u8 & 1) << 3
+        } else {
+            0
+        };
+        let mut header = vec![opcode | a8];
+        header.extend_from_slice(&(offset as u32).to_be_bytes()[4 - n..]);
+        header
+    }
+}
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    /// 地址超出芯片容量
+    OutOfRange,
+    /// 等 WIP 清零超时
+    Timeout,
+    /// 写使能之后 WEL 没有置位, 可能是 WP 脚拉低了
+    WriteEnable,
+    /// 校验失败的第一个地址
+    Verify {
+        offset: usize,
+        expected: u8,
+        found: u8,
+    },
+}
+
+pub struct Eeprom<SPI> {
+    spi: SPI,
+    chip: Chip,
+}
+
+impl<SPI: SpiDevice> Eeprom<SPI> {
+    pub fn new(spi: SPI, chip: Chip) -> Self {
+        Self { spi, chip }
+    }
+
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    pub fn chip(&self) -> Chip {
+        self.chip
+    }
+
+    fn check_range(&self, offset: usize, len: usize) -> Result<(), Error<SPI::Error>> {
+        if offset
+            .checked_add(len)
+            .is_none_or(|end| end > self.chip.size())
+        {
+            Err(Error::OutOfRange)
+        } else {
+            Ok(())
+        }
+    }
+
+    pub fn read_status(&mut self) -> Result<u8, Error<SPI::Error>> {
+        let mut status = [0];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::READ_STATUS]),
+                Operation::Read(&mut status),
+            ])
+            .map_err(Error::Spi)?;
+        Ok(status[0])
+    }
+
+    /// 只有 BP1/BP0 和 WPEN 可写
+    pub fn write_status(&mut self, status: u8) -> Result<(), Error<SPI::Error>> {
+        self.write_enable()?;
+        self.spi
+            .write(&[command::WRITE_STATUS, status])
+            .map_err(Error::Spi)?;
+        self.wait_ready()
+    }
+
+    fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.spi
+            .write(&[command::WRITE_ENABLE])
+            .map_err(Error::Spi)?;
+        if self.read_status()? & STATUS_WEL == 0 {
+            return Err(Error::WriteEnable);
+        }
+        Ok(())
+    }
+
+    /// 轮询 WIP, FRAM 直接返回
+    fn wait_ready(&mut self) -> Result<(), Error<SPI::Error>> {
+        if self.chip.is_fram() {
+            return Ok(());
+        }
+        let start = Instant::now();
+        loop {
+            if self.read_status()? & STATUS_WIP == 0 {
+                return Ok(());
+            }
+            if start.elapsed() > WRITE_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_micros(500));
+        }
+    }
+
+    /// 顺序读, 地址自动递增 (包括 A8)
+    pub fn read(&mut self, offset: usize, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(offset, buf.len())?;
+        let header = self.chip.header(command::READ, offset);
+        self.spi
+            .transaction(&mut [Operation::Write(&header), Operation::Read(buf)])
+            .map_err(Error::Spi)
+    }
+
+    /// EEPROM 按页拆开写, FRAM 一次写完
+    pub fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(offset, data.len())?;
+
+        let page = self.chip.page_size();
+        let mut done = 0;
+        while done < data.len() {
+            let at = offset + done;
+            let len = (page - at % page).min(data.len() - done);
+            self.write_enable()?;
+            let header = self.chip.header(command::WRITE, at);
+            self.spi
+                .transaction(&mut [
+                    Operation::Write(&header),
+                    Operation::Write(&data[done..done + len]),
+                ])
+                .map_err(Error::Spi)?;
+            self.wait_ready()?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    /// 读出整片, `progress(done, total)`
+    pub fn read_image(
+        &mut self,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<u8>, Error<SPI::Error>> {
+        let size = self.chip.size();
+        let mut image = vec![0; size];
+        for (i, chunk) in image.chunks_mut(4096).enumerate() {
+            self.read(i * 4096, chunk)?;
+            progress(i * 4096 + chunk.len(), size);
+        }
+        Ok(image)
+    }
+
+    /// 从 0 地址开始写入整个镜像, `progress(done, total)`
+    pub fn write_image(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        self.check_range(0, image.len())?;
+        // FRAM 的页是整片, 分块只是为了报告进度
+        let page = self.chip.page_size().min(4096);
+        for (i, chunk) in image.chunks(page).enumerate() {
+            self.write(i * page, chunk)?;
+            progress(i * page + chunk.len(), image.len());
+        }
+        Ok(())
+    }
+
+    /// 从 0 开始读回比较, 第一个不同的字节报 [`Error::Verify`]
+    pub fn verify_image(
+        &mut self,
+        image: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        self.check_range(0, image.len())?;
+        let mut buf = vec![0; 4096];
+        for (i, expected) in image.chunks(4096).enumerate() {
+            let found = &mut buf[..expected.len()];
+            self.read(i * 4096, found)?;
+            if let Some((j, expected, found)) = first_mismatch(expected, found) {
+                return Err(Error::Verify {
+                    offset: i * 4096 + j,
+                    expected,
+                    found,
+                });
+            }
+            progress(i * 4096 + expected.len(), image.len());
+        }
+        Ok(())
+    }
+}
+
+mod embedded_storage_impl {
+    use embedded_hal::spi::SpiDevice;
+    use embedded_storage::{ReadStorage, Storage};
+
+    use super::{Eeprom, Error};
+
+    impl<SPI: SpiDevice> ReadStorage for Eeprom<SPI> {
+        type Error = Error<SPI::Error>;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            Eeprom::read(self, offset as usize, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.chip.size()
+        }
+    }
+
+    impl<SPI: SpiDevice> Storage for Eeprom<SPI> {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            Eeprom::write(self, offset as usize, bytes)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embedded_hal::spi::{ErrorKind, ErrorType};
+
+    use super::*;
+
+    /// 只数事务, 读到的都是 0
+    #[derive(Default)]
+    struct Mock {
+        transactions: usize,
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl SpiDevice for Mock {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            self.transactions += 1;
+            for op in operations {
+                if let Operation::Read(buf) = op {
+                    buf.fill(0);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn address_bytes() {
+        let cases = [
+            (Chip::Eeprom25x010, 1),
+            (Chip::Eeprom25x040, 1),
+            (Chip::Fm25L04, 1),
+            (Chip::Eeprom25x080, 2),
+            (Chip::Eeprom25x512, 2),
+            (Chip::Mb85Rs256, 2),
+            (Chip::Eeprom25x1024, 3),
+            (Chip::Mb85Rs2Mt, 3),
+        ];
+        for (chip, n) in cases {
+            assert_eq!(chip.address_bytes(), n, "{chip:?}");
+        }
+    }
+
+    #[test]
+    fn header_a8() {
+        // 512 字节的芯片, A8 在命令的第 3 位
+        let chip = Chip::Eeprom25x040;
+        assert_eq!(chip.header(command::READ, 0x0FF), [0x03, 0xFF]);
+        assert_eq!(chip.header(command::READ, 0x100), [0x0B, 0x00]);
+        assert_eq!(chip.header(command::WRITE, 0x1A5), [0x0A, 0xA5]);
+        assert_eq!(Chip::Fm25L04.header(command::WRITE, 0x1FF), [0x0A, 0xFF]);
+        // 256 字节以内 A8 总是 0
+        assert_eq!(Chip::Eeprom25x020.header(command::READ, 0xFF), [0x03, 0xFF]);
+    }
+
+    #[test]
+    fn header_multi_byte() {
+        assert_eq!(
+            Chip::Eeprom25x160.header(command::READ, 0x07FF),
+            [0x03, 0x07, 0xFF]
+        );
+        assert_eq!(
+            Chip::Eeprom25x1024.header(command::WRITE, 0x1_2345),
+            [0x02, 0x01, 0x23, 0x45]
+        );
+    }
+
+    #[test]
+    fn range() {
+        let mut eeprom = Eeprom::new(Mock::default(), Chip::Eeprom25x020);
+        let mut buf = [0; 2];
+        assert!(eeprom.read(255, &mut buf[..1]).is_ok());
+        assert!(matches!(eeprom.read(255, &mut buf), Err(Error::OutOfRange)));
+        // offset + len 溢出也不能绕过检查
+        assert!(matches!(
+            eeprom.read(usize::MAX, &mut buf),
+            Err(Error::OutOfRange)
+        ));
+        assert!(matches!(
+            eeprom.write(usize::MAX - 1, &buf),
+            Err(Error::OutOfRange)
+        ));
+        // 越界的读写不碰总线
+        assert_eq!(eeprom.release().transactions, 1);
+    }
+}
@@ -1,18 +1,51 @@
-use std::marker::PhantomData;
+use std::{marker::PhantomData, thread::sleep, time::Duration};
 
 use embassy_hal_internal::Peripheral;
+use embedded_hal::spi::{ErrorKind, Operation};
 
 use crate::hal::{self};
 
+pub mod avr;
+pub mod eeprom;
+pub mod nand;
+pub mod nor;
+pub mod sd;
+mod shared;
+
+pub use shared::{ChipSelect, SharedBus, SharedDevice};
+
 pub mod instance {
+    use embedded_hal::spi::ErrorKind;
+
     use crate::{
         ch347, format_u8_array,
-        spi::{CSPin, Ch347SpiConfig, Config},
+        spi::{CSPin, Ch347SpiConfig, Config, Polarity},
     };
 
+    /// USB 出错时返回 [`ErrorKind::Other`], 和 I2C 一样
+    fn usb_write(buf: &[u8]) -> Result<(), ErrorKind> {
+        ch347::write(buf).map_err(|_| ErrorKind::Other)
+    }
+
+    fn usb_read(buf: &mut [u8]) -> Result<usize, ErrorKind> {
+        ch347::read(buf).map_err(|_| ErrorKind::Other)
+    }
+
     pub trait Instance {
-        fn set_config(config: Config) {
-            let cfg = Ch347SpiConfig::from(config);
+        /// `cs` 是这次传输用的硬件片选, 只设置它的极性位
+        fn set_config(config: Config, cs: Option<CSPin>) -> Result<(), ErrorKind> {
+            Self::write_config(Ch347SpiConfig::new(config, cs))
+        }
+
+        /// 单线模式切换方向, 0xC000 发送, 0x8000 接收
+        fn set_direction(config: Config, cs: Option<CSPin>, tx: bool) -> Result<(), ErrorKind> {
+            let mut cfg = Ch347SpiConfig::new(config, cs);
+            cfg.direction = if tx { 0xC000 } else { 0x8000 };
+            Self::write_config(cfg)
+        }
+
+        /// 回复 `C0 xx xx 状态`, 状态不是 0 说明芯片不接受这个配置. USB 出错时重试一次
+        fn write_config(cfg: Ch347SpiConfig) -> Result<(), ErrorKind> {
             let mut ibuf = [0; 64];
             let mut buf: Vec<u8> = Vec::new();
             buf.push(0xC0);
@@ -22,43 +55,44 @@ pub mod instance {
                 std::slice::from_raw_parts(&cfg as *const Ch347SpiConfig as *const u8, 26)
             });
 
-            for i in 0..2 {
-                ch347::write(&buf).unwrap();
-                match ch347::read(&mut ibuf) {
-                    Ok(rev) => {
-                        assert_eq!(rev, 4);
-                        assert!(buf[3] == 0x00 && buf[0] == 0xC0);
-                        break;
-                    }
-                    Err(e) => {
-                        if i == 0 {
-                            println!("{:?}", e);
-                            continue;
-                        } else {
-                            panic!("Init Spi config error");
-                        }
-                    }
+            let mut result = Err(ErrorKind::Other);
+            for _ in 0..2 {
+                result = usb_write(&buf).and_then(|_| usb_read(&mut ibuf));
+                match result {
+                    Ok(_) => break,
+                    Err(e) => log::warn!("spi config: {:?}", e),
                 }
             }
+            let rev = result?;
+            if rev != 4 || ibuf[0] != 0xC0 || ibuf[3] != 0x00 {
+                log::warn!("spi config rejected: {}", format_u8_array(&ibuf[..rev]));
+                return Err(ErrorKind::Other);
+            }
 
             // is that cfg same of obuf
-            ch347::write(&[0xCA, 0x01, 0x00, 0x01]).unwrap();
-            ch347::read(&mut ibuf).unwrap();
+            usb_write(&[0xCA, 0x01, 0x00, 0x01])?;
+            usb_read(&mut ibuf)?;
+            Ok(())
         }
 
-        fn cs_write(pin: CSPin, level: bool) {
-            let state = if level { 0x80 | 0x40 } else { 0x80 };
+        /// 按 `config` 的极性和延时设置片选. 每个 CS 5 字节:
+        /// 控制 (0x80 有效, 0x40 电平), 有效后延时, 无效后延时, 延时都是 u16 小端, 单位 us
+        fn chip_select(pin: CSPin, active: bool, config: &Config) -> Result<(), ErrorKind> {
+            let high = active == (config.cs_polarity == Polarity::ActiveHigh);
             let index = if pin == CSPin::CS0 { 3 } else { 8 };
-            // obuf + 3 是 CS0, obuf + 8 是 CS1
-            let mut obuf = [
-                0xC1, 0x0A, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            ];
-            obuf[index] = state;
-            ch347::write(&obuf).unwrap();
+            let mut obuf = [0; 13];
+            obuf[..3].copy_from_slice(&[0xC1, 0x0A, 0x00]);
+            obuf[index] = if high { 0x80 | 0x40 } else { 0x80 };
+            if active {
+                obuf[index + 1..index + 3].copy_from_slice(&config.cs_setup_us.to_le_bytes());
+            } else {
+                obuf[index + 3..index + 5].copy_from_slice(&config.cs_idle_us.to_le_bytes());
+            }
+            usb_write(&obuf)
         }
 
         /// 一次最多发 4093 个byte
-        fn write(buf: &[u8]) {
+        fn write(buf: &[u8]) -> Result<(), ErrorKind> {
             let mut left = buf.len();
             let mut ptr = 0;
             let mut obuf = [0; 510];
@@ -74,18 +108,19 @@ pub mod instance {
                 let chunk = &buf[ptr..ptr + wlen];
                 (&mut obuf[3..3 + wlen]).copy_from_slice(chunk);
 
-                ch347::write(&obuf[..3 + wlen]).unwrap();
+                usb_write(&obuf[..3 + wlen])?;
 
                 // consume rev data, as sussese, ibuf[3] == 0x00
-                ch347::read(&mut ibuf).unwrap();
+                usb_read(&mut ibuf)?;
 
                 left -= wlen;
                 ptr += wlen;
             }
+            Ok(())
         }
 
         // 每次做多读 507 字节, 共 2^32
-        fn read(buf: &mut [u8]) {
+        fn read(buf: &mut [u8]) -> Result<(), ErrorKind> {
             let mut left = buf.len();
             let mut ptr = 0;
             let mut obuf = [0xC3, 0x04, 0x00, 0x00, 0x00, 0x00, 0x00];
@@ -94,21 +129,22 @@ pub mod instance {
             obuf[5] = ((left as u32) >> 16) as u8;
             obuf[6] = ((left as u32) >> 24) as u8;
             let mut ibuf = [0; 510];
-            ch347::write(&obuf).unwrap();
+            usb_write(&obuf)?;
 
             while left > 0 {
                 let wlen = left.min(507);
 
-                ch347::read(&mut ibuf).unwrap();
+                usb_read(&mut ibuf)?;
 
                 buf[ptr..ptr + wlen].copy_from_slice(&ibuf[3..3 + wlen]);
 
                 ptr += wlen;
                 left -= wlen;
             }
+            Ok(())
         }
 
-        fn write_and_read(ibuf: &mut [u8], obuf: &[u8]) {
+        fn write_and_read(ibuf: &mut [u8], obuf: &[u8]) -> Result<(), ErrorKind> {
             assert_eq!(ibuf.len(), obuf.len());
             let mut left = ibuf.len();
             let mut ptr = 0;
@@ -123,14 +159,15 @@ pub mod instance {
                 command[2] = ((wlen as u16) >> 8) as u8;
 
                 (&mut command[3..3 + wlen]).copy_from_slice(&obuf[ptr..ptr + wlen]);
-                ch347::write(&command[..3 + wlen]).unwrap();
+                usb_write(&command[..3 + wlen])?;
 
-                ch347::read(&mut buffer).unwrap();
+                usb_read(&mut buffer)?;
                 ibuf[ptr..ptr + wlen].copy_from_slice(&buffer[3..3 + wlen]);
 
                 ptr += wlen;
                 left -= wlen;
             }
+            Ok(())
         }
     }
 }
@@ -139,12 +176,13 @@ impl instance::Instance for hal::peripherals::SPI0 {}
 pub trait Instance: Peripheral<P = Self> + instance::Instance + 'static + Send {}
 impl Instance for hal::peripherals::SPI0 {}
 
-#[derive(Debug, PartialEq, PartialOrd)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd)]
 pub enum CSPin {
     CS0,
     CS1,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Mode0,
     Mode1,
@@ -152,17 +190,57 @@ pub enum Mode {
     Mode3,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum BitOrder {
     MSB,
     LSB,
 }
 
-/// speed is (60 * 1000 * 1000) >> speed, as 0: 60M, 1: 30M
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Polarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// 半双工时数据走 MOSI 一根线, 读之前把方向切成输入
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Duplex {
+    Full,
+    Half,
+}
+
+/// 硬件支持 8 位和 16 位的帧
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataWidth {
+    Bits8,
+    Bits16,
+}
+
+/// speed is (60 * 1000 * 1000) >> speed, as 0: 60M, 1: 30M, ... 7: 468.75K (slowest, 0 ~ 7 only)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Config {
     pub speed: u16,
     pub mode: Mode,
     pub bit_order: BitOrder,
+    /// 16 位模式下 u8 操作的长度必须是偶数, 否则返回 [`ErrorKind::Other`].
+    /// 8 位模式下的 u16 操作拆成两个字节发送, 线上的波形和 16 位模式一样
+    pub data_width: DataWidth,
+    pub duplex: Duplex,
+    /// 片选极性. CS0/CS1 由 C1 命令直接给电平, 同时写进配置里给芯片自动片选用;
+    /// IO 片选按极性输出高低电平
+    pub cs_polarity: Polarity,
+    /// 片选有效后到第一个时钟的时间, us. CS0/CS1 由芯片在 C1 命令里延时, 精度 1us;
+    /// IO 片选由主机 sleep, 至少一个 USB 往返
+    pub cs_setup_us: u16,
+    /// 最后一个时钟到片选无效的时间, us. 由主机在数据命令返回后 sleep 再发片选无效,
+    /// 所以实际上至少有一个 USB 往返 (几百 us)
+    pub cs_hold_us: u16,
+    /// 片选无效后到下一次有效的最短时间, us. CS0/CS1 由芯片在 C1 命令里延时, IO 片选由主机 sleep
+    pub cs_idle_us: u16,
+    /// 字和字之间的间隔, us, 由芯片插入, 0 表示连续发送
+    pub word_interval_us: u16,
+    /// 读的时候 MOSI 上发的数据, Transfer 写缓冲不够长时也用它补齐
+    pub idle_data: u8,
 }
 
 impl Default for Config {
@@ -171,8 +249,102 @@ impl Default for Config {
             speed: 2,
             mode: Mode::Mode0,
             bit_order: BitOrder::MSB,
+            data_width: DataWidth::Bits8,
+            duplex: Duplex::Full,
+            cs_polarity: Polarity::ActiveLow,
+            cs_setup_us: 0,
+            cs_hold_us: 0,
+            cs_idle_us: 0,
+            word_interval_us: 0,
+            idle_data: 0xFF,
+        }
+    }
+}
+
+/// u16 在发给芯片的缓冲区里的字节序: 16 位模式下芯片按小端取字;
+/// 8 位模式下 MSB 先发时高字节在前, LSB 先发时低字节在前
+fn little_endian(config: &Config) -> bool {
+    config.data_width == DataWidth::Bits16 || config.bit_order == BitOrder::LSB
+}
+
+fn encode_words(config: &Config, words: &[u16]) -> Vec<u8> {
+    if little_endian(config) {
+        words.iter().flat_map(|w| w.to_le_bytes()).collect()
+    } else {
+        words.iter().flat_map(|w| w.to_be_bytes()).collect()
+    }
+}
+
+fn decode_words(config: &Config, bytes: &[u8], words: &mut [u16]) {
+    for (w, b) in words.iter_mut().zip(bytes.chunks_exact(2)) {
+        *w = if little_endian(config) {
+            u16::from_le_bytes([b[0], b[1]])
+        } else {
+            u16::from_be_bytes([b[0], b[1]])
+        };
+    }
+}
+
+/// 16 位模式下芯片按字收发, 奇数长度的字节操作最后半个字发不出去
+fn check_width(config: &Config, operations: &[Operation<'_, u8>]) -> Result<(), ErrorKind> {
+    if config.data_width != DataWidth::Bits16 {
+        return Ok(());
+    }
+    let odd = operations.iter().any(|op| match op {
+        Operation::Read(buf) | Operation::TransferInPlace(buf) => buf.len() % 2 != 0,
+        Operation::Write(buf) => buf.len() % 2 != 0,
+        Operation::Transfer(read, written) => read.len() % 2 != 0 || written.len() % 2 != 0,
+        Operation::DelayNs(_) => false,
+    });
+    if odd {
+        return Err(ErrorKind::Other);
+    }
+    Ok(())
+}
+
+/// 把 u16 操作换成字节操作交给 `f`, 完成后把读到的数据转换回来
+fn with_bytes(
+    config: &Config,
+    operations: &mut [Operation<'_, u16>],
+    f: impl FnOnce(&mut [Operation<'_, u8>]) -> Result<(), ErrorKind>,
+) -> Result<(), ErrorKind> {
+    // 每个操作的 (写缓冲, 读缓冲)
+    let mut bufs: Vec<(Vec<u8>, Vec<u8>)> = operations
+        .iter()
+        .map(|op| match op {
+            Operation::Write(words) => (encode_words(config, words), Vec::new()),
+            Operation::Read(words) => (Vec::new(), vec![0; words.len() * 2]),
+            Operation::Transfer(read, written) => {
+                (encode_words(config, written), vec![0; read.len() * 2])
+            }
+            Operation::TransferInPlace(words) => (Vec::new(), encode_words(config, words)),
+            Operation::DelayNs(_) => (Vec::new(), Vec::new()),
+        })
+        .collect();
+
+    let mut ops: Vec<Operation<'_, u8>> = operations
+        .iter()
+        .zip(bufs.iter_mut())
+        .map(|(op, (written, read))| match op {
+            Operation::Write(_) => Operation::Write(written),
+            Operation::Read(_) => Operation::Read(read),
+            Operation::Transfer(..) => Operation::Transfer(read, written),
+            Operation::TransferInPlace(_) => Operation::TransferInPlace(read),
+            Operation::DelayNs(ns) => Operation::DelayNs(*ns),
+        })
+        .collect();
+    f(&mut ops)?;
+    drop(ops);
+
+    for (op, (_, read)) in operations.iter_mut().zip(bufs.iter()) {
+        match op {
+            Operation::Read(words)
+            | Operation::TransferInPlace(words)
+            | Operation::Transfer(words, _) => decode_words(config, read, words),
+            _ => {}
         }
     }
+    Ok(())
 }
 
 #[repr(C)]
@@ -216,6 +388,18 @@ impl From<Config> for Ch347SpiConfig {
     fn from(value: Config) -> Self {
         let mut cfg: Ch347SpiConfig = Default::default();
         cfg.buad_prescalar = value.speed;
+        cfg.write_read_interval = value.word_interval_us;
+        cfg.out_default_data = value.idle_data;
+        // SPI_Direction_1Line_Tx, 需要读的时候再切成 1Line_Rx
+        cfg.direction = match value.duplex {
+            Duplex::Full => 0x0000,
+            Duplex::Half => 0xC000,
+        };
+        // SPI_DataSize_16b
+        cfg.bpw = match value.data_width {
+            DataWidth::Bits8 => 0x0000,
+            DataWidth::Bits16 => 0x0800,
+        };
         match value.mode {
             Mode::Mode0 => {
                 cfg.polarity = 0;
@@ -243,76 +427,431 @@ impl From<Config> for Ch347SpiConfig {
     }
 }
 
+impl Ch347SpiConfig {
+    /// [`From<Config>`] 不知道用哪个片选, 片选都按低有效配置
+    fn new(config: Config, cs: Option<CSPin>) -> Self {
+        let mut cfg = Self::from(config);
+        // bit7 CS0 高有效, bit6 CS1 高有效. 只改自己的片选, 另一个片选上的设备不受影响
+        if config.cs_polarity == Polarity::ActiveHigh {
+            cfg.cs_config = match cs {
+                Some(CSPin::CS0) => 0x80,
+                Some(CSPin::CS1) => 0x40,
+                None => 0x00,
+            };
+        }
+        cfg
+    }
+}
+
 /// SPI 与部分 GPIO 复用, 后续再说
 /// SpiDevice 额外具有 CS
 pub struct SpiDevice<'d, T: Instance> {
     _spi: PhantomData<&'d T>,
+    config: Config,
 }
 
 impl<'d, T: Instance> SpiDevice<'d, T> {
-    pub fn new(_spi: impl Peripheral<P = T>, config: Config) -> Self {
-        T::set_config(config);
-        Self { _spi: PhantomData }
+    /// 下发配置失败 (USB 出错或者芯片不接受) 时返回错误
+    pub fn new(_spi: impl Peripheral<P = T>, config: Config) -> Result<Self, ErrorKind> {
+        T::set_config(config, Some(CSPin::CS0))?;
+        Ok(Self {
+            _spi: PhantomData,
+            config,
+        })
+    }
+
+    pub fn write_data(&self, buf: &[u8]) -> Result<(), ErrorKind> {
+        self.transact(&mut [Operation::Write(buf)])
     }
 
-    pub fn write_data(&self, buf: &[u8]) {
-        T::cs_write(CSPin::CS0, false);
-        T::write(buf);
-        T::cs_write(CSPin::CS0, true);
+    pub fn read_data(&self, buf: &mut [u8]) -> Result<(), ErrorKind> {
+        self.transact(&mut [Operation::Read(buf)])
     }
 
-    pub fn read_data(&self, buf: &mut [u8]) {
-        T::cs_write(CSPin::CS0, false);
-        T::read(buf);
-        T::cs_write(CSPin::CS0, true);
+    pub fn write_and_read(&self, ibuf: &mut [u8], obuf: &[u8]) -> Result<(), ErrorKind> {
+        self.transact(&mut [Operation::Transfer(ibuf, obuf)])
     }
 
-    pub fn write_and_read(&self, ibuf: &mut [u8], obuf: &[u8]) {
-        T::cs_write(CSPin::CS0, false);
-        T::write_and_read(ibuf, obuf);
-        T::cs_write(CSPin::CS0, true);
+    pub fn write_and_read_in_place(&self, buf: &mut [u8]) -> Result<(), ErrorKind> {
+        self.transact(&mut [Operation::TransferInPlace(buf)])
     }
 
-    pub fn write_and_read_in_place(&self, buf: &mut [u8]) {
-        // 做不到单片机那种细致的传输
-        let mut obuf = Vec::new();
-        obuf.extend_from_slice(&buf);
-        T::cs_write(CSPin::CS0, false);
-        self.write_and_read(buf, &obuf);
-        T::cs_write(CSPin::CS0, true);
+    /// 整个传输只拉低一次 CS
+    fn transact(&self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        check_width(&self.config, operations)?;
+        T::chip_select(CSPin::CS0, true, &self.config)?;
+        let result = execute::<T>(&self.config, Some(CSPin::CS0), operations);
+        hold(&self.config);
+        // 传输出错也要放开片选
+        let released = T::chip_select(CSPin::CS0, false, &self.config);
+        result.and(released)
     }
 }
 
-// SpiBus 是 SCK, MISO, MOSI
+fn hold(config: &Config) {
+    if config.cs_hold_us > 0 {
+        sleep(Duration::from_micros(u64::from(config.cs_hold_us)));
+    }
+}
 
-mod embedded_hal_v100_impl {
-    use std::{thread::sleep, time::Duration};
+/// 执行一次传输里的操作, 不管片选. `cs` 是半双工切换方向时重新下发配置用的
+fn execute<T: Instance>(
+    config: &Config,
+    cs: Option<CSPin>,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), ErrorKind> {
+    match config.duplex {
+        Duplex::Full => run(operations, config.idle_data, T::write, T::write_and_read),
+        Duplex::Half => run_half_duplex::<T>(config, cs, operations),
+    }
+}
+
+/// 半双工的状态: 攒着还没发的写数据, 当前方向
+struct HalfDuplex<'c, T: Instance> {
+    config: &'c Config,
+    cs: Option<CSPin>,
+    pending: Vec<u8>,
+    tx: bool,
+    _spi: PhantomData<T>,
+}
+
+impl<T: Instance> HalfDuplex<'_, T> {
+    fn turn(&mut self, tx: bool) -> Result<(), ErrorKind> {
+        if self.tx != tx {
+            T::set_direction(*self.config, self.cs, tx)?;
+            self.tx = tx;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), ErrorKind> {
+        if !self.pending.is_empty() {
+            self.turn(true)?;
+            T::write(&self.pending)?;
+            self.pending.clear();
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buf: &mut [u8]) -> Result<(), ErrorKind> {
+        self.flush()?;
+        if !buf.is_empty() {
+            self.turn(false)?;
+            T::read(buf)?;
+        }
+        Ok(())
+    }
+}
+
+/// 相邻的写合并成一个 C4, 读用 C3. Transfer 和 TransferInPlace 当作先写后读.
+/// 结束时方向切回发送
+fn run_half_duplex<T: Instance>(
+    config: &Config,
+    cs: Option<CSPin>,
+    operations: &mut [Operation<'_, u8>],
+) -> Result<(), ErrorKind> {
+    let mut line = HalfDuplex::<T> {
+        config,
+        cs,
+        pending: Vec::new(),
+        tx: true,
+        _spi: PhantomData,
+    };
+    for op in operations.iter_mut() {
+        match op {
+            Operation::Write(buf) => line.pending.extend_from_slice(buf),
+            Operation::Read(buf) => line.read(buf)?,
+            Operation::Transfer(read, written) => {
+                line.pending.extend_from_slice(written);
+                line.read(read)?;
+            }
+            Operation::TransferInPlace(buf) => {
+                line.pending.extend_from_slice(buf);
+                line.read(buf)?;
+            }
+            Operation::DelayNs(ns) => {
+                line.flush()?;
+                sleep(Duration::from_nanos(u64::from(*ns)));
+            }
+        }
+    }
+    line.flush()?;
+    line.turn(true)
+}
 
+/// 以 DelayNs 为界把操作分段, 每段拼成一个数据流:
+/// 有读的用全双工 (C2), 只有写的用 C4. 延时在 CS 有效期间执行
+fn run(
+    operations: &mut [Operation<'_, u8>],
+    fill: u8,
+    write: impl Fn(&[u8]) -> Result<(), ErrorKind>,
+    write_and_read: impl Fn(&mut [u8], &[u8]) -> Result<(), ErrorKind>,
+) -> Result<(), ErrorKind> {
+    let mut start = 0;
+    for i in 0..=operations.len() {
+        let delay = match operations.get(i) {
+            Some(Operation::DelayNs(ns)) => Some(*ns),
+            Some(_) => continue,
+            None => None,
+        };
+        run_segment(&mut operations[start..i], fill, &write, &write_and_read)?;
+        if let Some(ns) = delay {
+            sleep(Duration::from_nanos(u64::from(ns)));
+        }
+        start = i + 1;
+    }
+    Ok(())
+}
+
+fn run_segment(
+    operations: &mut [Operation<'_, u8>],
+    fill: u8,
+    write: impl Fn(&[u8]) -> Result<(), ErrorKind>,
+    write_and_read: impl Fn(&mut [u8], &[u8]) -> Result<(), ErrorKind>,
+) -> Result<(), ErrorKind> {
+    // 读的时候发 `fill`, Transfer 的写缓冲比读缓冲短时也补 `fill`
+    let mut obuf = Vec::new();
+    let mut reads = false;
+    for op in operations.iter() {
+        match op {
+            Operation::Write(buf) => obuf.extend_from_slice(buf),
+            Operation::Read(buf) => {
+                reads = true;
+                obuf.resize(obuf.len() + buf.len(), fill);
+            }
+            Operation::Transfer(read, written) => {
+                reads = true;
+                obuf.extend_from_slice(written);
+                obuf.resize(obuf.len() + read.len().saturating_sub(written.len()), fill);
+            }
+            Operation::TransferInPlace(buf) => {
+                reads = true;
+                obuf.extend_from_slice(buf);
+            }
+            Operation::DelayNs(_) => unreachable!(),
+        }
+    }
+
+    if obuf.is_empty() {
+        return Ok(());
+    }
+    if !reads {
+        return write(&obuf);
+    }
+
+    let mut ibuf = vec![0; obuf.len()];
+    write_and_read(&mut ibuf, &obuf)?;
+
+    let mut at = 0;
+    for op in operations.iter_mut() {
+        match op {
+            Operation::Write(buf) => at += buf.len(),
+            Operation::Read(buf) | Operation::TransferInPlace(buf) => {
+                buf.copy_from_slice(&ibuf[at..at + buf.len()]);
+                at += buf.len();
+            }
+            Operation::Transfer(read, written) => {
+                read.copy_from_slice(&ibuf[at..at + read.len()]);
+                at += read.len().max(written.len());
+            }
+            Operation::DelayNs(_) => unreachable!(),
+        }
+    }
+    Ok(())
+}
+
+/// SpiBus 是 SCK, MISO, MOSI, 不管片选.
+/// 配合 embedded-hal-bus 的 `ExclusiveDevice`/`CriticalSectionDevice` 等由上层控制片选
+pub struct SpiBus<'d, T: Instance> {
+    _spi: PhantomData<&'d T>,
+    config: Config,
+}
+
+impl<'d, T: Instance> SpiBus<'d, T> {
+    /// 下发配置失败 (USB 出错或者芯片不接受) 时返回错误
+    pub fn new(_spi: impl Peripheral<P = T>, config: Config) -> Result<Self, ErrorKind> {
+        T::set_config(config, None)?;
+        Ok(Self {
+            _spi: PhantomData,
+            config,
+        })
+    }
+
+    /// 换模式, 速度或者字宽
+    pub fn set_config(&mut self, config: Config) -> Result<(), ErrorKind> {
+        T::set_config(config, None)?;
+        self.config = config;
+        Ok(())
+    }
+
+    fn execute(&self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        check_width(&self.config, operations)?;
+        execute::<T>(&self.config, None, operations)
+    }
+}
+
+mod embedded_hal_v100_impl {
     use embedded_hal::spi::*;
 
     use crate::spi::Instance;
 
     impl<'d, T: Instance> ErrorType for super::SpiDevice<'d, T> {
-        type Error = core::convert::Infallible;
+        type Error = ErrorKind;
     }
 
     impl<'d, T: Instance> SpiDevice for super::SpiDevice<'d, T> {
         fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
-            // 通常来说 operations 只有一个
-            for op in operations.iter_mut() {
-                match op {
-                    Operation::Read(buf) => self.read_data(buf),
-                    Operation::Write(buf) => self.write_data(buf),
-                    Operation::Transfer(ibuf, obuf) => self.write_and_read(ibuf, obuf),
-                    Operation::TransferInPlace(buf) => self.write_and_read_in_place(buf),
-                    Operation::DelayNs(ns) => {
-                        sleep(Duration::from_nanos(*ns as u64));
-                    }
-                }
-            }
+            self.transact(operations)
+        }
+    }
+
+    impl<'d, T: Instance> SpiDevice<u16> for super::SpiDevice<'d, T> {
+        fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u16>],
+        ) -> Result<(), Self::Error> {
+            super::with_bytes(&self.config, operations, |ops| self.transact(ops))
+        }
+    }
+}
+
+mod embedded_hal_v100_bus_impl {
+    use embedded_hal::spi::*;
+
+    use crate::spi::{Instance, decode_words, encode_words};
+
+    impl<'d, T: Instance> ErrorType for super::SpiBus<'d, T> {
+        type Error = ErrorKind;
+    }
+
+    /// 半双工时 transfer 当作先写后读
+    impl<'d, T: Instance> SpiBus for super::SpiBus<'d, T> {
+        fn read(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+            self.execute(&mut [Operation::Read(words)])
+        }
+
+        fn write(&mut self, words: &[u8]) -> Result<(), Self::Error> {
+            self.execute(&mut [Operation::Write(words)])
+        }
+
+        /// 长度不同时多出来的部分写 `idle_data`, 读到的丢掉
+        fn transfer(&mut self, read: &mut [u8], write: &[u8]) -> Result<(), Self::Error> {
+            self.execute(&mut [Operation::Transfer(read, write)])
+        }
+
+        fn transfer_in_place(&mut self, words: &mut [u8]) -> Result<(), Self::Error> {
+            self.execute(&mut [Operation::TransferInPlace(words)])
+        }
+
+        /// 每个命令都等到回复才返回, 没有需要等的
+        fn flush(&mut self) -> Result<(), Self::Error> {
+            Ok(())
+        }
+    }
+
+    impl<'d, T: Instance> SpiBus<u16> for super::SpiBus<'d, T> {
+        fn read(&mut self, words: &mut [u16]) -> Result<(), Self::Error> {
+            let mut bytes = vec![0; words.len() * 2];
+            SpiBus::<u8>::read(self, &mut bytes)?;
+            decode_words(&self.config, &bytes, words);
+            Ok(())
+        }
+
+        fn write(&mut self, words: &[u16]) -> Result<(), Self::Error> {
+            SpiBus::<u8>::write(self, &encode_words(&self.config, words))
+        }
+
+        fn transfer(&mut self, read: &mut [u16], write: &[u16]) -> Result<(), Self::Error> {
+            let mut bytes = vec![0; read.len() * 2];
+            SpiBus::<u8>::transfer(self, &mut bytes, &encode_words(&self.config, write))?;
+            decode_words(&self.config, &bytes, read);
+            Ok(())
+        }
+
+        fn transfer_in_place(&mut self, words: &mut [u16]) -> Result<(), Self::Error> {
+            let mut bytes = encode_words(&self.config, words);
+            SpiBus::<u8>::transfer_in_place(self, &mut bytes)?;
+            decode_words(&self.config, &bytes, words);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<(), Self::Error> {
             Ok(())
         }
     }
 }
 
-// SpiBus + GPIO, waiting, 看那个spi模块的crate用SpiBus, 先测 mipidsi crate
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn odd_length_in_16_bit_mode() {
+        let wide = Config {
+            data_width: DataWidth::Bits16,
+            ..Default::default()
+        };
+        let mut odd = [0; 3];
+        let mut even = [0; 4];
+        assert_eq!(
+            check_width(&wide, &[Operation::Write(&[1, 2, 3])]),
+            Err(ErrorKind::Other)
+        );
+        assert_eq!(
+            check_width(&wide, &[Operation::Transfer(&mut even, &[1, 2, 3])]),
+            Err(ErrorKind::Other)
+        );
+        assert_eq!(
+            check_width(&wide, &[Operation::Read(&mut odd)]),
+            Err(ErrorKind::Other)
+        );
+        assert_eq!(
+            check_width(
+                &wide,
+                &[
+                    Operation::Write(&[1, 2]),
+                    Operation::DelayNs(3),
+                    Operation::Read(&mut even)
+                ]
+            ),
+            Ok(())
+        );
+        assert_eq!(
+            check_width(&Config::default(), &[Operation::Read(&mut odd)]),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn active_high_sets_own_cs_bit() {
+        let high = Config {
+            cs_polarity: Polarity::ActiveHigh,
+            ..Default::default()
+        };
+        assert_eq!(Ch347SpiConfig::new(high, Some(CSPin::CS0)).cs_config, 0x80);
+        assert_eq!(Ch347SpiConfig::new(high, Some(CSPin::CS1)).cs_config, 0x40);
+        assert_eq!(Ch347SpiConfig::new(high, None).cs_config, 0x00);
+        let low = Config::default();
+        assert_eq!(Ch347SpiConfig::new(low, Some(CSPin::CS1)).cs_config, 0x00);
+    }
+
+    #[test]
+    fn word_byte_order() {
+        let msb = Config::default();
+        let lsb = Config {
+            bit_order: BitOrder::LSB,
+            ..Default::default()
+        };
+        let wide = Config {
+            data_width: DataWidth::Bits16,
+            ..Default::default()
+        };
+        assert_eq!(encode_words(&msb, &[0x1234]), [0x12, 0x34]);
+        assert_eq!(encode_words(&lsb, &[0x1234]), [0x34, 0x12]);
+        assert_eq!(encode_words(&wide, &[0x1234]), [0x34, 0x12]);
+
+        let mut words = [0; 2];
+        decode_words(&msb, &[0x12, 0x34, 0x56, 0x78], &mut words);
+        assert_eq!(words, [0x1234, 0x5678]);
+    }
+}
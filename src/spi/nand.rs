@@ -0,0 +1,873 @@
+//! SPI NAND Flash (W25N, GD5F)
+//!
+//! 读: 0x13 把一页读到芯片的缓存, 再用 0x03 从缓存读出.
+//! 写: 0x02 把数据装进缓存, 再用 0x10 写到页里. 擦除按块 (0xD8).
+//! 页后面跟着 OOB (spare) 区, 出厂坏块在块第一页 OOB 的第 0 字节标成非 0xFF.
+//! 读写镜像时跳过坏块, 所以镜像在芯片上不一定连续
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+pub mod command {
+    pub const RESET: u8 = 0xFF;
+    pub const READ_JEDEC_ID: u8 = 0x9F;
+    pub const GET_FEATURE: u8 = 0x0F;
+    pub const SET_FEATURE: u8 = 0x1F;
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const WRITE_DISABLE: u8 = 0x04;
+    pub const PAGE_READ: u8 = 0x13;
+    pub const READ_CACHE: u8 = 0x03;
+    pub const PROGRAM_LOAD: u8 = 0x02;
+    pub const RANDOM_PROGRAM_LOAD: u8 = 0x84;
+    pub const PROGRAM_EXECUTE: u8 = 0x10;
+    pub const BLOCK_ERASE: u8 = 0xD8;
+}
+
+/// 特性寄存器地址
+pub mod feature {
+    pub const PROTECTION: u8 = 0xA0;
+    pub const CONFIG: u8 = 0xB0;
+    pub const STATUS: u8 = 0xC0;
+}
+
+/// 配置寄存器
+pub const CONFIG_OTP_E: u8 = 0x40;
+pub const CONFIG_ECC_E: u8 = 0x10;
+/// Winbond 的 buffer read 模式, 0 时是连续读
+pub const CONFIG_BUF: u8 = 0x08;
+
+/// 状态寄存器
+pub const STATUS_OIP: u8 = 0x01;
+pub const STATUS_WEL: u8 = 0x02;
+pub const STATUS_E_FAIL: u8 = 0x04;
+pub const STATUS_P_FAIL: u8 = 0x08;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+const PROGRAM_TIMEOUT: Duration = Duration::from_millis(10);
+const ERASE_TIMEOUT: Duration = Duration::from_millis(50);
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    /// 读到的 ID 全 0 或全 1
+    NoDevice,
+    /// 不在 [`PARTS`] 里
+    UnknownPart([u8; 3]),
+    OutOfRange,
+    /// 等 OIP 清零超时
+    Timeout,
+    WriteEnable,
+    EraseFailed {
+        block: u32,
+    },
+    ProgramFailed {
+        page: u32,
+    },
+    /// ECC 纠不过来
+    Uncorrectable {
+        page: u32,
+    },
+    /// 操作的块是坏块
+    BadBlock(u32),
+    /// 跳过坏块后空间不够
+    NoSpace,
+}
+
+/// 读页之后的 ECC 状态
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ecc {
+    Clean,
+    Corrected,
+    Uncorrectable,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Part {
+    pub name: &'static str,
+    pub manufacturer: u8,
+    pub device: &'static [u8],
+    pub page_size: u32,
+    pub oob_size: u32,
+    pub pages_per_block: u32,
+    pub blocks: u32,
+    /// ECC 状态为 0b11 时表示纠正了 (GigaDevice), 否则是纠不过来 (Winbond)
+    pub ecc_11_corrected: bool,
+    /// 配置寄存器有 BUF 位
+    pub buf_mode: bool,
+}
+
+impl Part {
+    pub fn block_size(&self) -> u32 {
+        self.page_size * self.pages_per_block
+    }
+
+    pub fn size(&self) -> u64 {
+        u64::from(self.block_size()) * u64::from(self.blocks)
+    }
+
+    fn ecc(&self, status: u8) -> Ecc {
+        match (status >> 4) & 0x03 {
+            0b00 => Ecc::Clean,
+            0b01 => Ecc::Corrected,
+            0b11 if self.ecc_11_corrected => Ecc::Corrected,
+            _ => Ecc::Uncorrectable,
+        }
+    }
+}
+
+pub const PARTS: &[Part] = &[
+    Part {
+        name: "W25N512GV",
+        manufacturer: 0xEF,
+        device: &[0xAA, 0x20],
+        page_size: 2048,
+        oob_size: 64,
+        pages_per_block: 64,
+        blocks: 512,
+        ecc_11_corrected: false,
+        buf_mode: true,
+    },
+    Part {
+        name: "W25N01GV",
+        manufacturer: 0xEF,
+        device: &[0xAA, 0x21],
+        page_size: 2048,
+        oob_size: 64,
+        pages_per_block: 64,
+        blocks: 1024,
+        ecc_11_corrected: false,
+        buf_mode: true,
+    },
+    Part {
+        name: "W25N02KV",
+        manufacturer: 0xEF,
+        device: &[0xAA, 0x22],
+        page_size: 2048,
+        oob_size: 128,
+        pages_per_block: 64,
+        blocks: 2048,
+        ecc_11_corrected: false,
+        buf_mode: true,
+    },
+    Part {
+        name: "GD5F1GQ4U",
+        manufacturer: 0xC8,
+        device: &[0xD1],
+        page_size: 2048,
+        oob_size: 128,
+        pages_per_block: 64,
+        blocks: 1024,
+        ecc_11_corrected: true,
+        buf_mode: false,
+    },
+    Part {
+        name: "GD5F2GQ4U",
+        manufacturer: 0xC8,
+        device: &[0xD2],
+        page_size: 2048,
+        oob_size: 128,
+        pages_per_block: 64,
+        blocks: 2048,
+        ecc_11_corrected: true,
+        buf_mode: false,
+    },
+    Part {
+        name: "GD5F1GQ5U",
+        manufacturer: 0xC8,
+        device: &[0x51],
+        page_size: 2048,
+        oob_size: 128,
+        pages_per_block: 64,
+        blocks: 1024,
+        ecc_11_corrected: false,
+        buf_mode: false,
+    },
+];
+
+impl Part {
+    /// `id` 是 0x9F 加一个 dummy 字节后读到的 3 字节
+    pub fn find(id: [u8; 3]) -> Option<&'static Part> {
+        PARTS
+            .iter()
+            .find(|p| p.manufacturer == id[0] && id[1..].starts_with(p.device))
+    }
+}
+
+pub struct Nand<SPI> {
+    spi: SPI,
+    part: &'static Part,
+    /// 坏块表, 调用 [`Nand::scan_bad_blocks`] 之前只有 [`Nand::mark_bad`] 标的块
+    bad: Vec<u32>,
+    /// 扫描过之后坏块表是完整的, 找好块时不用再读标记
+    scanned: bool,
+}
+
+impl<SPI: SpiDevice> Nand<SPI> {
+    /// 复位, 识别型号, 解除所有块的写保护, 打开 ECC
+    pub fn new(mut spi: SPI) -> Result<Self, Error<SPI::Error>> {
+        spi.write(&[command::RESET]).map_err(Error::Spi)?;
+        sleep(Duration::from_millis(1));
+
+        let mut id = [0; 3];
+        spi.transaction(&mut [
+            Operation::Write(&[command::READ_JEDEC_ID, 0x00]),
+            Operation::Read(&mut id),
+        ])
+        .map_err(Error::Spi)?;
+        if matches!(id[0], 0x00 | 0xFF) {
+            return Err(Error::NoDevice);
+        }
+        let part = Part::find(id).ok_or(Error::UnknownPart(id))?;
+
+        let mut nand = Self {
+            spi,
+            part,
+            bad: Vec::new(),
+            scanned: false,
+        };
+        nand.wait_ready(READ_TIMEOUT)?;
+        nand.set_feature(feature::PROTECTION, 0x00)?;
+        let mut config = nand.get_feature(feature::CONFIG)?;
+        config |= CONFIG_ECC_E;
+        config &= !CONFIG_OTP_E;
+        if part.buf_mode {
+            config |= CONFIG_BUF;
+        }
+        nand.set_feature(feature::CONFIG, config)?;
+        Ok(nand)
+    }
+
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    pub fn part(&self) -> &'static Part {
+        self.part
+    }
+
+    pub fn get_feature(&mut self, address: u8) -> Result<u8, Error<SPI::Error>> {
+        let mut value = [0];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::GET_FEATURE, address]),
+                Operation::Read(&mut value),
+            ])
+            .map_err(Error::Spi)?;
+        Ok(value[0])
+    }
+
+    pub fn set_feature(&mut self, address: u8, value: u8) -> Result<(), Error<SPI::Error>> {
+        self.spi
+            .write(&[command::SET_FEATURE, address, value])
+            .map_err(Error::Spi)
+    }
+
+    /// 打开或关闭片上 ECC. 关闭后读到的是原始数据, OOB 里的 ECC 字节也能写
+    pub fn set_ecc(&mut self, enable: bool) -> Result<(), Error<SPI::Error>> {
+        let config = self.get_feature(feature::CONFIG)?;
+        let config = if enable {
+            config | CONFIG_ECC_E
+        } else {
+            config & !CONFIG_ECC_E
+        };
+        self.set_feature(feature::CONFIG, config)
+    }
+
+    /// 轮询 OIP, 返回最后的状态寄存器
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<u8, Error<SPI::Error>> {
+        let start = Instant::now();
+        loop {
+            let status = self.get_feature(feature::STATUS)?;
+            if status & STATUS_OIP == 0 {
+                return Ok(status);
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_micros(50));
+        }
+    }
+
+    fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.spi
+            .write(&[command::WRITE_ENABLE])
+            .map_err(Error::Spi)?;
+        if self.get_feature(feature::STATUS)? & STATUS_WEL == 0 {
+            return Err(Error::WriteEnable);
+        }
+        Ok(())
+    }
+
+    fn check_page(&self, page: u32) -> Result<(), Error<SPI::Error>> {
+        if page >= self.part.pages_per_block * self.part.blocks {
+            return Err(Error::OutOfRange);
+        }
+        Ok(())
+    }
+
+    fn row(page: u32) -> [u8; 3] {
+        let a = page.to_be_bytes();
+        [a[1], a[2], a[3]]
+    }
+
+    /// 把一页读到缓存, 返回 ECC 状态
+    pub fn page_read(&mut self, page: u32) -> Result<Ecc, Error<SPI::Error>> {
+        self.check_page(page)?;
+        let [a, b, c] = Self::row(page);
+        self.spi
+            .write(&[command::PAGE_READ, a, b, c])
+            .map_err(Error::Spi)?;
+        let status = self.wait_ready(READ_TIMEOUT)?;
+        Ok(self.part.ecc(status))
+    }
+
+    /// 从缓存的 `column` 开始读, 页数据后面接着是 OOB
+    pub fn read_cache(&mut self, column: u16, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let [a, b] = column.to_be_bytes();
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::READ_CACHE, a, b, 0x00]),
+                Operation::Read(buf),
+            ])
+            .map_err(Error::Spi)
+    }
+
+    /// 装载数据到缓存, `random` 时不把缓存的其他部分清成 0xFF
+    pub fn program_load(
+        &mut self,
+        column: u16,
+        data: &[u8],
+        random: bool,
+    ) -> Result<(), Error<SPI::Error>> {
+        let opcode = if random {
+            command::RANDOM_PROGRAM_LOAD
+        } else {
+            command::PROGRAM_LOAD
+        };
+        let [a, b] = column.to_be_bytes();
+        self.spi
+            .transaction(&mut [Operation::Write(&[opcode, a, b]), Operation::Write(data)])
+            .map_err(Error::Spi)
+    }
+
+    /// 把缓存写到页里
+    pub fn program_execute(&mut self, page: u32) -> Result<(), Error<SPI::Error>> {
+        self.check_page(page)?;
+        let [a, b, c] = Self::row(page);
+        self.spi
+            .write(&[command::PROGRAM_EXECUTE, a, b, c])
+            .map_err(Error::Spi)?;
+        if self.wait_ready(PROGRAM_TIMEOUT)? & STATUS_P_FAIL != 0 {
+            return Err(Error::ProgramFailed { page });
+        }
+        Ok(())
+    }
+
+    /// 读一页数据 (不含 OOB), ECC 纠不过来时返回 [`Error::Uncorrectable`]
+    pub fn read_page(&mut self, page: u32, buf: &mut [u8]) -> Result<Ecc, Error<SPI::Error>> {
+        let ecc = self.page_read(page)?;
+        if ecc == Ecc::Uncorrectable {
+            return Err(Error::Uncorrectable { page });
+        }
+        let len = buf.len().min(self.part.page_size as usize);
+        self.read_cache(0, &mut buf[..len])?;
+        Ok(ecc)
+    }
+
+    /// 写一页, `data` 不超过页大小, 页要先擦除
+    pub fn write_page(&mut self, page: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        if data.len() > self.part.page_size as usize {
+            return Err(Error::OutOfRange);
+        }
+        self.check_page(page)?;
+        self.write_enable()?;
+        self.program_load(0, data, false)?;
+        self.program_execute(page)
+    }
+
+    pub fn erase_block(&mut self, block: u32) -> Result<(), Error<SPI::Error>> {
+        if block >= self.part.blocks {
+            return Err(Error::OutOfRange);
+        }
+        self.write_enable()?;
+        let [a, b, c] = Self::row(block * self.part.pages_per_block);
+        self.spi
+            .write(&[command::BLOCK_ERASE, a, b, c])
+            .map_err(Error::Spi)?;
+        if self.wait_ready(ERASE_TIMEOUT)? & STATUS_E_FAIL != 0 {
+            return Err(Error::EraseFailed { block });
+        }
+        Ok(())
+    }
+
+    /// 关掉 ECC 读整页加 OOB, 不管 ECC 状态, 给分析用
+    pub fn read_raw(&mut self, page: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let len = (self.part.page_size + self.part.oob_size) as usize;
+        if buf.len() < len {
+            return Err(Error::OutOfRange);
+        }
+        self.set_ecc(false)?;
+        let result = self
+            .page_read(page)
+            .and_then(|_| self.read_cache(0, &mut buf[..len]));
+        self.set_ecc(true)?;
+        result
+    }
+
+    /// 原始转储 [from, from + count) 页, 每页 (页号, 页数据加 OOB) 交给 `f`
+    pub fn dump_raw(
+        &mut self,
+        from: u32,
+        count: u32,
+        mut f: impl FnMut(u32, &[u8]),
+    ) -> Result<(), Error<SPI::Error>> {
+        let end = from.checked_add(count).ok_or(Error::OutOfRange)?;
+        if end > self.part.pages_per_block * self.part.blocks {
+            return Err(Error::OutOfRange);
+        }
+        let mut buf = vec![0; (self.part.page_size + self.part.oob_size) as usize];
+        self.set_ecc(false)?;
+        let mut result = Ok(());
+        for page in from..end {
+            result = self
+                .page_read(page)
+                .and_then(|_| self.read_cache(0, &mut buf));
+            if result.is_err() {
+                break;
+            }
+            f(page, &buf);
+        }
+        self.set_ecc(true)?;
+        result
+    }
+
+    /// 块的第 0、1 页 OOB 第 0 字节不是 0xFF 就是坏块
+    pub fn is_bad_block(&mut self, block: u32) -> Result<bool, Error<SPI::Error>> {
+        if block >= self.part.blocks {
+            return Err(Error::OutOfRange);
+        }
+        for page in 0..2 {
+            self.page_read(block * self.part.pages_per_block + page)?;
+            let mut marker = [0];
+            self.read_cache(self.part.page_size as u16, &mut marker)?;
+            if marker[0] != 0xFF {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// 扫描所有块的坏块标记, 结果会记住, 读写镜像时用
+    pub fn scan_bad_blocks(
+        &mut self,
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<&[u32], Error<SPI::Error>> {
+        let blocks = self.part.blocks;
+        self.bad.clear();
+        for block in 0..blocks {
+            if self.is_bad_block(block)? {
+                self.bad.push(block);
+            }
+            progress(block as usize + 1, blocks as usize);
+        }
+        self.scanned = true;
+        Ok(&self.bad)
+    }
+
+    pub fn bad_blocks(&self) -> &[u32] {
+        &self.bad
+    }
+
+    /// 在 OOB 第 0 字节写 0x00 标成坏块
+    pub fn mark_bad(&mut self, block: u32) -> Result<(), Error<SPI::Error>> {
+        if block >= self.part.blocks {
+            return Err(Error::OutOfRange);
+        }
+        // 擦除失败不影响打标记, SPI 出错就打不了了
+        match self.erase_block(block) {
+            Ok(()) | Err(Error::EraseFailed { .. }) => {}
+            Err(e) => return Err(e),
+        }
+        self.write_enable()?;
+        self.program_load(self.part.page_size as u16, &[0x00], false)?;
+        let result = self.program_execute(block * self.part.pages_per_block);
+        if !self.bad.contains(&block) {
+            self.bad.push(block);
+            self.bad.sort_unstable();
+        }
+        result
+    }
+
+    /// 从 `block` 往后找第一个好块, 扫描过坏块时只查坏块表
+    fn next_good(&mut self, mut block: u32) -> Result<u32, Error<SPI::Error>> {
+        while block < self.part.blocks {
+            if !self.bad.contains(&block) && (self.scanned || !self.is_bad_block(block)?) {
+                return Ok(block);
+            }
+            block += 1;
+        }
+        Err(Error::NoSpace)
+    }
+
+    /// 从 `start` 块开始写镜像, 跳过坏块. 擦写失败的块标成坏块后换下一块.
+    /// 全 0xFF 的页不写. `progress(done, total)`, 返回用到的块
+    pub fn write_image(
+        &mut self,
+        start: u32,
+        data: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<Vec<u32>, Error<SPI::Error>> {
+        let block_size = self.part.block_size() as usize;
+        let page_size = self.part.page_size as usize;
+        let mut used = Vec::new();
+        let mut block = start;
+
+        for (i, chunk) in data.chunks(block_size).enumerate() {
+            loop {
+                block = self.next_good(block)?;
+                match self.write_block(block, chunk, page_size) {
+                    Ok(()) => break,
+                    Err(Error::EraseFailed { .. } | Error::ProgramFailed { .. }) => {
+                        self.mark_bad(block)?;
+                        block += 1;
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            used.push(block);
+            block += 1;
+            progress(i * block_size + chunk.len(), data.len());
+        }
+        Ok(used)
+    }
+
+    fn write_block(
+        &mut self,
+        block: u32,
+        data: &[u8],
+        page_size: usize,
+    ) -> Result<(), Error<SPI::Error>> {
+        self.erase_block(block)?;
+        let first = block * self.part.pages_per_block;
+        for (i, page) in data.chunks(page_size).enumerate() {
+            if page.iter().any(|&b| b != 0xFF) {
+                self.write_page(first + i as u32, page)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 从 `start` 块开始读镜像, 跳过坏块, 和 [`Nand::write_image`] 对应
+    pub fn read_image(
+        &mut self,
+        start: u32,
+        buf: &mut [u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        let block_size = self.part.block_size() as usize;
+        let page_size = self.part.page_size as usize;
+        let total = buf.len();
+        let mut block = start;
+
+        for (i, chunk) in buf.chunks_mut(block_size).enumerate() {
+            block = self.next_good(block)?;
+            let first = block * self.part.pages_per_block;
+            for (j, page) in chunk.chunks_mut(page_size).enumerate() {
+                self.read_page(first + j as u32, page)?;
+            }
+            block += 1;
+            progress(i * block_size + chunk.len(), total);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet};
+
+    use embedded_hal::spi::{ErrorKind, ErrorType};
+
+    use super::*;
+
+    /// 模拟的 SPI NAND: 页缓存, 特性寄存器, 出厂坏块标记和擦除失败的块
+    struct Mock {
+        part: &'static Part,
+        /// 写过或标过的页, 包括 OOB; 没有的页全 0xFF
+        pages: HashMap<u32, Vec<u8>>,
+        cache: Vec<u8>,
+        status: u8,
+        config: u8,
+        /// 读这些页时状态寄存器的 ECC 位
+        ecc: HashMap<u32, u8>,
+        /// 擦除总是失败的块
+        erase_fail: HashSet<u32>,
+        /// PROGRAM_EXECUTE 写过的页
+        programmed: Vec<u32>,
+    }
+
+    impl Mock {
+        fn new(part: &'static Part) -> Self {
+            Self {
+                part,
+                pages: HashMap::new(),
+                cache: vec![0xFF; (part.page_size + part.oob_size) as usize],
+                status: 0,
+                config: CONFIG_OTP_E,
+                ecc: HashMap::new(),
+                erase_fail: HashSet::new(),
+                programmed: Vec::new(),
+            }
+        }
+
+        /// 在块第一页 OOB 第 0 字节打出厂坏块标记
+        fn factory_bad(&mut self, block: u32) {
+            let mut page = self.cache.clone();
+            page.fill(0xFF);
+            page[self.part.page_size as usize] = 0x00;
+            self.pages.insert(block * self.part.pages_per_block, page);
+        }
+
+        fn id(&self) -> [u8; 3] {
+            let mut id = [0; 3];
+            id[0] = self.part.manufacturer;
+            id[1..1 + self.part.device.len()].copy_from_slice(self.part.device);
+            id
+        }
+
+        fn row(tx: &[u8]) -> u32 {
+            u32::from_be_bytes([0, tx[1], tx[2], tx[3]])
+        }
+
+        fn respond(&self, tx: &[u8], buf: &mut [u8]) {
+            match tx[0] {
+                command::READ_JEDEC_ID => buf.copy_from_slice(&self.id()[..buf.len()]),
+                command::GET_FEATURE => {
+                    buf[0] = match tx[1] {
+                        feature::STATUS => self.status,
+                        feature::CONFIG => self.config,
+                        _ => 0,
+                    }
+                }
+                command::READ_CACHE => {
+                    let column = usize::from(u16::from_be_bytes([tx[1], tx[2]]));
+                    buf.copy_from_slice(&self.cache[column..column + buf.len()]);
+                }
+                opcode => panic!("unexpected read after {opcode:#04x}"),
+            }
+        }
+
+        fn command(&mut self, tx: &[u8]) {
+            let len = self.cache.len();
+            match tx[0] {
+                command::SET_FEATURE if tx[1] == feature::CONFIG => self.config = tx[2],
+                command::WRITE_ENABLE => self.status |= STATUS_WEL,
+                command::PAGE_READ => {
+                    let page = Self::row(tx);
+                    self.cache = self
+                        .pages
+                        .get(&page)
+                        .cloned()
+                        .unwrap_or_else(|| vec![0xFF; len]);
+                    self.status = self.ecc.get(&page).map_or(0, |&bits| bits << 4);
+                }
+                command::PROGRAM_LOAD | command::RANDOM_PROGRAM_LOAD => {
+                    if tx[0] == command::PROGRAM_LOAD {
+                        self.cache.fill(0xFF);
+                    }
+                    let column = usize::from(u16::from_be_bytes([tx[1], tx[2]]));
+                    let data = &tx[3..];
+                    self.cache[column..column + data.len()].copy_from_slice(data);
+                }
+                command::PROGRAM_EXECUTE => {
+                    assert_ne!(self.status & STATUS_WEL, 0, "program without WEL");
+                    let page = Self::row(tx);
+                    let cells = self.pages.entry(page).or_insert_with(|| vec![0xFF; len]);
+                    for (cell, &byte) in cells.iter_mut().zip(&self.cache) {
+                        *cell &= byte;
+                    }
+                    self.programmed.push(page);
+                    self.status = 0;
+                }
+                command::BLOCK_ERASE => {
+                    assert_ne!(self.status & STATUS_WEL, 0, "erase without WEL");
+                    let block = Self::row(tx) / self.part.pages_per_block;
+                    self.status = 0;
+                    if self.erase_fail.contains(&block) {
+                        self.status = STATUS_E_FAIL;
+                    } else {
+                        let pages = self.part.pages_per_block;
+                        self.pages.retain(|&page, _| page / pages != block);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    impl ErrorType for Mock {
+        type Error = ErrorKind;
+    }
+
+    impl SpiDevice for Mock {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+            let mut tx = Vec::new();
+            for op in operations {
+                match op {
+                    Operation::Write(buf) => tx.extend_from_slice(buf),
+                    Operation::Read(buf) => self.respond(&tx, buf),
+                    _ => return Err(ErrorKind::Other),
+                }
+            }
+            self.command(&tx);
+            Ok(())
+        }
+    }
+
+    fn part(name: &str) -> &'static Part {
+        PARTS.iter().find(|p| p.name == name).unwrap()
+    }
+
+    fn image(len: usize) -> Vec<u8> {
+        (0..len).map(|i| (i % 251) as u8).collect()
+    }
+
+    #[test]
+    fn find() {
+        let cases = [
+            ([0xEF, 0xAA, 0x20], Some("W25N512GV")),
+            ([0xEF, 0xAA, 0x21], Some("W25N01GV")),
+            ([0xEF, 0xAA, 0x22], Some("W25N02KV")),
+            ([0xC8, 0xD1, 0x7F], Some("GD5F1GQ4U")),
+            ([0xC8, 0xD2, 0x00], Some("GD5F2GQ4U")),
+            ([0xC8, 0x51, 0xC8], Some("GD5F1GQ5U")),
+            ([0xEF, 0xAB, 0x21], None),
+            ([0xEF, 0x21, 0xAA], None),
+            ([0xC8, 0xD3, 0x00], None),
+            ([0x2C, 0x14, 0x00], None),
+        ];
+        for (id, name) in cases {
+            assert_eq!(Part::find(id).map(|p| p.name), name, "{id:02x?}");
+        }
+    }
+
+    #[test]
+    fn ecc_status() {
+        let winbond = part("W25N01GV");
+        let gd = part("GD5F1GQ4U");
+        // 其他位不影响 ECC 状态
+        let other = STATUS_OIP | STATUS_WEL | STATUS_P_FAIL;
+        for (status, w, g) in [
+            (0x00, Ecc::Clean, Ecc::Clean),
+            (0x10, Ecc::Corrected, Ecc::Corrected),
+            (0x20, Ecc::Uncorrectable, Ecc::Uncorrectable),
+            (0x30, Ecc::Uncorrectable, Ecc::Corrected),
+        ] {
+            assert_eq!(winbond.ecc(status | other), w, "{status:#04x}");
+            assert_eq!(gd.ecc(status | other), g, "{status:#04x}");
+        }
+        assert!(!part("GD5F1GQ5U").ecc_11_corrected);
+    }
+
+    #[test]
+    fn new_enables_ecc() {
+        let nand = Nand::new(Mock::new(part("W25N01GV"))).unwrap();
+        assert_eq!(nand.part().name, "W25N01GV");
+        assert_eq!(nand.release().config, CONFIG_ECC_E | CONFIG_BUF);
+
+        let nand = Nand::new(Mock::new(part("GD5F1GQ4U"))).unwrap();
+        assert_eq!(nand.release().config, CONFIG_ECC_E);
+    }
+
+    #[test]
+    fn read_page_ecc() {
+        let mut mock = Mock::new(part("GD5F1GQ4U"));
+        mock.ecc.insert(1, 0b11);
+        mock.ecc.insert(2, 0b10);
+        let mut nand = Nand::new(mock).unwrap();
+        let mut buf = [0; 16];
+        assert_eq!(nand.read_page(0, &mut buf).unwrap(), Ecc::Clean);
+        assert_eq!(nand.read_page(1, &mut buf).unwrap(), Ecc::Corrected);
+        assert!(matches!(
+            nand.read_page(2, &mut buf),
+            Err(Error::Uncorrectable { page: 2 })
+        ));
+    }
+
+    #[test]
+    fn image_skips_bad_blocks() {
+        let p = part("W25N512GV");
+        let block_size = p.block_size() as usize;
+        let page_size = p.page_size as usize;
+        let mut mock = Mock::new(p);
+        mock.factory_bad(1);
+        mock.factory_bad(3);
+        let mut nand = Nand::new(mock).unwrap();
+
+        // 两块半, 第二块的第一页全 0xFF
+        let mut data = image(block_size * 5 / 2);
+        data[block_size..block_size + page_size].fill(0xFF);
+        let used = nand.write_image(0, &data, |_, _| {}).unwrap();
+        assert_eq!(used, [0, 2, 4]);
+
+        let mock = nand.release();
+        let pages = p.pages_per_block;
+        assert!(!mock.programmed.contains(&(2 * pages)));
+        assert!(mock.programmed.contains(&(2 * pages + 1)));
+        // 最后半块只写了一半的页
+        let last = mock.programmed.iter().filter(|&&page| page / pages == 4);
+        assert_eq!(last.count(), pages as usize / 2);
+
+        let mut nand = Nand::new(mock).unwrap();
+        let mut buf = vec![0; data.len()];
+        nand.read_image(0, &mut buf, |_, _| {}).unwrap();
+        assert!(buf == data);
+
+        // 扫描后只查坏块表, 结果一样
+        assert_eq!(nand.scan_bad_blocks(|_, _| {}).unwrap(), [1, 3]);
+        buf.fill(0);
+        nand.read_image(0, &mut buf, |_, _| {}).unwrap();
+        assert!(buf == data);
+    }
+
+    #[test]
+    fn image_marks_failed_blocks() {
+        let p = part("W25N512GV");
+        let block_size = p.block_size() as usize;
+        let mut mock = Mock::new(p);
+        mock.factory_bad(2);
+        mock.erase_fail.insert(1);
+        let mut nand = Nand::new(mock).unwrap();
+
+        let data = image(block_size * 3);
+        let used = nand.write_image(0, &data, |_, _| {}).unwrap();
+        assert_eq!(used, [0, 3, 4]);
+        assert_eq!(nand.bad_blocks(), [1]);
+
+        // 擦除失败的块也打上了标记, 新的实例读得出来
+        let mut nand = Nand::new(nand.release()).unwrap();
+        assert!(nand.is_bad_block(1).unwrap());
+        let mut buf = vec![0; data.len()];
+        nand.read_image(0, &mut buf, |_, _| {}).unwrap();
+        assert!(buf == data);
+    }
+
+    #[test]
+    fn image_no_space() {
+        let p = part("W25N512GV");
+        let mut mock = Mock::new(p);
+        mock.factory_bad(p.blocks - 1);
+        let mut nand = Nand::new(mock).unwrap();
+        let data = image(p.block_size() as usize);
+        assert!(matches!(
+            nand.write_image(p.blocks - 2, &data, |_, _| {}),
+            Ok(used) if used == [p.blocks - 2]
+        ));
+        assert!(matches!(
+            nand.write_image(p.blocks - 1, &data, |_, _| {}),
+            Err(Error::NoSpace)
+        ));
+    }
+}
@@ -0,0 +1,465 @@
+//! SPI NOR Flash
+//!
+//! 先读 JEDEC ID, 再读 SFDP 拿到容量、页大小、擦除类型和地址模式.
+//! 没有 SFDP 的老芯片按 JEDEC ID 的容量字节和常见的 4K/32K/64K 擦除命令处理.
+//! 超过 16MB 的芯片进入 4 字节地址模式. 写保护和 OTP 见 [`protect`]
+
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use crate::util::first_mismatch;
+
+pub mod protect;
+pub mod sfdp;
+
+pub use protect::{Layout, Protection};
+pub use sfdp::{AddressMode, EraseType, Parameters};
+
+pub mod command {
+    pub const WRITE_ENABLE: u8 = 0x06;
+    pub const WRITE_DISABLE: u8 = 0x04;
+    pub const READ_STATUS: u8 = 0x05;
+    pub const READ: u8 = 0x03;
+    pub const PAGE_PROGRAM: u8 = 0x02;
+    pub const CHIP_ERASE: u8 = 0xC7;
+    pub const READ_JEDEC_ID: u8 = 0x9F;
+    pub const READ_SFDP: u8 = 0x5A;
+    pub const ENTER_4BYTE: u8 = 0xB7;
+    pub const EXIT_4BYTE: u8 = 0xE9;
+}
+
+/// 状态寄存器 1
+pub const STATUS_WIP: u8 = 0x01;
+pub const STATUS_WEL: u8 = 0x02;
+
+const PROGRAM_TIMEOUT: Duration = Duration::from_millis(50);
+const ERASE_TIMEOUT: Duration = Duration::from_secs(5);
+const CHIP_ERASE_TIMEOUT: Duration = Duration::from_secs(400);
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    OutOfRange,
+    /// 擦除地址或长度没有按擦除单位对齐
+    NotAligned,
+    /// 等 WIP 清零超时
+    Timeout,
+    /// 写使能之后 WEL 没有置位, 可能是写保护
+    WriteEnable,
+    /// 读到的 JEDEC ID 全 0 或全 1, 通常是没接上
+    NoDevice,
+    /// 这个芯片没有这个功能, 或者不认识这个厂商
+    Unsupported,
+    Verify {
+        address: u32,
+        expected: u8,
+        found: u8,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JedecId {
+    pub manufacturer: u8,
+    pub memory_type: u8,
+    pub capacity: u8,
+}
+
+impl JedecId {
+    /// 大多数厂商的容量字节是 log2(字节数)
+    pub fn size(&self) -> u64 {
+        if (0x10..=0x22).contains(&self.capacity) {
+            1 << self.capacity
+        } else {
+            0
+        }
+    }
+}
+
+pub struct Flash<SPI> {
+    spi: SPI,
+    id: JedecId,
+    params: Parameters,
+    address_bytes: usize,
+}
+
+impl<SPI: SpiDevice> Flash<SPI> {
+    pub fn new(spi: SPI) -> Result<Self, Error<SPI::Error>> {
+        let mut flash = Self {
+            spi,
+            id: JedecId {
+                manufacturer: 0,
+                memory_type: 0,
+                capacity: 0,
+            },
+            params: Parameters {
+                version: (0, 0),
+                size: 0,
+                page_size: 256,
+                address_mode: AddressMode::ThreeByte,
+                erase_types: Vec::new(),
+            },
+            address_bytes: 3,
+        };
+
+        flash.id = flash.read_jedec_id()?;
+        if matches!(flash.id.manufacturer, 0x00 | 0xFF) {
+            return Err(Error::NoDevice);
+        }
+        flash.params = match flash.read_sfdp()? {
+            Some(params) => params,
+            None => flash.default_parameters(),
+        };
+        if flash.params.erase_types.is_empty() {
+            flash.params.erase_types = flash.default_parameters().erase_types;
+        }
+
+        if flash.params.size > 1 << 24 && flash.params.address_mode != AddressMode::ThreeByte {
+            flash.command(&[command::ENTER_4BYTE])?;
+            flash.address_bytes = 4;
+        }
+        Ok(flash)
+    }
+
+    fn default_parameters(&self) -> Parameters {
+        Parameters {
+            version: (0, 0),
+            size: self.id.size(),
+            page_size: 256,
+            address_mode: if self.id.size() > 1 << 24 {
+                AddressMode::ThreeOrFour
+            } else {
+                AddressMode::ThreeByte
+            },
+            erase_types: vec![
+                EraseType {
+                    size: 4096,
+                    opcode: 0x20,
+                },
+                EraseType {
+                    size: 32768,
+                    opcode: 0x52,
+                },
+                EraseType {
+                    size: 65536,
+                    opcode: 0xD8,
+                },
+            ],
+        }
+    }
+
+    pub fn release(self) -> SPI {
+        self.spi
+    }
+
+    pub fn id(&self) -> JedecId {
+        self.id
+    }
+
+    pub fn parameters(&self) -> &Parameters {
+        &self.params
+    }
+
+    pub fn size(&self) -> u32 {
+        self.params.size.min(u64::from(u32::MAX)) as u32
+    }
+
+    /// 最小的擦除单位
+    pub fn sector_size(&self) -> u32 {
+        self.params
+            .erase_types
+            .first()
+            .map(|e| e.size)
+            .unwrap_or(4096)
+    }
+
+    fn command(&mut self, cmd: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.spi.write(cmd).map_err(Error::Spi)
+    }
+
+    fn read_jedec_id(&mut self) -> Result<JedecId, Error<SPI::Error>> {
+        let mut id = [0; 3];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::READ_JEDEC_ID]),
+                Operation::Read(&mut id),
+            ])
+            .map_err(Error::Spi)?;
+        Ok(JedecId {
+            manufacturer: id[0],
+            memory_type: id[1],
+            capacity: id[2],
+        })
+    }
+
+    /// SFDP 总是 3 字节地址加 1 个 dummy 字节
+    fn sfdp_read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        let a = address.to_be_bytes();
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::READ_SFDP, a[1], a[2], a[3], 0x00]),
+                Operation::Read(buf),
+            ])
+            .map_err(Error::Spi)
+    }
+
+    /// 不支持 SFDP 时返回 `None`
+    pub fn read_sfdp(&mut self) -> Result<Option<Parameters>, Error<SPI::Error>> {
+        let mut header = vec![0; 16];
+        self.sfdp_read(0, &mut header)?;
+        if &header[..4] != b"SFDP" {
+            return Ok(None);
+        }
+        header.resize(sfdp::header_len(&header), 0);
+        self.sfdp_read(0, &mut header)?;
+
+        let Some((pointer, dwords)) = sfdp::find_bfpt(&header) else {
+            return Ok(None);
+        };
+        let mut table = vec![0; dwords * 4];
+        self.sfdp_read(pointer, &mut table)?;
+        Ok(sfdp::parse(&header, &table))
+    }
+
+    pub fn read_status(&mut self) -> Result<u8, Error<SPI::Error>> {
+        let mut status = [0];
+        self.spi
+            .transaction(&mut [
+                Operation::Write(&[command::READ_STATUS]),
+                Operation::Read(&mut status),
+            ])
+            .map_err(Error::Spi)?;
+        Ok(status[0])
+    }
+
+    /// 轮询 WIP
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), Error<SPI::Error>> {
+        let start = Instant::now();
+        loop {
+            if self.read_status()? & STATUS_WIP == 0 {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_micros(100));
+        }
+    }
+
+    /// 写使能后检查 WEL
+    pub fn write_enable(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.command(&[command::WRITE_ENABLE])?;
+        if self.read_status()? & STATUS_WEL == 0 {
+            return Err(Error::WriteEnable);
+        }
+        Ok(())
+    }
+
+    fn address(&self, address: u32) -> Vec<u8> {
+        address.to_be_bytes()[4 - self.address_bytes..].to_vec()
+    }
+
+    fn check_range(&self, address: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if u64::from(address) + len as u64 > self.params.size {
+            return Err(Error::OutOfRange);
+        }
+        Ok(())
+    }
+
+    pub fn read(&mut self, address: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, buf.len())?;
+        let mut cmd = vec![command::READ];
+        cmd.extend(self.address(address));
+        self.spi
+            .transaction(&mut [Operation::Write(&cmd), Operation::Read(buf)])
+            .map_err(Error::Spi)
+    }
+
+    /// 写入已经擦除过的区域, 按页拆开
+    pub fn write(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, data.len())?;
+        let page = self.params.page_size as usize;
+        let mut done = 0;
+        while done < data.len() {
+            let at = address as usize + done;
+            let len = (page - at % page).min(data.len() - done);
+            self.page_program(at as u32, &data[done..done + len])?;
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn page_program(&mut self, address: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.write_enable()?;
+        let mut cmd = vec![command::PAGE_PROGRAM];
+        cmd.extend(self.address(address));
+        self.spi
+            .transaction(&mut [Operation::Write(&cmd), Operation::Write(data)])
+            .map_err(Error::Spi)?;
+        self.wait_ready(PROGRAM_TIMEOUT)
+    }
+
+    /// 用指定的擦除类型擦一块, `address` 要按这个类型对齐
+    pub fn erase_block(&mut self, address: u32, erase: EraseType) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, erase.size as usize)?;
+        if !address.is_multiple_of(erase.size) {
+            return Err(Error::NotAligned);
+        }
+        self.write_enable()?;
+        let mut cmd = vec![erase.opcode];
+        cmd.extend(self.address(address));
+        self.command(&cmd)?;
+        self.wait_ready(ERASE_TIMEOUT)
+    }
+
+    /// 擦除最小单位
+    pub fn erase_sector(&mut self, address: u32) -> Result<(), Error<SPI::Error>> {
+        let erase = self.params.erase_types[0];
+        self.erase_block(address, erase)
+    }
+
+    pub fn erase_chip(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.write_enable()?;
+        self.command(&[command::CHIP_ERASE])?;
+        self.wait_ready(CHIP_ERASE_TIMEOUT)
+    }
+
+    /// 擦除 [from, to), 每次选能对齐的最大擦除类型
+    pub fn erase(&mut self, from: u32, to: u32) -> Result<(), Error<SPI::Error>> {
+        let sector = self.sector_size();
+        if !from.is_multiple_of(sector) || !to.is_multiple_of(sector) || from > to {
+            return Err(Error::NotAligned);
+        }
+        self.check_range(from, (to - from) as usize)?;
+
+        let mut at = from;
+        while at < to {
+            let erase = *self
+                .params
+                .erase_types
+                .iter()
+                .rev()
+                .find(|e| at.is_multiple_of(e.size) && at + e.size <= to)
+                .ok_or(Error::NotAligned)?;
+            self.erase_block(at, erase)?;
+            at += erase.size;
+        }
+        Ok(())
+    }
+
+    /// 按扇区写入, 内容一样的扇区跳过. 扇区里不在 `data` 范围内的部分保持原样.
+    /// `progress(done, total)`, 返回实际擦写的扇区数
+    pub fn program(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<usize, Error<SPI::Error>> {
+        self.check_range(address, data.len())?;
+        let sector = self.sector_size();
+        let end = address + data.len() as u32;
+        let mut at = address - address % sector;
+        let mut buf = vec![0; sector as usize];
+        let mut changed = 0;
+
+        while at < end {
+            self.read(at, &mut buf)?;
+            let mut wanted = buf.clone();
+            let from = at.max(address);
+            let to = (at + sector).min(end);
+            wanted[(from - at) as usize..(to - at) as usize]
+                .copy_from_slice(&data[(from - address) as usize..(to - address) as usize]);
+
+            if wanted != buf {
+                self.erase_sector(at)?;
+                // 擦完是 0xFF, 全 0xFF 的页不用写
+                let page = self.params.page_size as usize;
+                for (i, chunk) in wanted.chunks(page).enumerate() {
+                    if chunk.iter().any(|&b| b != 0xFF) {
+                        self.page_program(at + (i * page) as u32, chunk)?;
+                    }
+                }
+                changed += 1;
+            }
+            at += sector;
+            progress((to - address) as usize, data.len());
+        }
+        Ok(changed)
+    }
+
+    /// 按 4K 读回和 `data` 比较, [`Error::Verify`] 给出第一个不同字节的地址
+    pub fn verify(
+        &mut self,
+        address: u32,
+        data: &[u8],
+        mut progress: impl FnMut(usize, usize),
+    ) -> Result<(), Error<SPI::Error>> {
+        self.check_range(address, data.len())?;
+        let mut buf = vec![0; 4096];
+        for (i, expected) in data.chunks(4096).enumerate() {
+            let base = address + (i * 4096) as u32;
+            let found = &mut buf[..expected.len()];
+            self.read(base, found)?;
+            if let Some((j, expected, found)) = first_mismatch(expected, found) {
+                return Err(Error::Verify {
+                    address: base + j as u32,
+                    expected,
+                    found,
+                });
+            }
+            progress(i * 4096 + expected.len(), data.len());
+        }
+        Ok(())
+    }
+}
+
+mod embedded_storage_impl {
+    use embedded_hal::spi::SpiDevice;
+    use embedded_storage::nor_flash::{
+        ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+    };
+
+    use super::{Error, Flash};
+
+    impl<E: core::fmt::Debug> NorFlashError for Error<E> {
+        fn kind(&self) -> NorFlashErrorKind {
+            match self {
+                Error::OutOfRange => NorFlashErrorKind::OutOfBounds,
+                Error::NotAligned => NorFlashErrorKind::NotAligned,
+                _ => NorFlashErrorKind::Other,
+            }
+        }
+    }
+
+    impl<SPI: SpiDevice> ErrorType for Flash<SPI> {
+        type Error = Error<SPI::Error>;
+    }
+
+    impl<SPI: SpiDevice> ReadNorFlash for Flash<SPI> {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            Flash::read(self, offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.size() as usize
+        }
+    }
+
+    /// `ERASE_SIZE` 固定按 4K 算. SFDP 里没有 4K 擦除的芯片, 最小擦除单位是
+    /// [`Flash::sector_size`], 没按它对齐的擦除返回 [`Error::NotAligned`]
+    impl<SPI: SpiDevice> NorFlash for Flash<SPI> {
+        const WRITE_SIZE: usize = 1;
+        const ERASE_SIZE: usize = 4096;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            Flash::erase(self, from, to)
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            Flash::write(self, offset, bytes)
+        }
+    }
+}
@@ -0,0 +1,794 @@
+//! 状态寄存器、写保护、安全寄存器 (OTP) 和唯一 ID
+//!
+//! 各家的位定义不一样, 按厂商 ID 查 [`VENDORS`], 同一家里位定义不同的型号放在 [`PART_LAYOUTS`].
+//! 三个状态寄存器拼成一个 24 位的值,
+//! SR1 在最低字节, 表里的位置都是在这个值里的位号.
+//! Macronix 的配置寄存器和 ISSI 的功能寄存器也当作状态寄存器处理
+
+use std::ops::Range;
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+use super::{Error, Flash, JedecId, PROGRAM_TIMEOUT, command};
+
+/// 写状态寄存器的方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StatusWrite {
+    /// 每个寄存器单独写, 比如 Winbond 的 0x01/0x31/0x11
+    Separate([Option<u8>; 3]),
+    /// 0x01 后面跟 SR1 和 SR2 (Macronix 的配置寄存器)
+    Combined,
+}
+
+/// BP 位每加一保护多大
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BpUnit {
+    /// BP=1 保护 1/64, 之后每次翻倍, BP 全 1 保护整片
+    Fraction64,
+    /// BP=1 保护一个 64K 块, 之后每次翻倍, BP 全 1 保护整片
+    Block64K,
+}
+
+/// 安全寄存器 (OTP)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Security {
+    /// 用独立的读/写/擦命令按地址访问, 锁定位在状态寄存器里
+    Registers {
+        read: u8,
+        program: u8,
+        erase: u8,
+        /// 每个寄存器的起始地址
+        addresses: &'static [u32],
+        size: u32,
+        /// 每个寄存器对应的锁定位
+        lock_bits: &'static [u8],
+    },
+    /// Macronix: 进入 Secured OTP 模式后用普通的读和页编程访问, 不能擦除.
+    /// 锁定位是安全寄存器 (0x2B 读, 0x2F 置位) 的 LDSO
+    Mode { enter: u8, exit: u8, size: u32 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub vendor: &'static str,
+    pub manufacturer: u8,
+    /// SR1 ~ SR3 的读命令
+    pub read: [Option<u8>; 3],
+    pub write: StatusWrite,
+    /// 写易失状态寄存器前用的写使能, 掉电后恢复
+    pub volatile_enable: Option<u8>,
+    /// BP 位的 (最低位, 位数)
+    pub bp: (u8, u8),
+    pub bp_unit: BpUnit,
+    pub tb: Option<u8>,
+    pub sec: Option<u8>,
+    pub cmp: Option<u8>,
+    pub srp0: Option<u8>,
+    pub srp1: Option<u8>,
+    pub qe: Option<u8>,
+    /// 0 用 BP 保护, 1 用单独的块锁
+    pub wps: Option<u8>,
+    /// 支持 0x7E/0x98 全局块锁定/解锁
+    pub global_lock: bool,
+    pub security: Option<Security>,
+    /// 唯一 ID 命令 0x4B 后面的 dummy 字节数和 ID 长度
+    pub unique_id: Option<(usize, usize)>,
+}
+
+const WINBOND: Layout = Layout {
+    vendor: "Winbond",
+    manufacturer: 0xEF,
+    read: [Some(0x05), Some(0x35), Some(0x15)],
+    write: StatusWrite::Separate([Some(0x01), Some(0x31), Some(0x11)]),
+    volatile_enable: Some(0x50),
+    bp: (2, 3),
+    bp_unit: BpUnit::Fraction64,
+    tb: Some(5),
+    sec: Some(6),
+    cmp: Some(14),
+    srp0: Some(7),
+    srp1: Some(8),
+    qe: Some(9),
+    wps: Some(18),
+    global_lock: true,
+    security: Some(Security::Registers {
+        read: 0x48,
+        program: 0x42,
+        erase: 0x44,
+        addresses: &[0x1000, 0x2000, 0x3000],
+        size: 256,
+        lock_bits: &[11, 12, 13],
+    }),
+    unique_id: Some((4, 8)),
+};
+
+pub const VENDORS: &[Layout] = &[
+    WINBOND,
+    Layout {
+        vendor: "GigaDevice",
+        manufacturer: 0xC8,
+        read: [Some(0x05), Some(0x35), Some(0x15)],
+        write: StatusWrite::Separate([Some(0x01), Some(0x31), Some(0x11)]),
+        volatile_enable: Some(0x50),
+        bp: (2, 3),
+        bp_unit: BpUnit::Fraction64,
+        tb: Some(5),
+        sec: Some(6),
+        cmp: Some(14),
+        srp0: Some(7),
+        srp1: Some(8),
+        qe: Some(9),
+        wps: None,
+        global_lock: false,
+        security: Some(Security::Registers {
+            read: 0x48,
+            program: 0x42,
+            erase: 0x44,
+            addresses: &[0x1000, 0x2000, 0x3000],
+            size: 256,
+            lock_bits: &[11, 12, 13],
+        }),
+        unique_id: Some((4, 16)),
+    },
+    Layout {
+        vendor: "Macronix",
+        manufacturer: 0xC2,
+        read: [Some(0x05), Some(0x15), None],
+        write: StatusWrite::Combined,
+        volatile_enable: None,
+        bp: (2, 4),
+        bp_unit: BpUnit::Block64K,
+        tb: Some(11),
+        sec: None,
+        cmp: None,
+        srp0: Some(7),
+        srp1: None,
+        qe: Some(6),
+        wps: None,
+        global_lock: true,
+        security: Some(Security::Mode {
+            enter: 0xB1,
+            exit: 0xC1,
+            size: 512,
+        }),
+        unique_id: None,
+    },
+    Layout {
+        vendor: "ISSI",
+        manufacturer: 0x9D,
+        // 第三个是功能寄存器
+        read: [Some(0x05), None, Some(0x48)],
+        write: StatusWrite::Separate([Some(0x01), None, Some(0x42)]),
+        volatile_enable: None,
+        bp: (2, 4),
+        bp_unit: BpUnit::Block64K,
+        tb: Some(17),
+        sec: None,
+        cmp: None,
+        srp0: Some(7),
+        srp1: None,
+        qe: Some(6),
+        wps: None,
+        global_lock: false,
+        security: Some(Security::Registers {
+            read: 0x68,
+            program: 0x62,
+            erase: 0x64,
+            addresses: &[0x000, 0x100, 0x200, 0x300],
+            size: 256,
+            lock_bits: &[20, 21, 22, 23],
+        }),
+        unique_id: Some((4, 16)),
+    },
+];
+
+/// 跟厂商默认位定义不一样的型号, 按 [`PARTS`] 里的 (厂商, 类型, 容量) 查
+pub const PART_LAYOUTS: &[((u8, u8, u8), Layout)] = &[(
+    // W25Q256JV: BP0 ~ BP3 在 SR1 的 2 ~ 5 位, TB 在第 6 位, 没有 SEC, BP 按 64K 块翻倍
+    (0xEF, 0x40, 0x19),
+    Layout {
+        bp: (2, 4),
+        bp_unit: BpUnit::Block64K,
+        tb: Some(6),
+        sec: None,
+        ..WINBOND
+    },
+)];
+
+/// 常见型号, (厂商, 类型, 容量, 名字)
+pub const PARTS: &[(u8, u8, u8, &str)] = &[
+    (0xEF, 0x40, 0x15, "W25Q16JV"),
+    (0xEF, 0x40, 0x16, "W25Q32JV"),
+    (0xEF, 0x40, 0x17, "W25Q64JV"),
+    (0xEF, 0x40, 0x18, "W25Q128JV"),
+    (0xEF, 0x40, 0x19, "W25Q256JV"),
+    (0xEF, 0x70, 0x18, "W25Q128JV-M"),
+    (0xC8, 0x40, 0x15, "GD25Q16C"),
+    (0xC8, 0x40, 0x16, "GD25Q32C"),
+    (0xC8, 0x40, 0x17, "GD25Q64C"),
+    (0xC8, 0x40, 0x18, "GD25Q128C"),
+    (0xC2, 0x20, 0x16, "MX25L3233F"),
+    (0xC2, 0x20, 0x17, "MX25L6433F"),
+    (0xC2, 0x20, 0x18, "MX25L12835F"),
+    (0xC2, 0x20, 0x19, "MX25L25645G"),
+    (0x9D, 0x60, 0x16, "IS25LP032"),
+    (0x9D, 0x60, 0x17, "IS25LP064"),
+    (0x9D, 0x60, 0x18, "IS25LP128"),
+];
+
+pub fn part_name(id: JedecId) -> Option<&'static str> {
+    PARTS
+        .iter()
+        .find(|p| (p.0, p.1, p.2) == (id.manufacturer, id.memory_type, id.capacity))
+        .map(|p| p.3)
+}
+
+/// 写保护相关的位, 芯片没有的位读出来总是 `false`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Protection {
+    pub bp: u8,
+    /// 从底部开始保护
+    pub tb: bool,
+    /// 按 4K 扇区保护
+    pub sec: bool,
+    /// 取反保护范围
+    pub cmp: bool,
+    pub srp0: bool,
+    pub srp1: bool,
+    pub wps: bool,
+}
+
+fn bit(value: u32, index: Option<u8>) -> bool {
+    index.is_some_and(|i| value & (1 << i) != 0)
+}
+
+/// 芯片没有这个位时只能写 `false`
+fn set_bit<E>(value: &mut u32, index: Option<u8>, on: bool) -> Result<(), Error<E>> {
+    match index {
+        Some(i) if on => *value |= 1 << i,
+        Some(i) => *value &= !(1 << i),
+        None if on => return Err(Error::Unsupported),
+        None => {}
+    }
+    Ok(())
+}
+
+impl Layout {
+    pub fn find(manufacturer: u8) -> Option<&'static Layout> {
+        VENDORS.iter().find(|l| l.manufacturer == manufacturer)
+    }
+
+    /// 先查 [`PART_LAYOUTS`], 没有再按厂商查
+    pub fn for_id(id: JedecId) -> Option<&'static Layout> {
+        PART_LAYOUTS
+            .iter()
+            .find(|(part, _)| *part == (id.manufacturer, id.memory_type, id.capacity))
+            .map(|(_, layout)| layout)
+            .or_else(|| Self::find(id.manufacturer))
+    }
+
+    pub fn decode(&self, status: u32) -> Protection {
+        let (offset, width) = self.bp;
+        Protection {
+            bp: ((status >> offset) & ((1 << width) - 1)) as u8,
+            tb: bit(status, self.tb),
+            sec: bit(status, self.sec),
+            cmp: bit(status, self.cmp),
+            srp0: bit(status, self.srp0),
+            srp1: bit(status, self.srp1),
+            wps: bit(status, self.wps),
+        }
+    }
+
+    pub fn encode<E>(&self, status: u32, p: &Protection) -> Result<u32, Error<E>> {
+        let (offset, width) = self.bp;
+        let mask = (1u32 << width) - 1;
+        if u32::from(p.bp) > mask {
+            return Err(Error::Unsupported);
+        }
+        let mut value = (status & !(mask << offset)) | (u32::from(p.bp) << offset);
+        set_bit(&mut value, self.tb, p.tb)?;
+        set_bit(&mut value, self.sec, p.sec)?;
+        set_bit(&mut value, self.cmp, p.cmp)?;
+        set_bit(&mut value, self.srp0, p.srp0)?;
+        set_bit(&mut value, self.srp1, p.srp1)?;
+        set_bit(&mut value, self.wps, p.wps)?;
+        Ok(value)
+    }
+
+    /// 按常见的规律算 BP 保护的地址范围, 具体以数据手册为准.
+    /// BP 全 1 时不管 SEC 都保护整片
+    pub fn protected_range(&self, p: &Protection, size: u32) -> Range<u32> {
+        let all = (1u8 << self.bp.1) - 1;
+        let len = if p.bp == 0 {
+            0
+        } else if p.bp == all {
+            size
+        } else if p.sec {
+            4096u32 << (p.bp - 1).min(3)
+        } else {
+            let unit = match self.bp_unit {
+                BpUnit::Fraction64 => size / 64,
+                BpUnit::Block64K => 65536,
+            };
+            unit.checked_shl(u32::from(p.bp - 1))
+                .unwrap_or(size)
+                .min(size)
+        };
+
+        match (p.tb, p.cmp) {
+            (false, false) => size - len..size,
+            (true, false) => 0..len,
+            (false, true) => 0..size - len,
+            (true, true) => len..size,
+        }
+    }
+}
+
+impl<SPI: SpiDevice> Flash<SPI> {
+    pub fn layout(&self) -> Option<&'static Layout> {
+        Layout::for_id(self.id)
+    }
+
+    pub fn part_name(&self) -> Option<&'static str> {
+        part_name(self.id)
+    }
+
+    fn require_layout(&self) -> Result<&'static Layout, Error<SPI::Error>> {
+        self.layout().ok_or(Error::Unsupported)
+    }
+
+    fn read_register(&mut self, opcode: u8) -> Result<u8, Error<SPI::Error>> {
+        let mut value = [0];
+        self.spi
+            .transaction(&mut [Operation::Write(&[opcode]), Operation::Read(&mut value)])
+            .map_err(Error::Spi)?;
+        Ok(value[0])
+    }
+
+    /// SR1 | SR2 << 8 | SR3 << 16, 没有的寄存器为 0
+    pub fn read_status_registers(&mut self) -> Result<u32, Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        let mut value = 0;
+        for (i, opcode) in layout.read.iter().enumerate() {
+            if let Some(opcode) = opcode {
+                value |= u32::from(self.read_register(*opcode)?) << (i * 8);
+            }
+        }
+        Ok(value)
+    }
+
+    /// 只写有变化的寄存器. `volatile` 时用易失写使能, 掉电后恢复
+    pub fn write_status_registers(
+        &mut self,
+        value: u32,
+        volatile: bool,
+    ) -> Result<(), Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        let old = self.read_status_registers()?;
+        let enable = if volatile {
+            Some(layout.volatile_enable.ok_or(Error::Unsupported)?)
+        } else {
+            None
+        };
+        let byte = |v: u32, i: usize| (v >> (i * 8)) as u8;
+
+        match layout.write {
+            StatusWrite::Separate(opcodes) => {
+                for (i, opcode) in opcodes.iter().enumerate() {
+                    if let Some(opcode) = opcode
+                        && byte(old, i) != byte(value, i)
+                    {
+                        self.status_write_enable(enable)?;
+                        self.command(&[*opcode, byte(value, i)])?;
+                        self.wait_ready(PROGRAM_TIMEOUT)?;
+                    }
+                }
+            }
+            StatusWrite::Combined => {
+                if old & 0xFFFF != value & 0xFFFF {
+                    self.status_write_enable(enable)?;
+                    self.command(&[0x01, byte(value, 0), byte(value, 1)])?;
+                    self.wait_ready(PROGRAM_TIMEOUT)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn status_write_enable(&mut self, volatile: Option<u8>) -> Result<(), Error<SPI::Error>> {
+        match volatile {
+            Some(opcode) => self.command(&[opcode]),
+            None => self.write_enable(),
+        }
+    }
+
+    pub fn protection(&mut self) -> Result<Protection, Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        Ok(layout.decode(self.read_status_registers()?))
+    }
+
+    pub fn set_protection(
+        &mut self,
+        protection: &Protection,
+        volatile: bool,
+    ) -> Result<(), Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        let value = layout.encode(self.read_status_registers()?, protection)?;
+        self.write_status_registers(value, volatile)
+    }
+
+    /// 当前被 BP 保护的地址范围
+    pub fn protected_range(&mut self) -> Result<Range<u32>, Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        let p = layout.decode(self.read_status_registers()?);
+        Ok(layout.protected_range(&p, self.size()))
+    }
+
+    /// 清掉 BP/TB/SEC/CMP, 不动 SRP
+    pub fn unprotect(&mut self, volatile: bool) -> Result<(), Error<SPI::Error>> {
+        let p = self.protection()?;
+        self.set_protection(
+            &Protection {
+                bp: 0,
+                tb: false,
+                sec: false,
+                cmp: false,
+                ..p
+            },
+            volatile,
+        )
+    }
+
+    pub fn quad_enabled(&mut self) -> Result<bool, Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        Ok(bit(self.read_status_registers()?, layout.qe))
+    }
+
+    pub fn set_quad_enable(
+        &mut self,
+        enable: bool,
+        volatile: bool,
+    ) -> Result<(), Error<SPI::Error>> {
+        let layout = self.require_layout()?;
+        let mut value = self.read_status_registers()?;
+        set_bit(&mut value, layout.qe, enable)?;
+        self.write_status_registers(value, volatile)
+    }
+
+    /// 锁定所有块, 需要 WPS=1 (Winbond) 或者 WPSEL=1 (Macronix)
+    pub fn global_lock(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.global(0x7E)
+    }
+
+    pub fn global_unlock(&mut self) -> Result<(), Error<SPI::Error>> {
+        self.global(0x98)
+    }
+
+    fn global(&mut self, opcode: u8) -> Result<(), Error<SPI::Error>> {
+        if !self.require_layout()?.global_lock {
+            return Err(Error::Unsupported);
+        }
+        self.write_enable()?;
+        self.command(&[opcode])?;
+        self.wait_ready(PROGRAM_TIMEOUT)
+    }
+
+    pub fn unique_id(&mut self) -> Result<Vec<u8>, Error<SPI::Error>> {
+        let (dummy, len) = self.require_layout()?.unique_id.ok_or(Error::Unsupported)?;
+        let mut cmd = vec![0x4B];
+        cmd.resize(1 + dummy, 0x00);
+        let mut id = vec![0; len];
+        self.spi
+            .transaction(&mut [Operation::Write(&cmd), Operation::Read(&mut id)])
+            .map_err(Error::Spi)?;
+        Ok(id)
+    }
+
+    fn security(&self) -> Result<Security, Error<SPI::Error>> {
+        self.require_layout()?.security.ok_or(Error::Unsupported)
+    }
+
+    /// 安全寄存器的个数和每个的大小
+    pub fn security_geometry(&self) -> Result<(usize, u32), Error<SPI::Error>> {
+        Ok(match self.security()? {
+            Security::Registers {
+                addresses, size, ..
+            } => (addresses.len(), size),
+            Security::Mode { size, .. } => (1, size),
+        })
+    }
+
+    fn security_address(
+        &self,
+        index: usize,
+        offset: u32,
+        len: usize,
+    ) -> Result<u32, Error<SPI::Error>> {
+        let (count, size) = self.security_geometry()?;
+        if index >= count || offset as usize + len > size as usize {
+            return Err(Error::OutOfRange);
+        }
+        Ok(match self.security()? {
+            Security::Registers { addresses, .. } => addresses[index] + offset,
+            Security::Mode { .. } => offset,
+        })
+    }
+
+    pub fn read_security(
+        &mut self,
+        index: usize,
+        offset: u32,
+        buf: &mut [u8],
+    ) -> Result<(), Error<SPI::Error>> {
+        let address = self.security_address(index, offset, buf.len())?;
+        match self.security()? {
+            Security::Registers { read, .. } => {
+                let mut cmd = vec![read];
+                cmd.extend(self.address(address));
+                cmd.push(0x00);
+                self.spi
+                    .transaction(&mut [Operation::Write(&cmd), Operation::Read(buf)])
+                    .map_err(Error::Spi)
+            }
+            Security::Mode { enter, exit, .. } => {
+                self.command(&[enter])?;
+                let mut cmd = vec![command::READ];
+                cmd.extend(self.address(address));
+                let result = self
+                    .spi
+                    .transaction(&mut [Operation::Write(&cmd), Operation::Read(buf)])
+                    .map_err(Error::Spi);
+                self.command(&[exit])?;
+                result
+            }
+        }
+    }
+
+    /// 只能把 1 写成 0, 不能跨页
+    pub fn program_security(
+        &mut self,
+        index: usize,
+        offset: u32,
+        data: &[u8],
+    ) -> Result<(), Error<SPI::Error>> {
+        let address = self.security_address(index, offset, data.len())?;
+        let page = self.params.page_size;
+        if offset / page != (offset + data.len().max(1) as u32 - 1) / page {
+            return Err(Error::NotAligned);
+        }
+        match self.security()? {
+            Security::Registers { program, .. } => {
+                self.write_enable()?;
+                let mut cmd = vec![program];
+                cmd.extend(self.address(address));
+                self.spi
+                    .transaction(&mut [Operation::Write(&cmd), Operation::Write(data)])
+                    .map_err(Error::Spi)?;
+                self.wait_ready(PROGRAM_TIMEOUT)
+            }
+            Security::Mode { enter, exit, .. } => {
+                self.command(&[enter])?;
+                let result = self.page_program(address, data);
+                self.command(&[exit])?;
+                result
+            }
+        }
+    }
+
+    /// Macronix 的 Secured OTP 不能擦除
+    pub fn erase_security(&mut self, index: usize) -> Result<(), Error<SPI::Error>> {
+        let address = self.security_address(index, 0, 0)?;
+        match self.security()? {
+            Security::Registers { erase, .. } => {
+                self.write_enable()?;
+                let mut cmd = vec![erase];
+                cmd.extend(self.address(address));
+                self.command(&cmd)?;
+                self.wait_ready(super::ERASE_TIMEOUT)
+            }
+            Security::Mode { .. } => Err(Error::Unsupported),
+        }
+    }
+
+    pub fn security_locked(&mut self, index: usize) -> Result<bool, Error<SPI::Error>> {
+        self.security_address(index, 0, 0)?;
+        match self.security()? {
+            Security::Registers { lock_bits, .. } => {
+                let status = self.read_status_registers()?;
+                Ok(bit(status, Some(lock_bits[index])))
+            }
+            // LDSO
+            Security::Mode { .. } => Ok(self.read_register(0x2B)? & 0x02 != 0),
+        }
+    }
+
+    /// 永久锁定, 不能恢复
+    pub fn lock_security(&mut self, index: usize) -> Result<(), Error<SPI::Error>> {
+        self.security_address(index, 0, 0)?;
+        match self.security()? {
+            Security::Registers { lock_bits, .. } => {
+                let mut value = self.read_status_registers()?;
+                value |= 1 << lock_bits[index];
+                self.write_status_registers(value, false)
+            }
+            Security::Mode { .. } => {
+                self.write_enable()?;
+                self.command(&[0x2F])?;
+                self.wait_ready(PROGRAM_TIMEOUT)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const M: u32 = 1 << 20;
+    const K: u32 = 1 << 10;
+
+    fn layout(manufacturer: u8, memory_type: u8, capacity: u8) -> &'static Layout {
+        Layout::for_id(JedecId {
+            manufacturer,
+            memory_type,
+            capacity,
+        })
+        .unwrap()
+    }
+
+    fn w25q128() -> &'static Layout {
+        layout(0xEF, 0x40, 0x18)
+    }
+
+    fn w25q256() -> &'static Layout {
+        layout(0xEF, 0x40, 0x19)
+    }
+
+    fn mx25l128() -> &'static Layout {
+        layout(0xC2, 0x20, 0x18)
+    }
+
+    fn bp(bp: u8) -> Protection {
+        Protection {
+            bp,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn part_override() {
+        assert_eq!(w25q128().bp, (2, 3));
+        assert_eq!(w25q128().sec, Some(6));
+        assert_eq!(
+            (w25q256().bp, w25q256().tb, w25q256().sec),
+            ((2, 4), Some(6), None)
+        );
+        assert_eq!(w25q256().vendor, "Winbond");
+        assert!(
+            Layout::for_id(JedecId {
+                manufacturer: 0x20,
+                memory_type: 0xBA,
+                capacity: 0x18,
+            })
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn decode() {
+        let cases: &[(&Layout, u32, Protection)] = &[
+            (w25q128(), 0x00_0000, Protection::default()),
+            (w25q128(), 0x00_001C, bp(7)),
+            (w25q128(), 0x00_0024, Protection { tb: true, ..bp(1) }),
+            (w25q128(), 0x00_0044, Protection { sec: true, ..bp(1) }),
+            (
+                w25q128(),
+                0x04_4180,
+                Protection {
+                    cmp: true,
+                    srp0: true,
+                    srp1: true,
+                    wps: true,
+                    ..bp(0)
+                },
+            ),
+            (w25q256(), 0x00_003C, bp(15)),
+            (w25q256(), 0x00_0020, bp(8)),
+            (w25q256(), 0x00_0044, Protection { tb: true, ..bp(1) }),
+            (mx25l128(), 0x00_0808, Protection { tb: true, ..bp(2) }),
+            (mx25l128(), 0x00_003C, bp(15)),
+        ];
+        for (i, (layout, status, expected)) in cases.iter().enumerate() {
+            assert_eq!(layout.decode(*status), *expected, "case {i}");
+        }
+    }
+
+    #[test]
+    fn encode() {
+        // (布局, 原来的值, 要设置的保护, 结果)
+        let cases: &[(&Layout, u32, Protection, u32)] = &[
+            (w25q128(), 0x00_0000, bp(7), 0x00_001C),
+            // 不动 QE 和 BUSY
+            (w25q128(), 0x00_0201, bp(1), 0x00_0205),
+            (
+                w25q128(),
+                0x00_0000,
+                Protection {
+                    tb: true,
+                    sec: true,
+                    cmp: true,
+                    ..bp(2)
+                },
+                0x00_4068,
+            ),
+            (w25q256(), 0x00_0000, bp(15), 0x00_003C),
+            (
+                w25q256(),
+                0x00_0000,
+                Protection { tb: true, ..bp(9) },
+                0x00_0064,
+            ),
+            // 清 BP 时 BP3 也要清掉
+            (w25q256(), 0x00_007C, bp(0), 0x00_0000),
+            (mx25l128(), 0x00_0040, bp(15), 0x00_007C),
+        ];
+        for (i, (layout, status, p, expected)) in cases.iter().enumerate() {
+            assert_eq!(
+                layout.encode::<()>(*status, p).unwrap(),
+                *expected,
+                "case {i}"
+            );
+            assert_eq!(layout.decode(*expected), *p, "case {i}");
+        }
+    }
+
+    #[test]
+    fn encode_unsupported() {
+        assert!(matches!(
+            w25q128().encode::<()>(0, &bp(8)),
+            Err(Error::Unsupported)
+        ));
+        let sec = Protection { sec: true, ..bp(1) };
+        assert!(matches!(
+            w25q256().encode::<()>(0, &sec),
+            Err(Error::Unsupported)
+        ));
+        assert!(matches!(
+            mx25l128().encode::<()>(0, &Protection { cmp: true, ..bp(1) }),
+            Err(Error::Unsupported)
+        ));
+    }
+
+    #[test]
+    fn protected_range() {
+        let tb = |p: Protection| Protection { tb: true, ..p };
+        let sec = |p: Protection| Protection { sec: true, ..p };
+        let cmp = |p: Protection| Protection { cmp: true, ..p };
+        let cases: &[(&Layout, Protection, u32, Range<u32>)] = &[
+            (w25q128(), bp(0), 16 * M, 16 * M..16 * M),
+            (w25q128(), bp(1), 16 * M, 16 * M - 256 * K..16 * M),
+            (w25q128(), tb(bp(1)), 16 * M, 0..256 * K),
+            (w25q128(), bp(6), 16 * M, 8 * M..16 * M),
+            (w25q128(), bp(7), 16 * M, 0..16 * M),
+            (w25q128(), sec(bp(1)), 16 * M, 16 * M - 4 * K..16 * M),
+            (w25q128(), sec(tb(bp(2))), 16 * M, 0..8 * K),
+            (w25q128(), sec(bp(5)), 16 * M, 16 * M - 32 * K..16 * M),
+            // SEC=1 且 BP 全 1 时保护整片
+            (w25q128(), sec(bp(7)), 16 * M, 0..16 * M),
+            (w25q128(), cmp(bp(1)), 16 * M, 0..16 * M - 256 * K),
+            (w25q128(), cmp(tb(bp(1))), 16 * M, 256 * K..16 * M),
+            (w25q256(), bp(1), 32 * M, 32 * M - 64 * K..32 * M),
+            (w25q256(), tb(bp(1)), 32 * M, 0..64 * K),
+            (w25q256(), bp(9), 32 * M, 16 * M..32 * M),
+            (w25q256(), bp(10), 32 * M, 0..32 * M),
+            (w25q256(), bp(15), 32 * M, 0..32 * M),
+            (mx25l128(), bp(1), 16 * M, 16 * M - 64 * K..16 * M),
+            (mx25l128(), tb(bp(8)), 16 * M, 0..8 * M),
+            (mx25l128(), bp(14), 16 * M, 0..16 * M),
+        ];
+        for (i, (layout, p, size, expected)) in cases.iter().enumerate() {
+            assert_eq!(layout.protected_range(p, *size), *expected, "case {i}");
+        }
+    }
+}
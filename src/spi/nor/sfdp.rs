@@ -0,0 +1,208 @@
+//! SFDP (JESD216) 里的基本参数表
+//!
+//! 头 8 字节: "SFDP", 次/主版本, 参数头个数 - 1. 之后每个参数头 8 字节:
+//! ID 低字节, 次/主版本, 长度 (DWORD), 指针 (3 字节小端), ID 高字节.
+//! 基本参数表的 ID 是 0xFF00
+
+/// 基本参数表最多读 16 个 DWORD (JESD216B), 现在只用到第 11 个
+const BFPT_DWORDS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EraseType {
+    pub size: u32,
+    pub opcode: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressMode {
+    ThreeByte,
+    ThreeOrFour,
+    FourByte,
+}
+
+/// 从基本参数表里解析出来的几何参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Parameters {
+    pub version: (u8, u8),
+    /// 字节
+    pub size: u64,
+    pub page_size: u32,
+    pub address_mode: AddressMode,
+    /// 按大小从小到大排序
+    pub erase_types: Vec<EraseType>,
+}
+
+/// 参数头, 返回基本参数表的 (指针, DWORD 数)
+pub(crate) fn find_bfpt(header: &[u8]) -> Option<(u32, usize)> {
+    if header.len() < 16 || &header[..4] != b"SFDP" {
+        return None;
+    }
+    let count = header[6] as usize + 1;
+    header[8..]
+        .chunks_exact(8)
+        .take(count)
+        .find(|p| p[0] == 0x00 && p[7] == 0xFF)
+        .map(|p| {
+            let pointer = u32::from_le_bytes([p[4], p[5], p[6], 0]);
+            (pointer, (p[3] as usize).min(BFPT_DWORDS))
+        })
+}
+
+/// 参数头区域的长度, 先读这么多再找基本参数表
+pub(crate) fn header_len(header: &[u8]) -> usize {
+    8 + (header[6] as usize + 1) * 8
+}
+
+pub(crate) fn parse(header: &[u8], table: &[u8]) -> Option<Parameters> {
+    let dword = |i: usize| -> Option<u32> {
+        let b = table.get((i - 1) * 4..i * 4)?;
+        Some(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let dw1 = dword(1)?;
+    let address_mode = match (dw1 >> 17) & 0x03 {
+        0b00 => AddressMode::ThreeByte,
+        0b01 => AddressMode::ThreeOrFour,
+        _ => AddressMode::FourByte,
+    };
+
+    let dw2 = dword(2)?;
+    let size = if dw2 & 0x8000_0000 == 0 {
+        (u64::from(dw2) + 1) / 8
+    } else {
+        // 2^N 位, N 太大说明表是坏的
+        1u64.checked_shl(dw2 & 0x7FFF_FFFF)? / 8
+    };
+
+    let mut erase_types = Vec::new();
+    if let (Some(dw8), Some(dw9)) = (dword(8), dword(9)) {
+        for pair in [dw8, dw9] {
+            let b = pair.to_le_bytes();
+            for (exp, opcode) in [(b[0], b[1]), (b[2], b[3])] {
+                if exp != 0 {
+                    erase_types.push(EraseType {
+                        size: 1u32.checked_shl(u32::from(exp))?,
+                        opcode,
+                    });
+                }
+            }
+        }
+    }
+    // 老版本只有第一个 DWORD 里的 4K 擦除
+    if erase_types.is_empty() && dw1 & 0x03 == 0x01 {
+        erase_types.push(EraseType {
+            size: 4096,
+            opcode: (dw1 >> 8) as u8,
+        });
+    }
+    erase_types.sort_by_key(|e| e.size);
+
+    let page_size = match dword(11) {
+        Some(dw11) => 1 << ((dw11 >> 4) & 0x0F),
+        None => 256,
+    };
+
+    Some(Parameters {
+        version: (header[5], header[4]),
+        size,
+        page_size,
+        address_mode,
+        erase_types,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// W25Q128JV 的 SFDP, 0x00 开始的参数头和 0x80 开始的基本参数表
+    const W25Q128_HEADER: [u8; 16] = [
+        0x53, 0x46, 0x44, 0x50, 0x05, 0x01, 0x00, 0xFF, 0x00, 0x05, 0x01, 0x10, 0x80, 0x00, 0x00,
+        0xFF,
+    ];
+    const W25Q128_BFPT: [u8; 64] = [
+        0xE5, 0x20, 0xF9, 0xFF, 0xFF, 0xFF, 0xFF, 0x07, 0x44, 0xEB, 0x08, 0x6B, 0x08, 0x3B, 0x42,
+        0xBB, 0xFE, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x00, 0xFF, 0xFF, 0x40, 0xEB, 0x0C, 0x20,
+        0x0F, 0x52, 0x10, 0xD8, 0x00, 0x00, 0x36, 0x02, 0xA6, 0x00, 0x82, 0xEA, 0x14, 0xC9, 0xE9,
+        0x63, 0x76, 0x33, 0x7A, 0x75, 0x7A, 0x75, 0xF7, 0xA2, 0xD5, 0x5C, 0x19, 0xF7, 0x4D, 0xFF,
+        0xE9, 0x30, 0xF8, 0x80,
+    ];
+
+    fn with_dword(i: usize, value: u32) -> Vec<u8> {
+        let mut table = W25Q128_BFPT.to_vec();
+        table[(i - 1) * 4..i * 4].copy_from_slice(&value.to_le_bytes());
+        table
+    }
+
+    #[test]
+    fn w25q128() {
+        assert_eq!(header_len(&W25Q128_HEADER), 16);
+        assert_eq!(find_bfpt(&W25Q128_HEADER), Some((0x80, 16)));
+
+        let params = parse(&W25Q128_HEADER, &W25Q128_BFPT).unwrap();
+        assert_eq!(params.version, (1, 5));
+        assert_eq!(params.size, 16 << 20);
+        assert_eq!(params.page_size, 256);
+        assert_eq!(params.address_mode, AddressMode::ThreeByte);
+        assert_eq!(
+            params.erase_types,
+            [
+                EraseType {
+                    size: 4096,
+                    opcode: 0x20,
+                },
+                EraseType {
+                    size: 32768,
+                    opcode: 0x52,
+                },
+                EraseType {
+                    size: 65536,
+                    opcode: 0xD8,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn find_bfpt_rejects_bad_header() {
+        let mut header = W25Q128_HEADER;
+        header[0] = b'X';
+        assert_eq!(find_bfpt(&header), None);
+        assert_eq!(find_bfpt(&W25Q128_HEADER[..8]), None);
+
+        // 只有厂商参数表
+        let mut header = W25Q128_HEADER;
+        header[15] = 0xEF;
+        assert_eq!(find_bfpt(&header), None);
+    }
+
+    #[test]
+    fn old_table_uses_dword1_erase() {
+        // JESD216 之前的表只有 9 个 DWORD 以内, 这里只给前两个
+        let params = parse(&W25Q128_HEADER, &W25Q128_BFPT[..8]).unwrap();
+        assert_eq!(
+            params.erase_types,
+            [EraseType {
+                size: 4096,
+                opcode: 0x20,
+            }]
+        );
+        assert_eq!(params.page_size, 256);
+        assert_eq!(parse(&W25Q128_HEADER, &W25Q128_BFPT[..4]), None);
+    }
+
+    #[test]
+    fn density_as_power_of_two() {
+        // 2^32 位 = 512M 字节
+        let params = parse(&W25Q128_HEADER, &with_dword(2, 0x8000_0020)).unwrap();
+        assert_eq!(params.size, 512 << 20);
+        assert_eq!(parse(&W25Q128_HEADER, &with_dword(2, 0x8000_0040)), None);
+        assert_eq!(parse(&W25Q128_HEADER, &with_dword(2, 0xFFFF_FFFF)), None);
+    }
+
+    #[test]
+    fn erase_size_overflow() {
+        assert_eq!(parse(&W25Q128_HEADER, &with_dword(9, 0x0000_D820)), None);
+        assert_eq!(parse(&W25Q128_HEADER, &with_dword(9, 0xC7FF_0000)), None);
+    }
+}
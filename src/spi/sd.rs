@@ -0,0 +1,693 @@
+//! SPI 模式的 SD/MMC 卡
+//!
+//! 初始化: CMD0 进入 SPI 模式, CMD8 区分 v1/v2, ACMD41 (MMC 用 CMD1) 等卡准备好,
+//! CMD58 读 OCR 判断是不是按块寻址的 SDHC/SDXC.
+//! 规范要求初始化时钟不超过 400kHz, CH347 最低只有 468.75kHz (speed = 7), 一般的卡都能接受.
+//! 完成后可以用 [`SdCard::with_spi`] 提高时钟.
+//!
+//! 每次 USB 往返都很慢, 所以等响应时一次多读几个字节, 多读到的留着下次用.
+//! 片选在两次 transaction 之间会无效一下, 卡不在乎这个.
+//!
+//! 实现了 `embedded-sdmmc` 的 `BlockDevice`, 可以直接交给 `VolumeManager` 读写 FAT
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use embedded_hal::spi::{Operation, SpiDevice};
+
+pub mod command {
+    pub const GO_IDLE_STATE: u8 = 0;
+    pub const SEND_OP_COND: u8 = 1;
+    pub const SEND_IF_COND: u8 = 8;
+    pub const SEND_CSD: u8 = 9;
+    pub const SEND_CID: u8 = 10;
+    pub const STOP_TRANSMISSION: u8 = 12;
+    pub const SEND_STATUS: u8 = 13;
+    pub const SET_BLOCKLEN: u8 = 16;
+    pub const READ_SINGLE_BLOCK: u8 = 17;
+    pub const READ_MULTIPLE_BLOCK: u8 = 18;
+    pub const WRITE_BLOCK: u8 = 24;
+    pub const WRITE_MULTIPLE_BLOCK: u8 = 25;
+    pub const APP_CMD: u8 = 55;
+    pub const READ_OCR: u8 = 58;
+    pub const CRC_ON_OFF: u8 = 59;
+    /// ACMD41
+    pub const SD_SEND_OP_COND: u8 = 41;
+}
+
+/// R1 的位
+pub const R1_IDLE: u8 = 0x01;
+pub const R1_ILLEGAL_COMMAND: u8 = 0x04;
+pub const R1_CRC_ERROR: u8 = 0x08;
+
+const START_BLOCK: u8 = 0xFE;
+const START_MULTI_WRITE: u8 = 0xFC;
+const STOP_MULTI_WRITE: u8 = 0xFD;
+
+pub const BLOCK_SIZE: usize = 512;
+
+const INIT_TIMEOUT: Duration = Duration::from_secs(1);
+const READ_TIMEOUT: Duration = Duration::from_millis(200);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 等响应时每次读的字节数
+const POLL_CHUNK: usize = 8;
+
+#[derive(Debug)]
+pub enum Error<E> {
+    Spi(E),
+    Timeout,
+    /// 命令的 R1 不对
+    Command {
+        command: u8,
+        r1: u8,
+    },
+    /// CMD8 回显的检查模式不对, 或者卡不支持 3.3V
+    Unsupported,
+    /// 数据块 CRC 不对
+    Crc,
+    /// 等数据块时收到了错误令牌
+    DataToken(u8),
+    /// 写数据块后卡没有接受, 参数是数据响应
+    Write(u8),
+    OutOfRange,
+    NotInitialized,
+}
+
+/// `BlockDevice` 要求错误类型实现 `std::error::Error`
+impl<E: fmt::Debug> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Spi(e) => write!(f, "spi error: {e:?}"),
+            Error::Timeout => write!(f, "timeout"),
+            Error::Command { command, r1 } => write!(f, "CMD{command} failed, R1 = {r1:#04x}"),
+            Error::Unsupported => write!(f, "unsupported card"),
+            Error::Crc => write!(f, "data crc mismatch"),
+            Error::DataToken(token) => write!(f, "data error token {token:#04x}"),
+            Error::Write(response) => write!(f, "write rejected, response = {response:#04x}"),
+            Error::OutOfRange => write!(f, "out of range"),
+            Error::NotInitialized => write!(f, "card not initialized"),
+        }
+    }
+}
+
+impl<E: fmt::Debug> std::error::Error for Error<E> {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CardType {
+    Mmc,
+    SdV1,
+    SdV2,
+    /// SDHC/SDXC, 按块寻址
+    SdHc,
+}
+
+/// CRC7, 命令帧用
+pub fn crc7(data: &[u8]) -> u8 {
+    let mut crc = 0u8;
+    for &byte in data {
+        for i in (0..8).rev() {
+            let bit = ((byte >> i) & 1) ^ (crc >> 6);
+            crc = (crc << 1) & 0x7F;
+            if bit != 0 {
+                crc ^= 0x09;
+            }
+        }
+    }
+    crc
+}
+
+/// CRC16-CCITT, 数据块用
+pub fn crc16(data: &[u8]) -> u16 {
+    let mut crc = 0u16;
+    for &byte in data {
+        crc ^= u16::from(byte) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x1021
+            } else {
+                crc << 1
+            };
+        }
+    }
+    crc
+}
+
+/// 取 128 位寄存器的 [hi:lo] 位, 位号按规范从最高位 127 开始
+fn bits(raw: &[u8; 16], hi: usize, lo: usize) -> u32 {
+    let mut value = 0u32;
+    for bit in (lo..=hi).rev() {
+        let byte = raw[15 - bit / 8];
+        value = (value << 1) | u32::from((byte >> (bit % 8)) & 1);
+    }
+    value
+}
+
+/// 卡特定数据寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Csd(pub [u8; 16]);
+
+impl Csd {
+    /// 0: v1 (SDSC/MMC), 1: v2 (SDHC/SDXC)
+    pub fn version(&self) -> u8 {
+        bits(&self.0, 127, 126) as u8
+    }
+
+    pub fn capacity(&self) -> u64 {
+        if self.version() == 1 {
+            (u64::from(bits(&self.0, 69, 48)) + 1) * 512 * 1024
+        } else {
+            let c_size = u64::from(bits(&self.0, 73, 62));
+            let mult = bits(&self.0, 49, 47);
+            let read_bl_len = bits(&self.0, 83, 80);
+            (c_size + 1) << (mult + 2 + read_bl_len)
+        }
+    }
+
+    pub fn block_count(&self) -> u32 {
+        (self.capacity() / BLOCK_SIZE as u64) as u32
+    }
+
+    /// TRAN_SPEED 换算成 Hz
+    pub fn max_speed(&self) -> u32 {
+        // 4 ~ 7 保留
+        const UNIT: [u32; 8] = [100_000, 1_000_000, 10_000_000, 100_000_000, 0, 0, 0, 0];
+        // 乘 10
+        const VALUE: [u32; 16] = [
+            0, 10, 12, 13, 15, 20, 25, 30, 35, 40, 45, 50, 55, 60, 70, 80,
+        ];
+        let speed = bits(&self.0, 103, 96);
+        UNIT[(speed & 0x07) as usize] * VALUE[((speed >> 3) & 0x0F) as usize] / 10
+    }
+
+    /// 永久或临时写保护
+    pub fn write_protected(&self) -> bool {
+        bits(&self.0, 13, 12) != 0
+    }
+}
+
+/// 卡识别寄存器
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cid(pub [u8; 16]);
+
+impl Cid {
+    pub fn manufacturer(&self) -> u8 {
+        self.0[0]
+    }
+
+    pub fn oem(&self) -> String {
+        String::from_utf8_lossy(&self.0[1..3]).into_owned()
+    }
+
+    pub fn product(&self) -> String {
+        String::from_utf8_lossy(&self.0[3..8]).into_owned()
+    }
+
+    /// (主版本, 次版本)
+    pub fn revision(&self) -> (u8, u8) {
+        (self.0[8] >> 4, self.0[8] & 0x0F)
+    }
+
+    pub fn serial(&self) -> u32 {
+        bits(&self.0, 55, 24)
+    }
+
+    /// (年, 月)
+    pub fn date(&self) -> (u16, u8) {
+        (
+            2000 + bits(&self.0, 19, 12) as u16,
+            bits(&self.0, 11, 8) as u8,
+        )
+    }
+}
+
+struct Link<SPI> {
+    spi: SPI,
+    /// 等响应时多读到的字节
+    pending: VecDeque<u8>,
+    crc: bool,
+}
+
+impl<SPI: SpiDevice> Link<SPI> {
+    /// 读的时候 MOSI 要保持高, 所以用 0xFF 做 transfer
+    fn transaction(&mut self, writes: &[&[u8]], read: usize) -> Result<(), Error<SPI::Error>> {
+        let mut buf = vec![0xFF; read];
+        let mut ops: Vec<Operation<'_, u8>> = writes.iter().map(|w| Operation::Write(w)).collect();
+        if read > 0 {
+            ops.push(Operation::TransferInPlace(&mut buf));
+        }
+        self.spi.transaction(&mut ops).map_err(Error::Spi)?;
+        self.pending.extend(buf);
+        Ok(())
+    }
+
+    fn read_byte(&mut self) -> Result<u8, Error<SPI::Error>> {
+        if self.pending.is_empty() {
+            self.transaction(&[], POLL_CHUNK)?;
+        }
+        Ok(self.pending.pop_front().unwrap())
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        if self.pending.len() < buf.len() {
+            self.transaction(&[], buf.len() - self.pending.len())?;
+        }
+        for b in buf.iter_mut() {
+            *b = self.pending.pop_front().unwrap();
+        }
+        Ok(())
+    }
+
+    /// 读到第一个不是 0xFF 的字节
+    fn wait_byte(&mut self, timeout: Duration) -> Result<u8, Error<SPI::Error>> {
+        let start = Instant::now();
+        loop {
+            let byte = self.read_byte()?;
+            if byte != 0xFF {
+                return Ok(byte);
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+        }
+    }
+
+    /// 等忙信号 (0x00) 结束
+    fn wait_idle(&mut self, timeout: Duration) -> Result<(), Error<SPI::Error>> {
+        let start = Instant::now();
+        loop {
+            if self.read_byte()? == 0xFF {
+                return Ok(());
+            }
+            if start.elapsed() > timeout {
+                return Err(Error::Timeout);
+            }
+            if self.pending.is_empty() {
+                sleep(Duration::from_micros(100));
+            }
+        }
+    }
+
+    /// 发命令, 返回 R1. R3/R7 的后 4 个字节接着用 [`Link::read_exact`] 读
+    fn command(&mut self, command: u8, arg: u32) -> Result<u8, Error<SPI::Error>> {
+        self.pending.clear();
+        let mut frame = [0x40 | command, 0, 0, 0, 0, 0];
+        frame[1..5].copy_from_slice(&arg.to_be_bytes());
+        frame[5] = (crc7(&frame[..5]) << 1) | 1;
+        // 响应在 1~8 个字节后, 顺便把 R7 也读回来
+        self.transaction(&[&frame], POLL_CHUNK + 4)?;
+        if command == command::STOP_TRANSMISSION {
+            // 填充字节
+            self.read_byte()?;
+        }
+        for _ in 0..POLL_CHUNK {
+            let r1 = self.read_byte()?;
+            if r1 & 0x80 == 0 {
+                return Ok(r1);
+            }
+        }
+        Err(Error::Timeout)
+    }
+
+    fn app_command(&mut self, command: u8, arg: u32) -> Result<u8, Error<SPI::Error>> {
+        let r1 = self.command(command::APP_CMD, 0)?;
+        if r1 & !R1_IDLE != 0 {
+            return Err(Error::Command {
+                command: command::APP_CMD,
+                r1,
+            });
+        }
+        self.command(command, arg)
+    }
+
+    fn expect(&mut self, command: u8, arg: u32) -> Result<(), Error<SPI::Error>> {
+        match self.command(command, arg)? {
+            0 => Ok(()),
+            r1 => Err(Error::Command { command, r1 }),
+        }
+    }
+
+    /// 等起始令牌, 读数据块和 CRC
+    fn read_data(&mut self, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        match self.wait_byte(READ_TIMEOUT)? {
+            START_BLOCK => {}
+            token => return Err(Error::DataToken(token)),
+        }
+        self.read_exact(buf)?;
+        let mut crc = [0; 2];
+        self.read_exact(&mut crc)?;
+        if self.crc && u16::from_be_bytes(crc) != crc16(buf) {
+            return Err(Error::Crc);
+        }
+        Ok(())
+    }
+
+    /// 发一个数据块, 检查数据响应, 等写完
+    fn write_data(&mut self, token: u8, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.pending.clear();
+        let crc = crc16(data).to_be_bytes();
+        self.transaction(&[&[0xFF, token], data, &crc], POLL_CHUNK)?;
+        let response = self.wait_byte(WRITE_TIMEOUT)?;
+        if response & 0x1F != 0x05 {
+            return Err(Error::Write(response));
+        }
+        self.wait_idle(WRITE_TIMEOUT)
+    }
+}
+
+pub struct SdCard<SPI> {
+    link: RefCell<Link<SPI>>,
+    card: Option<CardType>,
+    csd: Csd,
+}
+
+impl<SPI: SpiDevice> SdCard<SPI> {
+    /// 初始化并读 CSD. 上电要求的 74 个时钟是在片选有效时发的, 大部分卡能接受.
+    /// `crc` 打开卡的 CRC 检查, 同时检查读到的数据块的 CRC16
+    pub fn new(spi: SPI, crc: bool) -> Result<Self, Error<SPI::Error>> {
+        let mut card = Self {
+            link: RefCell::new(Link {
+                spi,
+                pending: VecDeque::new(),
+                crc,
+            }),
+            card: None,
+            csd: Csd([0; 16]),
+        };
+        card.init()?;
+        Ok(card)
+    }
+
+    fn init(&mut self) -> Result<(), Error<SPI::Error>> {
+        let link = self.link.get_mut();
+        link.transaction(&[&[0xFF; 10]], 0)?;
+
+        let mut r1 = 0;
+        for _ in 0..10 {
+            r1 = link.command(command::GO_IDLE_STATE, 0)?;
+            if r1 == R1_IDLE {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+        if r1 != R1_IDLE {
+            return Err(Error::Command {
+                command: command::GO_IDLE_STATE,
+                r1,
+            });
+        }
+
+        // 2.7~3.6V, 检查模式 0xAA
+        let r1 = link.command(command::SEND_IF_COND, 0x1AA)?;
+        let v2 = r1 & R1_ILLEGAL_COMMAND == 0;
+        if v2 {
+            let mut r7 = [0; 4];
+            link.read_exact(&mut r7)?;
+            if r7[2] & 0x0F != 0x01 || r7[3] != 0xAA {
+                return Err(Error::Unsupported);
+            }
+        }
+
+        if link.crc {
+            link.expect(command::CRC_ON_OFF, 1).or_else(|e| match e {
+                Error::Command { r1: R1_IDLE, .. } => Ok(()),
+                e => Err(e),
+            })?;
+        }
+
+        // HCS
+        let arg = if v2 { 0x4000_0000 } else { 0 };
+        let mut mmc = false;
+        let start = Instant::now();
+        loop {
+            let r1 = if mmc {
+                link.command(command::SEND_OP_COND, 0)?
+            } else {
+                match link.app_command(command::SD_SEND_OP_COND, arg) {
+                    // MMC 不认 CMD55, 改用 CMD1
+                    Err(Error::Command {
+                        command: command::APP_CMD,
+                        r1,
+                    }) if !v2 && r1 & R1_ILLEGAL_COMMAND != 0 => {
+                        mmc = true;
+                        continue;
+                    }
+                    result => result?,
+                }
+            };
+            match r1 {
+                0 => break,
+                R1_IDLE => {}
+                r1 if !v2 && !mmc && r1 & R1_ILLEGAL_COMMAND != 0 => mmc = true,
+                r1 => {
+                    return Err(Error::Command {
+                        command: command::SD_SEND_OP_COND,
+                        r1,
+                    });
+                }
+            }
+            if start.elapsed() > INIT_TIMEOUT {
+                return Err(Error::Timeout);
+            }
+            sleep(Duration::from_millis(10));
+        }
+
+        let card = if v2 {
+            link.expect(command::READ_OCR, 0)?;
+            let mut ocr = [0; 4];
+            link.read_exact(&mut ocr)?;
+            // CCS
+            if ocr[0] & 0x40 != 0 {
+                CardType::SdHc
+            } else {
+                CardType::SdV2
+            }
+        } else if mmc {
+            CardType::Mmc
+        } else {
+            CardType::SdV1
+        };
+        if card != CardType::SdHc {
+            link.expect(command::SET_BLOCKLEN, BLOCK_SIZE as u32)?;
+        }
+
+        self.card = Some(card);
+        self.csd = self.read_csd()?;
+        Ok(())
+    }
+
+    pub fn card_type(&self) -> Option<CardType> {
+        self.card
+    }
+
+    pub fn csd(&self) -> Csd {
+        self.csd
+    }
+
+    /// 卡容量, 字节
+    pub fn capacity(&self) -> u64 {
+        self.csd.capacity()
+    }
+
+    /// 访问底层的 SPI, 比如初始化后提高时钟
+    pub fn with_spi<R>(&self, f: impl FnOnce(&mut SPI) -> R) -> R {
+        f(&mut self.link.borrow_mut().spi)
+    }
+
+    pub fn release(self) -> SPI {
+        self.link.into_inner().spi
+    }
+
+    fn read_register(&self, command: u8) -> Result<[u8; 16], Error<SPI::Error>> {
+        let mut link = self.link.borrow_mut();
+        link.expect(command, 0)?;
+        let mut raw = [0; 16];
+        link.read_data(&mut raw)?;
+        Ok(raw)
+    }
+
+    pub fn read_csd(&self) -> Result<Csd, Error<SPI::Error>> {
+        self.read_register(command::SEND_CSD).map(Csd)
+    }
+
+    pub fn read_cid(&self) -> Result<Cid, Error<SPI::Error>> {
+        self.read_register(command::SEND_CID).map(Cid)
+    }
+
+    /// R2 状态, 高字节是 R1
+    pub fn status(&self) -> Result<u16, Error<SPI::Error>> {
+        let mut link = self.link.borrow_mut();
+        let r1 = link.command(command::SEND_STATUS, 0)?;
+        let r2 = link.read_byte()?;
+        Ok(u16::from_be_bytes([r1, r2]))
+    }
+
+    /// SDHC 用块号, 其他卡用字节地址
+    fn address(&self, block: u32) -> Result<u32, Error<SPI::Error>> {
+        if block >= self.csd.block_count() {
+            return Err(Error::OutOfRange);
+        }
+        match self.card.ok_or(Error::NotInitialized)? {
+            CardType::SdHc => Ok(block),
+            _ => Ok(block * BLOCK_SIZE as u32),
+        }
+    }
+
+    fn check_len(&self, start: u32, len: usize) -> Result<(), Error<SPI::Error>> {
+        if !len.is_multiple_of(BLOCK_SIZE)
+            || u64::from(start) + (len / BLOCK_SIZE) as u64 > u64::from(self.csd.block_count())
+        {
+            return Err(Error::OutOfRange);
+        }
+        Ok(())
+    }
+
+    /// 从 `start` 块开始读, `buf` 长度是 512 的倍数. 多个块用 CMD18 连续读
+    pub fn read_blocks(&self, start: u32, buf: &mut [u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_len(start, buf.len())?;
+        let address = self.address(start)?;
+        let mut link = self.link.borrow_mut();
+
+        if buf.len() == BLOCK_SIZE {
+            link.expect(command::READ_SINGLE_BLOCK, address)?;
+            return link.read_data(buf);
+        }
+
+        link.expect(command::READ_MULTIPLE_BLOCK, address)?;
+        let result = buf
+            .chunks_mut(BLOCK_SIZE)
+            .try_for_each(|block| link.read_data(block));
+        link.command(command::STOP_TRANSMISSION, 0)?;
+        link.wait_idle(READ_TIMEOUT)?;
+        result
+    }
+
+    /// 从 `start` 块开始写, `data` 长度是 512 的倍数. 多个块用 CMD25 连续写
+    pub fn write_blocks(&self, start: u32, data: &[u8]) -> Result<(), Error<SPI::Error>> {
+        self.check_len(start, data.len())?;
+        let address = self.address(start)?;
+        let mut link = self.link.borrow_mut();
+
+        if data.len() == BLOCK_SIZE {
+            link.expect(command::WRITE_BLOCK, address)?;
+            return link.write_data(START_BLOCK, data);
+        }
+
+        link.expect(command::WRITE_MULTIPLE_BLOCK, address)?;
+        let result = data
+            .chunks(BLOCK_SIZE)
+            .try_for_each(|block| link.write_data(START_MULTI_WRITE, block));
+        link.pending.clear();
+        link.transaction(&[&[STOP_MULTI_WRITE]], POLL_CHUNK)?;
+        // 停止令牌后有一个字节才开始忙
+        link.read_byte()?;
+        link.wait_idle(WRITE_TIMEOUT)?;
+        result
+    }
+}
+
+mod embedded_sdmmc_impl {
+    use embedded_hal::spi::SpiDevice;
+    use embedded_sdmmc::{Block, BlockCount, BlockDevice, BlockIdx};
+
+    use super::{Error, SdCard};
+
+    impl<SPI: SpiDevice> BlockDevice for SdCard<SPI>
+    where
+        SPI::Error: 'static,
+    {
+        type Error = Error<SPI::Error>;
+
+        fn read(&self, blocks: &mut [Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+            let mut buf = vec![0; blocks.len() * Block::LEN];
+            self.read_blocks(start_block_idx.0, &mut buf)?;
+            for (block, data) in blocks.iter_mut().zip(buf.chunks(Block::LEN)) {
+                block.contents.copy_from_slice(data);
+            }
+            Ok(())
+        }
+
+        fn write(&self, blocks: &[Block], start_block_idx: BlockIdx) -> Result<(), Self::Error> {
+            let data: Vec<u8> = blocks.iter().flat_map(|b| b.contents).collect();
+            self.write_blocks(start_block_idx.0, &data)
+        }
+
+        fn num_blocks(&self) -> Result<BlockCount, Self::Error> {
+            Ok(BlockCount(self.csd.block_count()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 按规范的位号写 128 位寄存器的 [hi:lo] 位
+    fn set_bits(raw: &mut [u8; 16], hi: usize, lo: usize, value: u32) {
+        for bit in lo..=hi {
+            let byte = &mut raw[15 - bit / 8];
+            if (value >> (bit - lo)) & 1 != 0 {
+                *byte |= 1 << (bit % 8);
+            } else {
+                *byte &= !(1 << (bit % 8));
+            }
+        }
+    }
+
+    #[test]
+    fn crc7_command_frames() {
+        let frame = |f: [u8; 5]| (crc7(&f) << 1) | 1;
+        assert_eq!(frame([0x40, 0x00, 0x00, 0x00, 0x00]), 0x95);
+        assert_eq!(frame([0x48, 0x00, 0x00, 0x01, 0xAA]), 0x87);
+        // CMD17 地址 0
+        assert_eq!(frame([0x51, 0x00, 0x00, 0x00, 0x00]), 0x55);
+    }
+
+    #[test]
+    fn crc16_data() {
+        assert_eq!(crc16(b"123456789"), 0x31C3);
+        assert_eq!(crc16(&[0xFF; BLOCK_SIZE]), 0x7FA1);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn csd_v1_capacity() {
+        // 2GB SDSC: READ_BL_LEN = 10, C_SIZE = 4095, C_SIZE_MULT = 7
+        let mut raw = [0; 16];
+        set_bits(&mut raw, 83, 80, 10);
+        set_bits(&mut raw, 73, 62, 4095);
+        set_bits(&mut raw, 49, 47, 7);
+        let csd = Csd(raw);
+        assert_eq!(csd.version(), 0);
+        assert_eq!(csd.capacity(), 2 << 30);
+        assert_eq!(csd.block_count(), 4 << 20);
+    }
+
+    #[test]
+    fn csd_v2_capacity() {
+        let mut raw = [0; 16];
+        set_bits(&mut raw, 127, 126, 1);
+        set_bits(&mut raw, 69, 48, 15159);
+        let csd = Csd(raw);
+        assert_eq!(csd.version(), 1);
+        assert_eq!(csd.capacity(), 15160 * 512 * 1024);
+        assert_eq!(csd.block_count(), 15160 * 1024);
+    }
+
+    #[test]
+    fn csd_max_speed() {
+        let speed = |tran_speed: u32| {
+            let mut raw = [0; 16];
+            set_bits(&mut raw, 103, 96, tran_speed);
+            Csd(raw).max_speed()
+        };
+        assert_eq!(speed(0x32), 25_000_000);
+        assert_eq!(speed(0x5A), 50_000_000);
+        assert_eq!(speed(0x2B), 200_000_000);
+        // 单位是保留值
+        assert_eq!(speed(0x36), 0);
+    }
+}
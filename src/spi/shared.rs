@@ -0,0 +1,149 @@
+//! 一条 SPI 总线上挂多个设备
+//!
+//! 每个设备有自己的片选和 [`Config`], 切换设备时如果配置不同就重新下发 0xC0
+
+use std::{cell::Cell, marker::PhantomData, thread::sleep, time::Duration};
+
+use embassy_hal_internal::Peripheral;
+use embedded_hal::spi::{ErrorKind, Operation};
+
+use super::{CSPin, Config, Instance, Polarity, check_width, execute, hold};
+use crate::gpio::{Output, types::PinState};
+
+/// 片选, 极性由设备的 [`Config::cs_polarity`] 决定
+pub enum ChipSelect<'d> {
+    Cs0,
+    Cs1,
+    /// 用任意 IO 做片选
+    Gpio(Output<'d>),
+}
+
+impl ChipSelect<'_> {
+    /// 硬件片选, IO 片选返回 `None`
+    fn pin(&self) -> Option<CSPin> {
+        match self {
+            ChipSelect::Cs0 => Some(CSPin::CS0),
+            ChipSelect::Cs1 => Some(CSPin::CS1),
+            ChipSelect::Gpio(_) => None,
+        }
+    }
+
+    /// IO 片选的建立和空闲时间由主机 sleep
+    fn set<T: Instance>(&self, active: bool, config: &Config) -> Result<(), ErrorKind> {
+        match self {
+            ChipSelect::Cs0 => T::chip_select(CSPin::CS0, active, config),
+            ChipSelect::Cs1 => T::chip_select(CSPin::CS1, active, config),
+            ChipSelect::Gpio(pin) => {
+                let high = active == (config.cs_polarity == Polarity::ActiveHigh);
+                pin.write(if high { PinState::High } else { PinState::Low });
+                let us = if active {
+                    config.cs_setup_us
+                } else {
+                    config.cs_idle_us
+                };
+                if us > 0 {
+                    sleep(Duration::from_micros(u64::from(us)));
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+pub struct SharedBus<'d, T: Instance> {
+    _spi: PhantomData<&'d T>,
+    /// 当前下发到芯片的配置和它对应的硬件片选
+    current: Cell<Option<(Config, Option<CSPin>)>>,
+}
+
+impl<'d, T: Instance> SharedBus<'d, T> {
+    /// 第一次传输时才下发配置
+    pub fn new(_spi: impl Peripheral<P = T>) -> Self {
+        Self {
+            _spi: PhantomData,
+            current: Cell::new(None),
+        }
+    }
+
+    /// 创建时先让片选无效
+    pub fn device(
+        &self,
+        cs: ChipSelect<'d>,
+        config: Config,
+    ) -> Result<SharedDevice<'_, 'd, T>, ErrorKind> {
+        cs.set::<T>(false, &config)?;
+        Ok(SharedDevice {
+            bus: self,
+            cs,
+            config,
+        })
+    }
+
+    /// 高有效的极性位跟片选有关, 换片选时也要重新下发. 下发失败时清掉缓存, 下次重发
+    fn apply(&self, config: Config, cs: Option<CSPin>) -> Result<(), ErrorKind> {
+        if self.current.get() != Some((config, cs)) {
+            self.current.set(None);
+            T::set_config(config, cs)?;
+            self.current.set(Some((config, cs)));
+        }
+        Ok(())
+    }
+}
+
+pub struct SharedDevice<'b, 'd, T: Instance> {
+    bus: &'b SharedBus<'d, T>,
+    cs: ChipSelect<'d>,
+    config: Config,
+}
+
+impl<'b, 'd, T: Instance> SharedDevice<'b, 'd, T> {
+    pub fn config(&self) -> Config {
+        self.config
+    }
+
+    /// 下一次传输时生效
+    pub fn set_config(&mut self, config: Config) {
+        self.config = config;
+    }
+
+    /// 取回片选
+    pub fn release(self) -> ChipSelect<'d> {
+        self.cs
+    }
+
+    fn transact(&self, operations: &mut [Operation<'_, u8>]) -> Result<(), ErrorKind> {
+        check_width(&self.config, operations)?;
+        self.bus.apply(self.config, self.cs.pin())?;
+        self.cs.set::<T>(true, &self.config)?;
+        let result = execute::<T>(&self.config, self.cs.pin(), operations);
+        hold(&self.config);
+        // 传输出错也要放开片选
+        let released = self.cs.set::<T>(false, &self.config);
+        result.and(released)
+    }
+}
+
+mod embedded_hal_v100_impl {
+    use embedded_hal::spi::*;
+
+    use crate::spi::Instance;
+
+    impl<'b, 'd, T: Instance> ErrorType for super::SharedDevice<'b, 'd, T> {
+        type Error = ErrorKind;
+    }
+
+    impl<'b, 'd, T: Instance> SpiDevice for super::SharedDevice<'b, 'd, T> {
+        fn transaction(&mut self, operations: &mut [Operation<'_, u8>]) -> Result<(), Self::Error> {
+            self.transact(operations)
+        }
+    }
+
+    impl<'b, 'd, T: Instance> SpiDevice<u16> for super::SharedDevice<'b, 'd, T> {
+        fn transaction(
+            &mut self,
+            operations: &mut [Operation<'_, u16>],
+        ) -> Result<(), Self::Error> {
+            crate::spi::with_bytes(&self.config, operations, |ops| self.transact(ops))
+        }
+    }
+}
@@ -0,0 +1,23 @@
+//! 几个驱动共用的小工具
+
+/// 读回比较用, 返回第一个不同字节的 (下标, 期望值, 读到的值)
+pub(crate) fn first_mismatch(expected: &[u8], found: &[u8]) -> Option<(usize, u8, u8)> {
+    expected
+        .iter()
+        .zip(found)
+        .position(|(a, b)| a != b)
+        .map(|i| (i, expected[i], found[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::first_mismatch;
+
+    #[test]
+    fn mismatch() {
+        assert_eq!(first_mismatch(&[1, 2, 3], &[1, 2, 3]), None);
+        assert_eq!(first_mismatch(&[], &[]), None);
+        assert_eq!(first_mismatch(&[1, 2, 3], &[1, 0, 0]), Some((1, 2, 0)));
+        assert_eq!(first_mismatch(&[0xFF], &[0x00]), Some((0, 0xFF, 0x00)));
+    }
+}